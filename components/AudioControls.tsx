@@ -2,12 +2,101 @@
 
 import { useEffect, useState, useRef } from 'react';
 
+// Metadata from a stream's OpusTags header
+interface StreamTags {
+  vendor: string;
+  title: string | null;
+  artist: string | null;
+  trackGainDb: number | null;
+  comments: Record<string, string[]>;
+}
+
 // Define the audio file event type
 interface AudioFileEvent {
   type: string;
   success: boolean;
   fileName?: string;
   error?: string;
+  duration?: number;
+  tags?: (StreamTags | null)[];
+  loading?: boolean; // Whether the files are still arriving after playback became possible
+  streamId?: number; // The stream added or removed by `streamAdded` and `streamRemoved`
+  streamTags?: StreamTags | null;
+  offset?: number; // Where on the timeline an added stream starts, in seconds
+}
+
+// A log message from the audio engine
+interface LogRecord {
+  level: string;
+  category: string;
+  message: string;
+  time: number; // Milliseconds since the epoch
+}
+
+// One of the mixer's once-a-second sync checks of a stream
+interface DriftMeasurement {
+  time: number; // Mix position in seconds
+  driftMs: number;
+  playbackRate: number;
+}
+
+// Drift and decoding statistics for one stream
+interface StreamSyncStats {
+  streamId: number;
+  maxDriftMs: number;
+  averageDriftMs: number;
+  driftMeasurements: number;
+  maxSpeedAdjustment: number; // Fractions of 1x
+  averageSpeedAdjustment: number;
+  compensationCount: number;
+  appliedCorrection: number;
+  playbackRate: number;
+  decodedFrames: number;
+  resampledFrames: number;
+  history: DriftMeasurement[];
+}
+
+// Drift and decoding statistics for the whole mix
+interface SyncStats {
+  maxDriftMs: number;
+  streams: StreamSyncStats[];
+}
+
+// A snapshot of the ring buffer between the worker and the audio output
+interface BufferMetrics {
+  bufferSize: number; // In samples, counting both channels
+  queuedSamples: number;
+  fillMs: number;
+  capacityMs: number;
+  highWaterMarkRead: number;
+  highWaterMarkWrite: number;
+  writes: number;
+  reads: number;
+  underruns: number; // Times the output ran out of audio
+  overruns: number; // Writes that didn't all fit
+  samplesWritten: number;
+  samplesRead: number;
+}
+
+// Define the diagnostics event type (periodic statistics from the worker)
+interface DiagnosticsEvent {
+  type: string;
+  success: boolean;
+  stats?: SyncStats;
+  metrics?: BufferMetrics;
+}
+
+// Define the transport event type (seek results and position updates)
+interface TransportEvent {
+  type: string;
+  success: boolean;
+  position?: number;
+  duration?: number;
+  loopStart?: number | null;
+  loopEnd?: number | null;
+  loading?: boolean;
+  buffering?: boolean; // Whether playback is waiting for more of a file to arrive
+  error?: string;
 }
 
 // We'll need to define the AudioEngineInterface type since TypeScript doesn't know about it
@@ -17,15 +106,78 @@ interface AudioEngineInterface {
   resume(): Promise<void>;
   suspend(): Promise<void>;
   send_audio_files(files: FileList): Promise<void>;
+  send_audio_urls(urls: string[]): void;
+  add_audio_file(file: File, offset: number): void;
+  remove_stream(streamId: number): void;
   set_audio_file_callback(callback: (event: AudioFileEvent) => void): void;
   set_source_type(sourceType: string): void;
   get_source_type(): string;
   reset(): Promise<void>;
+  seek(seconds: number): void;
+  set_loop(start: number, end: number): void;
+  clear_loop(): void;
+  get_position(): number;
+  get_duration(): number;
+  set_transport_callback(callback: (event: TransportEvent) => void): void;
+  set_resampler_quality(quality: string): void;
+  set_buffer_size(samples: number): void;
+  set_target_fill(ms: number): void;
+  set_debug(enabled: boolean): void;
+  set_log_level(level: string, category: string | null): void;
+  set_log_callback(callback: (record: LogRecord) => void): void;
+  set_diagnostics_callback(callback: (event: DiagnosticsEvent) => void): void;
+  set_stream_gain(streamId: number, gainDb: number): void;
+  set_stream_pan(streamId: number, pan: number): void;
+  set_stream_mute(streamId: number, muted: boolean): void;
+  set_stream_solo(streamId: number, solo: boolean): void;
+  set_stream_offset(streamId: number, offset: number): void;
+  set_stream_offset_samples(streamId: number, samples: number): void;
+  set_stream_raw_channels(streamId: number, left: number, right: number): void;
+  set_stream_downmix(streamId: number): void;
+}
+
+// Mix settings for a single loaded stem
+interface StemState {
+  id: number; // The engine's id for the stream
+  name: string;
+  gainDb: number;
+  pan: number;
+  muted: boolean;
+  solo: boolean;
+  offset: number; // Where on the timeline the stem starts, in seconds
 }
 
 // Define the available source types
 type SourceType = 'oscillator' | 'opusPlayer';
 
+// Quality of the conversion from 48kHz to the AudioContext sample rate
+type ResamplerQuality = 'low' | 'medium' | 'high';
+
+// How much audio is queued between the worker and the output: little for live use, more to
+// ride out stalls on slow devices
+type BufferingMode = 'live' | 'standard' | 'safe';
+
+const BUFFERING_MODES: Record<BufferingMode, { bufferSize: number; targetFillMs: number | null }> = {
+  live: { bufferSize: 4096, targetFillMs: 10 },
+  standard: { bufferSize: 4096, targetFillMs: null }, // Keep the whole buffer full
+  safe: { bufferSize: 32768, targetFillMs: 200 },
+};
+
+// Most recent log messages kept for download
+const MAX_LOG_RECORDS = 5000;
+
+// Browsers type WebM files, MediaRecorder recordings included, as video even when they only
+// hold audio, so those are accepted by extension as well as by type
+const AUDIO_FILE_ACCEPT = 'audio/*,video/webm,.webm,.mka';
+
+function isAudioFile(file: File): boolean {
+  return (
+    file.type.startsWith('audio/') ||
+    file.type === 'video/webm' ||
+    /\.(webm|mka)$/i.test(file.name)
+  );
+}
+
 function AudioControls() {
   const [isPlaying, setIsPlaying] = useState(false);
   const [frequency, setFrequency] = useState(440);
@@ -37,6 +189,19 @@ function AudioControls() {
   const [sourceType, setSourceType] = useState<SourceType>('opusPlayer');
   const [needsReinitialization, setNeedsReinitialization] = useState(false);
   const [loadingDemoFiles, setLoadingDemoFiles] = useState(false);
+  const [stems, setStems] = useState<StemState[]>([]);
+  const [position, setPosition] = useState(0);
+  const [duration, setDuration] = useState(0);
+  const [isBuffering, setIsBuffering] = useState(false);
+  const [loopStartPoint, setLoopStartPoint] = useState<number | null>(null);
+  const [loopRegion, setLoopRegion] = useState<{ start: number; end: number } | null>(null);
+  const [resamplerQuality, setResamplerQuality] = useState<ResamplerQuality>('medium');
+  const [bufferingMode, setBufferingMode] = useState<BufferingMode>('standard');
+  const [addStemOffset, setAddStemOffset] = useState(0);
+  const [debugLogging, setDebugLogging] = useState(false);
+  const [syncStats, setSyncStats] = useState<SyncStats | null>(null);
+  const [bufferMetrics, setBufferMetrics] = useState<BufferMetrics | null>(null);
+  const logRecordsRef = useRef<LogRecord[]>([]);
 
   const demoFiles = [
     '/assets/git-it/bass.opus',
@@ -47,10 +212,45 @@ function AudioControls() {
     '/assets/git-it/vox.opus'
   ];
 
-  const arrayToFileList = (files: File[]): FileList => {
-    const dataTransfer = new DataTransfer();
-    files.forEach(file => dataTransfer.items.add(file));
-    return dataTransfer.files;
+  const resetStems = (names: string[]) => {
+    // The files loaded together get stream ids in order from 0
+    setStems(names.map((name, index) => ({
+      id: index,
+      name,
+      gainDb: 0,
+      pan: 0,
+      muted: false,
+      solo: false,
+      offset: 0
+    })));
+  };
+
+  const updateStem = (index: number, changes: Partial<StemState>) => {
+    const engine = audioEngineRef.current;
+    if (!engine) return;
+
+    const { id } = stems[index];
+    try {
+      if (changes.gainDb !== undefined) engine.set_stream_gain(id, changes.gainDb);
+      if (changes.pan !== undefined) engine.set_stream_pan(id, changes.pan);
+      if (changes.muted !== undefined) engine.set_stream_mute(id, changes.muted);
+      if (changes.solo !== undefined) engine.set_stream_solo(id, changes.solo);
+      if (changes.offset !== undefined) engine.set_stream_offset(id, changes.offset);
+    } catch (err) {
+      console.error('Error updating stem controls:', err);
+      return;
+    }
+
+    setStems(prev => prev.map((stem, i) => (i === index ? { ...stem, ...changes } : stem)));
+  };
+
+  const removeStem = (id: number) => {
+    try {
+      audioEngineRef.current?.remove_stream(id);
+    } catch (err) {
+      console.error('Error removing stem:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to remove stem'}`);
+    }
   };
 
   const loadDemoFiles = async () => {
@@ -63,20 +263,10 @@ function AudioControls() {
     setFileStatus('Loading demo files...');
 
     try {
-      const files = new Array<File>();
-
-      for (const filePath of demoFiles) {
-        const response = await fetch(filePath);
-        const blob = await response.blob();
-        const file = new File([blob], filePath.split('/').pop() || 'unknown', { type: 'audio/opus' });
-        files.push(file);
-      }
-
-      // Convert array to FileList
-      const fileList = arrayToFileList(files);
-
-      await audioEngineRef.current.send_audio_files(fileList);
-      setFileStatus('Demo files loaded successfully');
+      // The worker downloads the files itself, so playback can start before they finish
+      audioEngineRef.current.send_audio_urls(demoFiles);
+      resetStems(demoFiles.map(filePath => filePath.split('/').pop() || 'unknown'));
+      setFileStatus('Demo files sent to audio engine');
     } catch (err) {
       console.error('Error loading demo files:', err);
       setFileStatus(`Error: Failed to load demo files - ${err instanceof Error ? err.message : 'Unknown error'}`);
@@ -104,23 +294,130 @@ function AudioControls() {
 
         // Set the source type before initialization
         engine.set_source_type(sourceType);
+        engine.set_resampler_quality(resamplerQuality);
+        const { bufferSize, targetFillMs } = BUFFERING_MODES[bufferingMode];
+        engine.set_buffer_size(bufferSize);
+        if (targetFillMs !== null) {
+          engine.set_target_fill(targetFillMs);
+        }
+        engine.set_debug(debugLogging);
+
+        // Keep the engine's log messages so they can be downloaded, and still show them
+        engine.set_log_callback((record) => {
+          const records = logRecordsRef.current;
+          records.push(record);
+          if (records.length > MAX_LOG_RECORDS) {
+            records.splice(0, records.length - MAX_LOG_RECORDS);
+          }
+          const line = `[${record.category}] ${record.message}`;
+          if (record.level === 'error') console.error(line);
+          else if (record.level === 'warn') console.warn(line);
+          else console.log(line);
+        });
 
         // Initialize the audio engine
         await engine.init();
 
         // Register the audio file callback
         engine.set_audio_file_callback((event) => {
-          const { type, success, fileName, error } = event;
+          const { type, success, fileName, error, duration, tags, loading, streamId, streamTags, offset } = event;
+
+          if (type === 'audioLoaded') {
+            if (success) {
+              setFileStatus(`File "${fileName}" fully loaded`);
+              setDuration(duration ?? 0);
+            }
+          }
+
+          if (type === 'streamAdded') {
+            if (success && streamId !== undefined) {
+              const name = streamTags?.title
+                ? (streamTags.artist ? `${streamTags.artist} - ${streamTags.title}` : streamTags.title)
+                : fileName || 'unknown';
+              setStems(stems => [...stems, { id: streamId, name, gainDb: 0, pan: 0, muted: false, solo: false, offset: offset ?? 0 }]);
+              setDuration(duration ?? 0);
+              setFileStatus(`Added "${fileName}" to the mix`);
+            } else {
+              setFileStatus(`Error: ${error || 'Failed to add file'}`);
+            }
+          }
+
+          if (type === 'streamRemoved') {
+            if (success) {
+              setStems(stems => stems.filter(stem => stem.id !== streamId));
+            } else {
+              setFileStatus(`Error: ${error || 'Failed to remove stem'}`);
+            }
+          }
 
           if (type === 'audioFileReceived') {
             if (success) {
-              setFileStatus(`File "${fileName}" successfully processed by worker`);
+              setFileStatus(loading
+                ? `File "${fileName}" ready to play, still loading`
+                : `File "${fileName}" successfully processed by worker`);
+              setDuration(duration ?? 0);
+              setPosition(0);
+              setLoopRegion(null);
+              setLoopStartPoint(null);
+
+              // Label stems by their tags where the files have them
+              if (tags) {
+                setStems(stems => stems.map((stem, index) => {
+                  const streamTags = tags[index];
+                  if (!streamTags?.title) {
+                    return stem;
+                  }
+                  const name = streamTags.artist ? `${streamTags.artist} - ${streamTags.title}` : streamTags.title;
+                  return { ...stem, name };
+                }));
+              }
             } else {
               setFileStatus(`Error: ${error || 'Failed to process audio file'}`);
             }
           }
         });
 
+        // Register the transport callback to track the playhead and report where seeks landed
+        engine.set_transport_callback((event) => {
+          const { type, success, position, duration, loopStart, loopEnd, buffering, error } = event;
+
+          if (success && position !== undefined) {
+            setPosition(position);
+          }
+          if (success && duration !== undefined) {
+            setDuration(duration);
+          }
+          if (type === 'position') {
+            setIsBuffering(buffering ?? false);
+          }
+
+          if (type === 'seeked') {
+            if (success && position !== undefined) {
+              setFileStatus(`Playback position moved to ${position.toFixed(2)}s`);
+            } else {
+              setFileStatus(`Error: ${error || 'Failed to seek'}`);
+            }
+          }
+
+          if (type === 'loopSet') {
+            if (success) {
+              setLoopRegion(loopStart != null && loopEnd != null ? { start: loopStart, end: loopEnd } : null);
+            } else {
+              setFileStatus(`Error: ${error || 'Failed to set loop region'}`);
+            }
+          }
+        });
+
+        // Register the diagnostics callback to show the mixer's statistics
+        engine.set_diagnostics_callback((event) => {
+          if (event.type === 'syncStats' && event.success && event.stats) {
+            setSyncStats(event.stats);
+          }
+          if (event.type === 'bufferMetrics' && event.success && event.metrics) {
+            setBufferMetrics(event.metrics);
+          }
+        });
+
         // Store the engine in the ref
         audioEngineRef.current = engine;
         setIsLoading(false);
@@ -169,7 +466,7 @@ function AudioControls() {
         }
       }
     };
-  }, [isInitialized, isLoading, sourceType, needsReinitialization]);
+  }, [isInitialized, isLoading, sourceType, bufferingMode, needsReinitialization]);
 
   const handlePlayPause = async () => {
     if (!audioEngineRef.current) return;
@@ -207,7 +504,7 @@ function AudioControls() {
 
       // Check if all files are audio files
       for (let i = 0; i < fileCount; i++) {
-        if (!files[i].type.startsWith('audio/')) {
+        if (!isAudioFile(files[i])) {
           setFileStatus('Error: Please select only audio files');
           return;
         }
@@ -220,6 +517,7 @@ function AudioControls() {
       if (audioEngineRef.current) {
         try {
           await audioEngineRef.current.send_audio_files(files);
+          resetStems(Array.from(files).map(file => file.name));
           setFileStatus(`${fileCount} file(s) sent to audio engine`);
         } catch (err) {
           console.error('Error sending files to worker:', err);
@@ -231,6 +529,28 @@ function AudioControls() {
     }
   };
 
+  // Add a file to the running mix at the chosen timeline offset
+  const handleAddStem = (e: React.ChangeEvent<HTMLInputElement>) => {
+    const file = e.target.files?.[0];
+    e.target.value = '';
+    if (!file || !audioEngineRef.current) {
+      return;
+    }
+
+    if (!isAudioFile(file)) {
+      setFileStatus('Error: Please select an audio file');
+      return;
+    }
+
+    try {
+      audioEngineRef.current.add_audio_file(file, addStemOffset);
+      setFileStatus(`Adding "${file.name}" at ${addStemOffset.toFixed(1)}s...`);
+    } catch (err) {
+      console.error('Error adding stem:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to add file'}`);
+    }
+  };
+
   const handleReset = async () => {
     if (!audioEngineRef.current) {
       return;
@@ -245,12 +565,104 @@ function AudioControls() {
     }
   };
 
+  const formatTime = (seconds: number) => {
+    const minutes = Math.floor(seconds / 60);
+    return `${minutes}:${(seconds - minutes * 60).toFixed(1).padStart(4, '0')}`;
+  };
+
+  const handleSeek = (e: React.ChangeEvent<HTMLInputElement>) => {
+    if (!audioEngineRef.current) {
+      return;
+    }
+
+    const target = parseFloat(e.target.value);
+    setPosition(target);
+
+    try {
+      audioEngineRef.current.seek(target);
+    } catch (err) {
+      console.error('Error seeking:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to seek'}`);
+    }
+  };
+
+  const handleResamplerQualityChange = (e: React.ChangeEvent<HTMLSelectElement>) => {
+    const quality = e.target.value as ResamplerQuality;
+    setResamplerQuality(quality);
+
+    try {
+      audioEngineRef.current?.set_resampler_quality(quality);
+    } catch (err) {
+      console.error('Error setting resampler quality:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to set resampler quality'}`);
+    }
+  };
+
+  // The first press marks the loop start (A), the second sets the loop end (B)
+  const handleLoopPoint = () => {
+    if (!audioEngineRef.current) {
+      return;
+    }
+
+    if (loopStartPoint === null) {
+      setLoopStartPoint(position);
+      return;
+    }
+
+    try {
+      audioEngineRef.current.set_loop(Math.min(loopStartPoint, position), Math.max(loopStartPoint, position));
+    } catch (err) {
+      console.error('Error setting loop:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to set loop region'}`);
+    }
+    setLoopStartPoint(null);
+  };
+
+  const handleClearLoop = () => {
+    setLoopStartPoint(null);
+
+    try {
+      audioEngineRef.current?.clear_loop();
+    } catch (err) {
+      console.error('Error clearing loop:', err);
+      setFileStatus(`Error: ${err instanceof Error ? err.message : 'Failed to clear loop region'}`);
+    }
+  };
+
+  const handleDebugLoggingChange = (e: React.ChangeEvent<HTMLInputElement>) => {
+    const enabled = e.target.checked;
+    setDebugLogging(enabled);
+
+    try {
+      audioEngineRef.current?.set_debug(enabled);
+    } catch (err) {
+      console.error('Error setting debug logging:', err);
+    }
+  };
+
+  // Save the collected log messages as a JSON file
+  const handleDownloadLogs = () => {
+    const blob = new Blob([JSON.stringify(logRecordsRef.current, null, 2)], { type: 'application/json' });
+    const url = URL.createObjectURL(blob);
+    const link = document.createElement('a');
+    link.href = url;
+    link.download = `audio-engine-logs-${new Date().toISOString()}.json`;
+    link.click();
+    URL.revokeObjectURL(url);
+  };
+
   const handleSourceTypeChange = (e: React.ChangeEvent<HTMLSelectElement>) => {
     const newSourceType = e.target.value as SourceType;
     setSourceType(newSourceType);
     setNeedsReinitialization(true);
   };
 
+  // The ring buffer is created with the engine, so changing its size means starting over
+  const handleBufferingModeChange = (e: React.ChangeEvent<HTMLSelectElement>) => {
+    setBufferingMode(e.target.value as BufferingMode);
+    setNeedsReinitialization(true);
+  };
+
   if (error) {
     return (
       <div className="p-4 bg-red-100 border border-red-400 text-red-700 rounded">
@@ -282,6 +694,22 @@ function AudioControls() {
             </select>
           </div>
 
+          <div className="mb-4">
+            <label className="block mb-2 font-medium">
+              Buffering:
+            </label>
+            <select
+              value={bufferingMode}
+              onChange={handleBufferingModeChange}
+              disabled={isPlaying}
+              className="w-full p-2 border rounded"
+            >
+              <option value="live">Live (lowest latency)</option>
+              <option value="standard">Standard</option>
+              <option value="safe">Safe (for slow devices)</option>
+            </select>
+          </div>
+
           <div className="mb-4">
             <button
               onClick={handlePlayPause}
@@ -325,6 +753,23 @@ function AudioControls() {
                 Audio Source:
               </label>
               <div className="space-y-4">
+                <div className="p-4 border rounded-lg">
+                  <h3 className="font-medium mb-2">Resampler Quality</h3>
+                  <select
+                    value={resamplerQuality}
+                    onChange={handleResamplerQualityChange}
+                    disabled={!isInitialized}
+                    className="w-full p-2 border rounded"
+                  >
+                    <option value="low">Low (least CPU and latency)</option>
+                    <option value="medium">Medium</option>
+                    <option value="high">High (best quality)</option>
+                  </select>
+                  <div className="mt-2 text-sm text-gray-600">
+                    Converts the 48kHz mix to the output device&apos;s sample rate
+                  </div>
+                </div>
+
                 <div className="p-4 border rounded-lg">
                   <h3 className="font-medium mb-2">Demo Files</h3>
                   <button
@@ -344,7 +789,7 @@ function AudioControls() {
                   <input
                     type="file"
                     onChange={handleFileChange}
-                    accept="audio/*"
+                    accept={AUDIO_FILE_ACCEPT}
                     multiple
                     disabled={!isInitialized || sourceType !== 'opusPlayer'}
                     className="block w-full text-sm text-gray-500
@@ -359,10 +804,224 @@ function AudioControls() {
                     Select one or more audio files to upload
                   </div>
                 </div>
+
+                <div className="p-4 border rounded-lg">
+                  <h3 className="font-medium mb-2">Add a Stem</h3>
+                  <label className="block mb-2 text-sm">
+                    Starts at (seconds):
+                    <input
+                      type="number"
+                      step="0.1"
+                      value={addStemOffset}
+                      onChange={(e) => setAddStemOffset(parseFloat(e.target.value) || 0)}
+                      className="ml-2 w-24 p-1 border rounded"
+                    />
+                  </label>
+                  <input
+                    type="file"
+                    onChange={handleAddStem}
+                    accept={AUDIO_FILE_ACCEPT}
+                    disabled={!isInitialized || sourceType !== 'opusPlayer'}
+                    className="block w-full text-sm text-gray-500
+                      file:mr-4 file:py-2 file:px-4
+                      file:rounded file:border-0
+                      file:text-sm file:font-semibold
+                      file:bg-blue-50 file:text-blue-700
+                      hover:file:bg-blue-100
+                      disabled:opacity-50 disabled:cursor-not-allowed"
+                  />
+                  <div className="mt-2 text-sm text-gray-600">
+                    Adds a file to the mix without stopping playback
+                  </div>
+                </div>
               </div>
             </div>
           )}
 
+          {sourceType === 'opusPlayer' && duration > 0 && (
+            <div className="mb-4">
+              <label className="block mb-2 font-medium">
+                Position: {formatTime(position)} / {formatTime(duration)}
+                {isBuffering && ' (buffering...)'}
+              </label>
+              <input
+                type="range"
+                min="0"
+                max={duration}
+                step="0.1"
+                value={position}
+                onChange={handleSeek}
+                disabled={!isInitialized}
+                className="w-full"
+              />
+              <div className="mt-2 flex items-center space-x-2 text-sm">
+                <button
+                  onClick={handleLoopPoint}
+                  disabled={!isInitialized}
+                  className="px-2 py-1 rounded bg-gray-200 hover:bg-gray-300 disabled:opacity-50"
+                >
+                  {loopStartPoint === null ? 'Set Loop A' : 'Set Loop B'}
+                </button>
+                <button
+                  onClick={handleClearLoop}
+                  disabled={!isInitialized || (loopRegion === null && loopStartPoint === null)}
+                  className="px-2 py-1 rounded bg-gray-200 hover:bg-gray-300 disabled:opacity-50"
+                >
+                  Clear Loop
+                </button>
+                <span className="text-gray-600">
+                  {loopRegion
+                    ? `Looping ${formatTime(loopRegion.start)} - ${formatTime(loopRegion.end)}`
+                    : loopStartPoint !== null
+                      ? `Loop A at ${formatTime(loopStartPoint)}`
+                      : 'No loop'}
+                </span>
+              </div>
+            </div>
+          )}
+
+          {sourceType === 'opusPlayer' && stems.length > 0 && (
+            <div className="mb-4 p-4 border rounded-lg">
+              <h3 className="font-medium mb-2">Stems</h3>
+              <div className="space-y-3">
+                {stems.map((stem, index) => (
+                  <div key={stem.id} className="text-sm">
+                    <div className="flex items-center justify-between mb-1">
+                      <span className="font-medium truncate">{stem.name}</span>
+                      <div className="space-x-1">
+                        <button
+                          onClick={() => updateStem(index, { muted: !stem.muted })}
+                          className={`px-2 py-0.5 rounded ${stem.muted ? 'bg-red-500 text-white' : 'bg-gray-200'}`}
+                        >
+                          M
+                        </button>
+                        <button
+                          onClick={() => updateStem(index, { solo: !stem.solo })}
+                          className={`px-2 py-0.5 rounded ${stem.solo ? 'bg-yellow-400' : 'bg-gray-200'}`}
+                        >
+                          S
+                        </button>
+                        <button
+                          onClick={() => removeStem(stem.id)}
+                          className="px-2 py-0.5 rounded bg-gray-200 hover:bg-gray-300"
+                        >
+                          Remove
+                        </button>
+                      </div>
+                    </div>
+                    <label className="block">
+                      Gain: {stem.gainDb.toFixed(1)} dB
+                      <input
+                        type="range"
+                        min="-60"
+                        max="12"
+                        step="0.5"
+                        value={stem.gainDb}
+                        onChange={(e) => updateStem(index, { gainDb: parseFloat(e.target.value) })}
+                        className="w-full"
+                      />
+                    </label>
+                    <label className="block">
+                      Pan: {stem.pan.toFixed(2)}
+                      <input
+                        type="range"
+                        min="-1"
+                        max="1"
+                        step="0.01"
+                        value={stem.pan}
+                        onChange={(e) => updateStem(index, { pan: parseFloat(e.target.value) })}
+                        className="w-full"
+                      />
+                    </label>
+                    <label className="block">
+                      Starts at (seconds):
+                      <input
+                        type="number"
+                        step="0.01"
+                        value={stem.offset}
+                        onChange={(e) => updateStem(index, { offset: parseFloat(e.target.value) || 0 })}
+                        className="ml-2 w-24 p-1 border rounded"
+                      />
+                    </label>
+                  </div>
+                ))}
+              </div>
+            </div>
+          )}
+
+          <div className="mb-4 flex items-center space-x-4 text-sm">
+            <label className="flex items-center">
+              <input
+                type="checkbox"
+                checked={debugLogging}
+                onChange={handleDebugLoggingChange}
+                disabled={!isInitialized}
+                className="mr-2"
+              />
+              Debug logging
+            </label>
+            <button
+              onClick={handleDownloadLogs}
+              className="px-2 py-1 rounded bg-gray-200 hover:bg-gray-300"
+            >
+              Download Logs
+            </button>
+          </div>
+
+          {bufferMetrics && (
+            <div className="mb-4 p-3 bg-gray-50 rounded-lg text-sm">
+              <h3 className="font-medium mb-2">Buffer health</h3>
+              <div className="w-full h-2 mb-2 bg-gray-200 rounded">
+                <div
+                  className={`h-2 rounded ${bufferMetrics.fillMs < bufferMetrics.capacityMs / 4 ? 'bg-red-500' : 'bg-green-500'}`}
+                  style={{ width: `${Math.min(100, (bufferMetrics.fillMs / bufferMetrics.capacityMs) * 100)}%` }}
+                />
+              </div>
+              <p>
+                Fill: {bufferMetrics.fillMs.toFixed(1)} / {bufferMetrics.capacityMs.toFixed(1)} ms,
+                underruns: {bufferMetrics.underruns}, overruns: {bufferMetrics.overruns}
+              </p>
+            </div>
+          )}
+
+          {sourceType === 'opusPlayer' && syncStats && syncStats.streams.length > 0 && (
+            <div className="mb-4 p-3 bg-gray-50 rounded-lg text-sm">
+              <h3 className="font-medium mb-2">
+                Diagnostics (max drift between stems: {syncStats.maxDriftMs.toFixed(2)} ms)
+              </h3>
+              <table className="w-full text-left">
+                <thead>
+                  <tr>
+                    <th>Stem</th>
+                    <th>Last drift</th>
+                    <th>Max drift</th>
+                    <th>Avg drift</th>
+                    <th>Rate</th>
+                    <th>Correction</th>
+                    <th>Decoded</th>
+                  </tr>
+                </thead>
+                <tbody>
+                  {syncStats.streams.map(stream => {
+                    const last = stream.history[stream.history.length - 1];
+                    const stem = stems.find(stem => stem.id === stream.streamId);
+                    return (
+                      <tr key={stream.streamId}>
+                        <td>{stem ? stem.name : `Stream ${stream.streamId}`}</td>
+                        <td>{last ? `${last.driftMs.toFixed(2)} ms` : '-'}</td>
+                        <td>{stream.maxDriftMs.toFixed(2)} ms</td>
+                        <td>{stream.averageDriftMs.toFixed(2)} ms</td>
+                        <td>{stream.playbackRate.toFixed(4)}x</td>
+                        <td>{(stream.appliedCorrection * 100).toFixed(3)}%</td>
+                        <td>{stream.decodedFrames.toLocaleString()} frames</td>
+                      </tr>
+                    );
+                  })}
+                </tbody>
+              </table>
+            </div>
+          )}
+
           {fileStatus && (
             <div className="mb-4 p-3 bg-gray-50 rounded-lg">
               <p className="text-sm text-gray-700">{fileStatus}</p>
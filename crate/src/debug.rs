@@ -1,33 +1,329 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU8, Ordering};
 
-// Debug flag - can be enabled via environment variable
-static DEBUG: AtomicBool = AtomicBool::new(false);
+use wasm_bindgen::prelude::*;
 
-// TODO: This isn't working
-/// Enable or disable debug output
-pub fn set_debug(enabled: bool) {
-    DEBUG.store(enabled, Ordering::Relaxed);
+#[cfg(target_arch = "wasm32")]
+use std::cell::RefCell;
+
+/// How much to log, from nothing up to every frame processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
 }
 
-/// Get the current debug state
-pub fn is_debug_enabled() -> bool {
-    DEBUG.load(Ordering::Relaxed)
+impl LogLevel {
+    pub fn from_name(name: &str) -> Result<Self, JsValue> {
+        match name {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(JsValue::from_str(&format!(
+                "Unknown log level: {} (expected off, error, warn, info or debug)",
+                name
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => LogLevel::Off,
+            1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            3 => LogLevel::Info,
+            _ => LogLevel::Debug,
+        }
+    }
 }
 
-// Debug macro for conditional printing
-#[macro_export]
-macro_rules! debug {
-    ($($arg:tt)*) => {{
-        if $crate::debug::is_debug_enabled() {
-            #[cfg(target_arch = "wasm32")]
-            {
-                use wasm_bindgen::JsValue;
-                web_sys::console::log_1(&format!($($arg)*).into());
+/// The part of the engine a message comes from, each with its own log level
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogCategory {
+    RingBuffer = 0,
+    Mixer = 1,
+    // Decoding and playing a single file
+    Stream = 2,
+    // Everything else: the worker interface and the other sources
+    Engine = 3,
+}
+
+const CATEGORIES: [LogCategory; 4] = [
+    LogCategory::RingBuffer,
+    LogCategory::Mixer,
+    LogCategory::Stream,
+    LogCategory::Engine,
+];
+
+impl LogCategory {
+    pub fn from_name(name: &str) -> Result<Self, JsValue> {
+        CATEGORIES
+            .iter()
+            .copied()
+            .find(|category| category.name() == name)
+            .ok_or_else(|| {
+                JsValue::from_str(&format!(
+                    "Unknown log category: {} (expected ringBuffer, mixer, stream or engine)",
+                    name
+                ))
+            })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LogCategory::RingBuffer => "ringBuffer",
+            LogCategory::Mixer => "mixer",
+            LogCategory::Stream => "stream",
+            LogCategory::Engine => "engine",
+        }
+    }
+
+    /// The category for messages logged from the module at `module_path`
+    pub fn for_module(module_path: &str) -> Self {
+        if module_path.ends_with("::ring_buffer") {
+            LogCategory::RingBuffer
+        } else if module_path.ends_with("::audio_mixer") || module_path.ends_with("::opus_source") {
+            LogCategory::Mixer
+        } else if module_path.contains("::opus_mixer") {
+            LogCategory::Stream
+        } else {
+            LogCategory::Engine
+        }
+    }
+}
+
+const DEFAULT_LEVEL: u8 = LogLevel::Warn as u8;
+
+/// The log level of each category
+struct Levels {
+    categories: [AtomicU8; 4],
+    // Highest level of any category, checked first so disabled messages cost next to nothing
+    max_level: AtomicU8,
+}
+
+impl Levels {
+    const fn new() -> Self {
+        Self {
+            categories: [
+                AtomicU8::new(DEFAULT_LEVEL),
+                AtomicU8::new(DEFAULT_LEVEL),
+                AtomicU8::new(DEFAULT_LEVEL),
+                AtomicU8::new(DEFAULT_LEVEL),
+            ],
+            max_level: AtomicU8::new(DEFAULT_LEVEL),
+        }
+    }
+
+    fn set(&self, level: LogLevel, category: Option<LogCategory>) {
+        match category {
+            Some(category) => {
+                self.categories[category as usize].store(level as u8, Ordering::Relaxed)
             }
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                println!($($arg)*);
+            None => {
+                for stored in &self.categories {
+                    stored.store(level as u8, Ordering::Relaxed);
+                }
             }
         }
+
+        let max_level = self
+            .categories
+            .iter()
+            .map(|stored| stored.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(DEFAULT_LEVEL);
+        self.max_level.store(max_level, Ordering::Relaxed);
+    }
+
+    fn get(&self, category: LogCategory) -> LogLevel {
+        LogLevel::from_u8(self.categories[category as usize].load(Ordering::Relaxed))
+    }
+
+    fn is_enabled(&self, level: LogLevel, module_path: &str) -> bool {
+        level != LogLevel::Off
+            && level as u8 <= self.max_level.load(Ordering::Relaxed)
+            && level <= self.get(LogCategory::for_module(module_path))
+    }
+}
+
+// Each wasm instance (the page's and the worker's) has its own levels
+static LEVELS: Levels = Levels::new();
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    static CALLBACK: RefCell<Option<js_sys::Function>> = RefCell::new(None);
+}
+
+/// Set the log level of one category, or of all of them
+pub fn set_level(level: LogLevel, category: Option<LogCategory>) {
+    LEVELS.set(level, category);
+}
+
+/// Whether a message at `level` from the module at `module_path` would be logged
+pub fn is_enabled(level: LogLevel, module_path: &str) -> bool {
+    LEVELS.is_enabled(level, module_path)
+}
+
+/// Log a message, to the callback set with `setLogCallback` if there is one, otherwise to the
+/// console
+pub fn write(level: LogLevel, module_path: &str, message: &str) {
+    let category = LogCategory::for_module(module_path);
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let handled = CALLBACK.with(|callback| match &*callback.borrow() {
+            Some(callback) => {
+                let _ = callback.call1(&JsValue::NULL, &log_record(level, category, message));
+                true
+            }
+            None => false,
+        });
+
+        if !handled {
+            let line = JsValue::from_str(&format!("[{}] {}", category.name(), message));
+            match level {
+                LogLevel::Error => web_sys::console::error_1(&line),
+                LogLevel::Warn => web_sys::console::warn_1(&line),
+                LogLevel::Info => web_sys::console::info_1(&line),
+                _ => web_sys::console::log_1(&line),
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        println!("[{}] {}: {}", category.name(), level.name(), message);
+    }
+}
+
+// A log message as a JavaScript object: { level, category, message, time }
+#[cfg(target_arch = "wasm32")]
+fn log_record(level: LogLevel, category: LogCategory, message: &str) -> JsValue {
+    let record = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&record, &"level".into(), &level.name().into());
+    let _ = js_sys::Reflect::set(&record, &"category".into(), &category.name().into());
+    let _ = js_sys::Reflect::set(&record, &"message".into(), &message.into());
+    let _ = js_sys::Reflect::set(
+        &record,
+        &"time".into(),
+        &JsValue::from_f64(js_sys::Date::now()),
+    );
+    record.into()
+}
+
+/// Set the log level: "off", "error", "warn", "info" or "debug". Applies to one category
+/// ("ringBuffer", "mixer", "stream" or "engine") if one is given, otherwise to all of them.
+/// Only affects the wasm instance it's called in, so the worker needs its own call.
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level(level: &str, category: Option<String>) -> Result<(), JsValue> {
+    let level = LogLevel::from_name(level)?;
+    let category = category
+        .as_deref()
+        .map(LogCategory::from_name)
+        .transpose()?;
+    set_level(level, category);
+    Ok(())
+}
+
+/// Log everything, or go back to logging only warnings and errors
+#[wasm_bindgen(js_name = setDebug)]
+pub fn set_debug(enabled: bool) {
+    let level = if enabled {
+        LogLevel::Debug
+    } else {
+        LogLevel::from_u8(DEFAULT_LEVEL)
+    };
+    set_level(level, None);
+}
+
+/// Send log messages to `callback` as { level, category, message, time } objects instead of
+/// the console, or back to the console if it's null
+#[wasm_bindgen(js_name = setLogCallback)]
+#[allow(unused_variables)]
+pub fn set_log_callback(callback: Option<js_sys::Function>) {
+    #[cfg(target_arch = "wasm32")]
+    CALLBACK.with(|stored| *stored.borrow_mut() = callback);
+}
+
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::debug::is_enabled(level, module_path!()) {
+            $crate::debug::write(level, module_path!(), &format!($($arg)*));
+        }
     }};
 }
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log_at!($crate::debug::LogLevel::Error, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log_at!($crate::debug::LogLevel::Warn, $($arg)*) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log_at!($crate::debug::LogLevel::Info, $($arg)*) };
+}
+
+// Debug macro for detailed tracing, off unless enabled with setDebug or setLogLevel
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log_at!($crate::debug::LogLevel::Debug, $($arg)*) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_filtered_by_their_module_category() {
+        assert_eq!(
+            LogCategory::for_module("wasm_pack_test_27_feb::ring_buffer"),
+            LogCategory::RingBuffer
+        );
+        assert_eq!(
+            LogCategory::for_module("wasm_pack_test_27_feb::opus_mixer::audio_mixer"),
+            LogCategory::Mixer
+        );
+        assert_eq!(
+            LogCategory::for_module("wasm_pack_test_27_feb::opus_mixer::ogg_decoder"),
+            LogCategory::Stream
+        );
+        assert_eq!(
+            LogCategory::for_module("wasm_pack_test_27_feb"),
+            LogCategory::Engine
+        );
+
+        // Separate levels from the global ones, which other tests log through in parallel
+        let levels = Levels::new();
+        assert_eq!(levels.get(LogCategory::Stream), LogLevel::Warn);
+
+        levels.set(LogLevel::Debug, Some(LogCategory::Mixer));
+        assert!(levels.is_enabled(LogLevel::Debug, "crate::opus_mixer::audio_mixer"));
+        assert!(!levels.is_enabled(LogLevel::Debug, "crate::ring_buffer"));
+        assert!(levels.is_enabled(LogLevel::Warn, "crate::ring_buffer"));
+
+        levels.set(LogLevel::Off, None);
+        assert!(!levels.is_enabled(LogLevel::Error, "crate::opus_mixer::audio_mixer"));
+        assert_eq!(levels.get(LogCategory::Stream), LogLevel::Off);
+    }
+}
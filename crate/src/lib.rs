@@ -3,10 +3,11 @@ mod opus_mixer;
 mod opus_source;
 mod oscillator;
 mod ring_buffer;
+mod sample_rate_converter;
 mod source;
 mod utils;
 
-use debug::set_debug;
+use opus_mixer::error::MixerError;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{AudioContext, AudioWorkletNode};
@@ -17,6 +18,13 @@ pub use oscillator::Oscillator;
 pub use ring_buffer::{get_buffer_size, get_metadata_size, RingBuffer};
 pub use source::{AudioSource, SourceType};
 
+// Mixer errors reach JavaScript as their message
+impl From<MixerError> for JsValue {
+    fn from(error: MixerError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
 #[wasm_bindgen]
 extern "C" {
     fn alert(s: &str);
@@ -35,7 +43,19 @@ pub struct AudioEngineInterface {
     is_initialized: bool,
     pending_operations: Vec<PendingOperation>,
     audio_file_callback: Option<js_sys::Function>,
+    transport_callback: Option<js_sys::Function>,
+    log_callback: Option<js_sys::Function>,
+    diagnostics_callback: Option<js_sys::Function>,
+    // Log levels set before initialization, as (level, category), sent with the init message
+    log_levels: Vec<(String, Option<String>)>,
     source_type: String,
+    resampler_quality: String,
+    // Ring buffer size in samples, and how full the worker keeps it in milliseconds (the whole
+    // buffer if not set)
+    buffer_size: usize,
+    target_fill_ms: Option<f64>,
+    position: f64,
+    duration: f64,
 }
 
 // Define an enum for pending operations
@@ -50,7 +70,6 @@ impl AudioEngineInterface {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Result<AudioEngineInterface, JsValue> {
         utils::set_panic_hook();
-        set_debug(false);
 
         // Create a new audio context
         let context = AudioContext::new()?;
@@ -63,7 +82,16 @@ impl AudioEngineInterface {
             is_initialized: false,
             pending_operations: Vec::new(),
             audio_file_callback: None,
+            transport_callback: None,
+            log_callback: None,
+            diagnostics_callback: None,
+            log_levels: Vec::new(),
             source_type: "opusPlayer".to_string(), // Default to opusPlayer
+            resampler_quality: "medium".to_string(),
+            buffer_size: ring_buffer::DEFAULT_BUFFER_SIZE,
+            target_fill_ms: None,
+            position: 0.0,
+            duration: 0.0,
         })
     }
 
@@ -204,23 +232,150 @@ impl AudioEngineInterface {
                         log("Failed to set frequency");
                     }
                 }
-                "audioFileReceived" => {
+                "seeked" | "position" | "loopSet" => {
+                    if type_str == "seeked" {
+                        if success {
+                            log("Seek completed successfully");
+                        } else {
+                            log("Failed to seek");
+                        }
+                    } else if type_str == "loopSet" && !success {
+                        log("Failed to set loop region");
+                    }
+
+                    let position = js_sys::Reflect::get(&js_obj, &"position".into())
+                        .ok()
+                        .and_then(|value| value.as_f64());
+                    let duration = js_sys::Reflect::get(&js_obj, &"duration".into())
+                        .ok()
+                        .and_then(|value| value.as_f64());
+
+                    // Update the transport state and call the registered callback if available
+                    unsafe {
+                        if !engine_ptr.is_null() {
+                            let engine = &mut *engine_ptr;
+                            if let Some(position) = position {
+                                engine.position = position;
+                            }
+                            if let Some(duration) = duration {
+                                engine.duration = duration;
+                            }
+                            if let Some(callback) = &engine.transport_callback {
+                                let _ = callback.call1(&JsValue::NULL, &js_obj);
+                            }
+                        }
+                    }
+                }
+                "targetFillSet" => {
                     if success {
+                        let target_fill = js_sys::Reflect::get(&js_obj, &"targetFill".into())
+                            .ok()
+                            .and_then(|value| value.as_f64());
+                        if let Some(target_fill) = target_fill {
+                            log(&format!("Target buffer fill set to {:.1}ms", target_fill));
+                        }
+                    } else {
+                        log("Failed to set target buffer fill");
+                    }
+                }
+                "resamplerQualitySet" => {
+                    if success {
+                        log("Resampler quality set successfully");
+                    } else {
+                        log("Failed to set resampler quality");
+                    }
+                }
+                "streamControlSet" => {
+                    if !success {
+                        log("Failed to set stream control");
+                    }
+                }
+                "audioFileReceived" | "audioLoaded" => {
+                    if type_str == "audioLoaded" {
+                        if success {
+                            log("Audio files finished loading");
+                        } else {
+                            log("Failed to finish loading audio files");
+                        }
+                    } else if success {
                         log("Audio file received by worker successfully");
                     } else {
                         log("Failed to process audio file in worker");
                     }
 
+                    let duration = js_sys::Reflect::get(&js_obj, &"duration".into())
+                        .ok()
+                        .and_then(|value| value.as_f64());
+
                     // Call the registered callback if available
                     unsafe {
                         if !engine_ptr.is_null() {
                             let engine = &mut *engine_ptr;
+                            if let Some(duration) = duration {
+                                engine.duration = duration;
+                                if type_str == "audioFileReceived" {
+                                    engine.position = 0.0;
+                                }
+                            }
                             if let Some(callback) = &engine.audio_file_callback {
                                 let _ = callback.call1(&JsValue::NULL, &js_obj);
                             }
                         }
                     }
                 }
+                "streamAdded" | "streamRemoved" => {
+                    if !success {
+                        log(&format!("Failed to update streams: {}", type_str));
+                    }
+
+                    let duration = js_sys::Reflect::get(&js_obj, &"duration".into())
+                        .ok()
+                        .and_then(|value| value.as_f64());
+
+                    unsafe {
+                        if !engine_ptr.is_null() {
+                            let engine = &mut *engine_ptr;
+                            if let Some(duration) = duration {
+                                engine.duration = duration;
+                            }
+                            if let Some(callback) = &engine.audio_file_callback {
+                                let _ = callback.call1(&JsValue::NULL, &js_obj);
+                            }
+                        }
+                    }
+                }
+                "syncStats" | "bufferMetrics" => {
+                    // Periodic drift and decoding statistics from the mixer, and ring buffer
+                    // health
+                    unsafe {
+                        if !engine_ptr.is_null() {
+                            let engine = &*engine_ptr;
+                            if let Some(callback) = &engine.diagnostics_callback {
+                                let _ = callback.call1(&JsValue::NULL, &js_obj);
+                            }
+                        }
+                    }
+                }
+                "log" => {
+                    // Log messages from the worker's wasm instance, sent in batches
+                    let records = js_sys::Reflect::get(&js_obj, &"records".into())
+                        .map(|records| js_sys::Array::from(&records))
+                        .unwrap_or_else(|_| js_sys::Array::new());
+
+                    unsafe {
+                        if !engine_ptr.is_null() {
+                            let engine = &*engine_ptr;
+                            for record in records.iter() {
+                                match &engine.log_callback {
+                                    Some(callback) => {
+                                        let _ = callback.call1(&JsValue::NULL, &record);
+                                    }
+                                    None => log(&format_log_record(&record)),
+                                }
+                            }
+                        }
+                    }
+                }
                 _ => {
                     log(&format!("Unknown message type: {}", type_str));
                 }
@@ -248,6 +403,39 @@ impl AudioEngineInterface {
             &JsValue::from_str(&self.source_type),
         )?;
 
+        // Pass the sample rate conversion quality to the worker
+        js_sys::Reflect::set(
+            &init_data,
+            &"resamplerQuality".into(),
+            &JsValue::from_str(&self.resampler_quality),
+        )?;
+
+        // Pass the ring buffer settings to the worker
+        js_sys::Reflect::set(
+            &init_data,
+            &"bufferSize".into(),
+            &JsValue::from_f64(self.buffer_size as f64),
+        )?;
+        js_sys::Reflect::set(
+            &init_data,
+            &"targetFillMs".into(),
+            &self.target_fill_ms.map_or(JsValue::NULL, JsValue::from_f64),
+        )?;
+
+        // Pass the log levels set so far to the worker's wasm instance
+        let log_levels = js_sys::Array::new();
+        for (level, category) in &self.log_levels {
+            let setting = js_sys::Object::new();
+            js_sys::Reflect::set(&setting, &"level".into(), &JsValue::from_str(level))?;
+            js_sys::Reflect::set(
+                &setting,
+                &"category".into(),
+                &category.as_deref().map_or(JsValue::NULL, JsValue::from_str),
+            )?;
+            log_levels.push(&setting);
+        }
+        js_sys::Reflect::set(&init_data, &"logLevels".into(), &log_levels)?;
+
         js_sys::Reflect::set(&init_msg, &"data".into(), &init_data)?;
 
         worker.post_message(&init_msg)?;
@@ -330,6 +518,42 @@ impl AudioEngineInterface {
         Ok(())
     }
 
+    // Method to stream audio files from URLs to the worker. Playback can start as soon as the
+    // start of every file has arrived, before the downloads finish.
+    pub fn send_audio_urls(&self, urls: js_sys::Array) -> Result<(), JsValue> {
+        if !self.is_initialized {
+            log("Cannot send audio URLs - engine not initialized");
+            return Err(JsValue::from_str("Audio engine not initialized"));
+        }
+
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"urls".into(), &urls)?;
+
+        log(&format!("Sending {} audio URLs to worker", urls.length()));
+        self.post_worker_message("loadAudioUrls", &data)
+    }
+
+    // Add an audio file to the mix without interrupting playback, with its first frame at
+    // `offset` seconds on the timeline (for opus player). The new stream's id is reported
+    // through the audio file callback in a `streamAdded` message.
+    pub fn add_audio_file(&self, file: web_sys::File, offset: f64) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"file".into(), &file)?;
+        js_sys::Reflect::set(&data, &"offset".into(), &JsValue::from_f64(offset))?;
+        self.post_worker_message("addAudioFile", &data)
+    }
+
+    // Remove a stream from the mix by id, fading it out if it's playing (for opus player)
+    pub fn remove_stream(&self, stream_id: usize) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        self.post_worker_message("removeStream", &data)
+    }
+
     pub async fn resume(&mut self) -> Result<(), JsValue> {
         // Resume the audio context
         JsFuture::from(self.context.resume()?).await?;
@@ -370,6 +594,91 @@ impl AudioEngineInterface {
         log("Audio file callback registered");
     }
 
+    // Set the log level ("off", "error", "warn", "info" or "debug") of one category
+    // ("ringBuffer", "mixer", "stream" or "engine"), or of all of them if `category` is null,
+    // both on this thread and in the worker
+    pub fn set_log_level(&mut self, level: &str, category: Option<String>) -> Result<(), JsValue> {
+        debug::set_log_level(level, category.clone())?;
+
+        // Before initialization the levels are sent with the init message
+        if !self.is_initialized {
+            if category.is_none() {
+                self.log_levels.clear();
+            }
+            self.log_levels.push((level.to_string(), category));
+            return Ok(());
+        }
+
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"level".into(), &JsValue::from_str(level))?;
+        js_sys::Reflect::set(
+            &data,
+            &"category".into(),
+            &category.as_deref().map_or(JsValue::NULL, JsValue::from_str),
+        )?;
+        self.post_worker_message("setLogLevel", &data)
+    }
+
+    // Log everything, or only warnings and errors, both on this thread and in the worker
+    pub fn set_debug(&mut self, enabled: bool) -> Result<(), JsValue> {
+        self.set_log_level(if enabled { "debug" } else { "warn" }, None)
+    }
+
+    // Method to register a callback for the engine's log messages, from this thread and the
+    // worker, as { level, category, message, time } objects. Without one they go to the console.
+    pub fn set_log_callback(&mut self, callback: js_sys::Function) {
+        debug::set_log_callback(Some(callback.clone()));
+        self.log_callback = Some(callback);
+        log("Log callback registered");
+    }
+
+    // Method to register a callback for diagnostics the worker sends every second: `syncStats`
+    // with the mixer's drift and decoding statistics (for opus player), and `bufferMetrics`
+    // with the ring buffer's fill level, underruns and overruns
+    pub fn set_diagnostics_callback(&mut self, callback: js_sys::Function) {
+        self.diagnostics_callback = Some(callback);
+        log("Diagnostics callback registered");
+    }
+
+    // Get the last reported playback position in seconds (for opus player)
+    pub fn get_position(&self) -> f64 {
+        self.position
+    }
+
+    // Get the duration of the loaded files in seconds (for opus player)
+    pub fn get_duration(&self) -> f64 {
+        self.duration
+    }
+
+    // Method to register a callback for transport events (seek results and position updates)
+    pub fn set_transport_callback(&mut self, callback: js_sys::Function) {
+        self.transport_callback = Some(callback);
+        log("Transport callback registered");
+    }
+
+    // Seek to a position in seconds (for opus player). The landed position is reported
+    // through the transport callback in a `seeked` message.
+    pub fn seek(&self, seconds: f64) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"seconds".into(), &JsValue::from_f64(seconds))?;
+        self.post_worker_message("seek", &data)
+    }
+
+    // Loop playback between two positions in seconds, wrapping seamlessly from the end back
+    // to the start (for opus player). Can be changed while playing; the region actually used
+    // is reported through the transport callback in a `loopSet` message.
+    pub fn set_loop(&self, start: f64, end: f64) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"start".into(), &JsValue::from_f64(start))?;
+        js_sys::Reflect::set(&data, &"end".into(), &JsValue::from_f64(end))?;
+        self.post_worker_message("setLoop", &data)
+    }
+
+    // Stop looping (for opus player)
+    pub fn clear_loop(&self) -> Result<(), JsValue> {
+        self.post_worker_message("clearLoop", &js_sys::Object::new())
+    }
+
     // Reset the audio source (for opus player)
     pub fn reset(&self) -> Result<(), JsValue> {
         if !self.is_initialized {
@@ -389,6 +698,192 @@ impl AudioEngineInterface {
     pub fn get_source_type(&self) -> String {
         self.source_type.clone()
     }
+
+    // Set the quality of the conversion from 48kHz to the AudioContext sample rate: "low",
+    // "medium" or "high". Higher quality costs more CPU and a little more latency.
+    pub fn set_resampler_quality(&mut self, quality: &str) -> Result<(), JsValue> {
+        self.resampler_quality = quality.to_string();
+
+        // Before initialization the quality is sent with the init message
+        if !self.is_initialized {
+            return Ok(());
+        }
+
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"quality".into(), &JsValue::from_str(quality))?;
+        self.post_worker_message("setResamplerQuality", &data)
+    }
+
+    // Set the size of the ring buffer between the worker and the audio output, in samples (a
+    // power of 2, counting both channels). Bigger buffers ride out longer stalls, at the cost
+    // of latency. Only possible before initialization.
+    pub fn set_buffer_size(&mut self, samples: usize) -> Result<(), JsValue> {
+        if self.is_initialized {
+            return Err(JsValue::from_str(
+                "The ring buffer size can only be set before initialization",
+            ));
+        }
+        ring_buffer::check_buffer_size(samples)?;
+        self.buffer_size = samples;
+        Ok(())
+    }
+
+    // Set how much audio the worker keeps queued in the ring buffer, in milliseconds: low for
+    // live use like the synth, higher for glitch-free playback on slow devices. Clamped to the
+    // buffer size; the level used is reported in a `targetFillSet` message.
+    pub fn set_target_fill(&mut self, ms: f64) -> Result<(), JsValue> {
+        if !ms.is_finite() || ms <= 0.0 {
+            return Err(JsValue::from_str(&format!("Invalid target fill: {}ms", ms)));
+        }
+        self.target_fill_ms = Some(ms);
+
+        // Before initialization the target is sent with the init message
+        if !self.is_initialized {
+            return Ok(());
+        }
+
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &"ms".into(), &JsValue::from_f64(ms))?;
+        self.post_worker_message("setTargetFill", &data)
+    }
+
+    // Set the gain of a stream in decibels (for opus player)
+    pub fn set_stream_gain(&self, stream_id: usize, gain_db: f32) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"gainDb".into(), &JsValue::from_f64(gain_db as f64))?;
+        self.post_worker_message("setStreamGain", &data)
+    }
+
+    // Set the pan of a stream from -1.0 (left) to 1.0 (right) (for opus player)
+    pub fn set_stream_pan(&self, stream_id: usize, pan: f32) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"pan".into(), &JsValue::from_f64(pan as f64))?;
+        self.post_worker_message("setStreamPan", &data)
+    }
+
+    // Mute or unmute a stream (for opus player)
+    pub fn set_stream_mute(&self, stream_id: usize, muted: bool) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"muted".into(), &JsValue::from_bool(muted))?;
+        self.post_worker_message("setStreamMute", &data)
+    }
+
+    // Solo or unsolo a stream (for opus player)
+    pub fn set_stream_solo(&self, stream_id: usize, solo: bool) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"solo".into(), &JsValue::from_bool(solo))?;
+        self.post_worker_message("setStreamSolo", &data)
+    }
+
+    // Start a stream `offset` seconds into the timeline, with silence before it, or skip its
+    // first `-offset` seconds if negative (for opus player)
+    pub fn set_stream_offset(&self, stream_id: usize, offset: f64) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"offset".into(), &JsValue::from_f64(offset))?;
+        self.post_worker_message("setStreamOffset", &data)
+    }
+
+    // Start a stream `samples` into the timeline, counted at 48kHz whatever the output sample
+    // rate (for opus player)
+    pub fn set_stream_offset_samples(&self, stream_id: usize, samples: f64) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"samples".into(), &JsValue::from_f64(samples))?;
+        self.post_worker_message("setStreamOffsetSamples", &data)
+    }
+
+    // Route two of a stream's raw channels straight to the left and right outputs instead of
+    // downmixing (for opus player)
+    pub fn set_stream_raw_channels(
+        &self,
+        stream_id: usize,
+        left: usize,
+        right: usize,
+    ) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        js_sys::Reflect::set(&data, &"left".into(), &JsValue::from_f64(left as f64))?;
+        js_sys::Reflect::set(&data, &"right".into(), &JsValue::from_f64(right as f64))?;
+        self.post_worker_message("setStreamRawChannels", &data)
+    }
+
+    // Fold all of a stream's channels down to stereo, the default (for opus player)
+    pub fn set_stream_downmix(&self, stream_id: usize) -> Result<(), JsValue> {
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &data,
+            &"streamId".into(),
+            &JsValue::from_f64(stream_id as f64),
+        )?;
+        self.post_worker_message("setStreamDownmix", &data)
+    }
+
+    // Helper method to send a message with a data payload to the worker
+    fn post_worker_message(&self, msg_type: &str, data: &js_sys::Object) -> Result<(), JsValue> {
+        if !self.is_initialized {
+            return Err(JsValue::from_str("Audio engine not initialized"));
+        }
+
+        if let Some(worker) = &self.worker {
+            let msg = js_sys::Object::new();
+            js_sys::Reflect::set(&msg, &"type".into(), &msg_type.into())?;
+            js_sys::Reflect::set(&msg, &"data".into(), data)?;
+            worker.post_message(&msg)?;
+        } else {
+            return Err(JsValue::from_str("Worker not available"));
+        }
+
+        Ok(())
+    }
+}
+
+// Format a log record from the worker for the console: "[worker] level category: message"
+fn format_log_record(record: &JsValue) -> String {
+    let field = |name: &str| {
+        js_sys::Reflect::get(record, &name.into())
+            .ok()
+            .and_then(|value| value.as_string())
+            .unwrap_or_default()
+    };
+    format!(
+        "[worker] {} {}: {}",
+        field("level"),
+        field("category"),
+        field("message")
+    )
 }
 
 #[wasm_bindgen]
@@ -1,15 +1,31 @@
 // use anyhow::Result;
-use wasm_bindgen::JsValue;
-use web_sys::File;
 
 use crate::debug;
 use crate::opus_mixer::audio_stream::AudioStream;
+use crate::opus_mixer::downmix::ChannelMode;
+use crate::opus_mixer::drift_stats::{DriftMeasurement, StreamSyncStats, SyncStats};
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::limiter::TruePeakLimiter;
+use crate::opus_mixer::opus_tags::OpusTags;
 use crate::opus_mixer::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
 
-/// Manages multiple audio streams and mixes their output
+// Largest playback rate change used to pull a drifting stream back into sync (0.5% is ~9 cents)
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+// Fixed gain on the master bus. Streams are summed at unity, so the level doesn't change as
+// streams start and finish; the limiter catches whatever still gets through too hot.
+const MASTER_HEADROOM_DB: f32 = -6.0;
+
+/// Manages multiple audio streams and mixes their output. Streams are identified by ids that
+/// stay the same as others are added and removed; the streams the mixer is created with are
+/// numbered from 0 in order.
 #[derive(Debug)]
 pub struct AudioMixer {
     streams: Vec<AudioStream>,
+    stream_ids: Vec<usize>,
+    next_stream_id: usize,
+    // Streams fading out, to be removed once they're silent
+    removing_streams: Vec<usize>,
     active_streams: usize,
     stream_finished: Vec<bool>,
     mixed_buffer: Vec<f32>,
@@ -18,22 +34,24 @@ pub struct AudioMixer {
     last_sync_check: i64,
     sync_interval: i64,
     max_sync_drift: f64, // Maximum observed drift between any two streams
+    loop_region: Option<(i64, i64)>, // Loop start and end, in samples
+    master_gain: f32,
+    limiter: TruePeakLimiter,
+    // Whether the limiter's delay line still holds audio that hasn't been output
+    limiter_tail_pending: bool,
 }
 
 impl AudioMixer {
-    pub async fn new(files: Vec<File>, start_timestamp: f64) -> Result<Self, JsValue> {
-        debug!("Creating mixer with {} streams", files.len());
-        let stream_count = files.len();
+    pub fn new(streams: Vec<AudioStream>, start_timestamp: f64) -> Self {
+        debug!("Creating mixer with {} streams", streams.len());
+        let stream_count = streams.len();
         let target_granule = (start_timestamp * SAMPLE_RATE as f64) as i64;
 
-        // Create streams asynchronously
-        let mut streams = Vec::with_capacity(files.len());
-        for file in files {
-            streams.push(AudioStream::new(file).await?);
-        }
-
-        Ok(Self {
+        Self {
             streams,
+            stream_ids: (0..stream_count).collect(),
+            next_stream_id: stream_count,
+            removing_streams: Vec::new(),
             active_streams: stream_count,
             stream_finished: vec![false; stream_count],
             mixed_buffer: vec![0f32; FRAME_SIZE * CHANNELS as usize],
@@ -42,7 +60,11 @@ impl AudioMixer {
             last_sync_check: target_granule,
             sync_interval: SAMPLE_RATE as i64,
             max_sync_drift: 0.0,
-        })
+            loop_region: None,
+            master_gain: libm::powf(10.0, MASTER_HEADROOM_DB / 20.0),
+            limiter: TruePeakLimiter::new(),
+            limiter_tail_pending: false,
+        }
     }
 
     /// Check and adjust synchronization between streams
@@ -59,7 +81,7 @@ impl AudioMixer {
 
         for (idx, stream) in self.streams.iter().enumerate() {
             if !self.stream_finished[idx] {
-                let pos = stream.current_granule_position;
+                let pos = stream.position();
                 total_pos += pos;
                 active_count += 1;
                 min_pos = min_pos.min(pos);
@@ -78,18 +100,19 @@ impl AudioMixer {
             // Calculate and apply drift compensation
             for (idx, stream) in self.streams.iter_mut().enumerate() {
                 if !self.stream_finished[idx] {
-                    let drift = stream.current_granule_position as f64 - avg_pos as f64;
+                    let drift = stream.position() as f64 - avg_pos as f64;
                     let drift_seconds = drift / SAMPLE_RATE as f64;
 
                     // Update drift statistics
                     stream.drift_stats.update_drift(drift_seconds);
 
-                    // Adjust compensation based on drift
-                    let max_adjustment = 0.02 * (self.target_granule - self.last_sync_check) as f64
-                        / SAMPLE_RATE as f64;
+                    // Pick a playback rate that cancels the drift over the next sync interval
+                    let interval_seconds =
+                        (self.target_granule - self.last_sync_check) as f64 / SAMPLE_RATE as f64;
 
                     if drift_seconds.abs() > 0.001 {
-                        let adjustment = (drift_seconds.abs() / 1.0).min(max_adjustment);
+                        let adjustment =
+                            (drift_seconds.abs() / interval_seconds).min(MAX_RATE_ADJUSTMENT);
                         stream.drift_compensation = if drift > 0.0 {
                             1.0 - adjustment as f32
                         } else {
@@ -99,12 +122,18 @@ impl AudioMixer {
                             .drift_stats
                             .update_compensation(stream.drift_compensation);
                         debug!(
-                            "Stream {} drift: {:.3}s, compensation: {:.3}x",
-                            idx, drift_seconds, stream.drift_compensation
+                            "Stream {} drift: {:.3}s, playback rate: {:.4}x",
+                            self.stream_ids[idx], drift_seconds, stream.drift_compensation
                         );
                     } else {
                         stream.drift_compensation = 1.0;
                     }
+
+                    stream.drift_stats.record_measurement(DriftMeasurement {
+                        time: self.target_granule as f64 / SAMPLE_RATE as f64,
+                        drift_seconds,
+                        playback_rate: stream.drift_compensation,
+                    });
                 }
             }
         }
@@ -112,31 +141,23 @@ impl AudioMixer {
         self.last_sync_check = self.target_granule;
     }
 
-    /// Seek to the desired timestamp in all streams using bisection search
-    pub fn seek_to_timestamp(&mut self) -> Result<(), JsValue> {
-        debug!(
-            "Seeking all streams to timestamp: {:.2}s",
-            self.start_timestamp
-        );
+    /// Seek all streams to `timestamp` seconds, returning the position playback resumes from
+    pub fn seek_to_timestamp(&mut self, timestamp: f64) -> Result<f64, MixerError> {
+        // Streams still loading can only seek as far as they've been received
+        let timestamp = self
+            .streams
+            .iter()
+            .filter(|stream| stream.is_loading())
+            .map(|stream| stream.duration())
+            .fold(timestamp, f64::min)
+            .max(0.0);
+        debug!("Seeking all streams to timestamp: {:.2}s", timestamp);
 
         // Seek each stream to the target timestamp
         for (stream_idx, stream) in self.streams.iter_mut().enumerate() {
             debug!("Seeking stream {}", stream_idx);
-            stream.seek_to_timestamp(self.start_timestamp)?;
-
-            // Process headers after seeking
-            while !stream.header_processed || !stream.comments_processed {
-                match stream.process_next_packet()? {
-                    Some(_) => {
-                        debug!("Processed post-seek headers for stream {}", stream_idx);
-                    }
-                    None => {
-                        if stream.header_processed && stream.comments_processed {
-                            break;
-                        }
-                    }
-                }
-            }
+            stream.seek_to_timestamp(timestamp)?;
+            stream.drift_compensation = 1.0;
 
             debug!(
                 "Stream {} ready at timestamp {:.2}s",
@@ -145,141 +166,836 @@ impl AudioMixer {
             );
         }
 
-        Ok(())
+        // Every stream is playable again, even those that had already finished
+        self.stream_finished = vec![false; self.streams.len()];
+        self.active_streams = self.streams.len();
+        self.target_granule = (timestamp * SAMPLE_RATE as f64) as i64;
+        self.last_sync_check = self.target_granule;
+
+        // Drop the audio from before the seek that's still in the limiter
+        self.limiter.reset();
+        self.limiter_tail_pending = false;
+
+        Ok(self.target_granule as f64 / SAMPLE_RATE as f64)
+    }
+
+    /// Seek all streams back to the timestamp the mixer was created with
+    pub fn reset(&mut self) -> Result<f64, MixerError> {
+        self.seek_to_timestamp(self.start_timestamp)
     }
 
     /// Mix the next batch of samples from all active streams
-    pub fn mix_next_samples(&mut self) -> Result<Option<&[f32]>, JsValue> {
+    pub fn mix_next_samples(&mut self) -> Result<Option<&[f32]>, MixerError> {
+        self.drop_removed_streams();
+
         if self.active_streams == 0 {
+            // Push silence through the limiter once to play out the end of its delay line
+            if self.limiter_tail_pending {
+                self.limiter_tail_pending = false;
+                self.mixed_buffer.fill(0.0);
+                self.limiter.process(&mut self.mixed_buffer);
+                return Ok(Some(&self.mixed_buffer));
+            }
+
             debug!("No active streams remaining");
             return Ok(None);
         }
 
+        // Wait for streams that are still loading to receive more of their files, rather than
+        // letting the others play on without them
+        if self.is_waiting_for_data()? {
+            debug!("Waiting for more data to arrive");
+            return Ok(None);
+        }
+
         self.mixed_buffer.fill(0.0);
-        let mut samples_mixed = false;
 
         // Check and adjust synchronization
         self.check_sync();
 
-        // Find the most behind stream that's not finished
-        let min_granule = self
-            .streams
-            .iter()
-            .enumerate()
-            .filter(|(idx, _)| !self.stream_finished[*idx])
-            .map(|(_, stream)| stream.current_granule_position)
-            .min()
-            .unwrap_or(self.target_granule);
+        // Soloing any stream silences every stream that isn't soloed
+        let any_solo = self.streams.iter().any(|stream| stream.controls.is_solo());
 
-        // Update target granule
-        self.target_granule = min_granule;
+        // If this frame crosses the loop end, mix up to it, then carry on from the loop start
+        let frames_before_wrap = match self.loop_region {
+            Some((_, loop_end))
+                if self.target_granule < loop_end
+                    && loop_end - self.target_granule <= FRAME_SIZE as i64 =>
+            {
+                Some((loop_end - self.target_granule) as usize)
+            }
+            _ => None,
+        };
+
+        let samples_mixed = match frames_before_wrap {
+            Some(frames) => {
+                let mixed = self.mix_streams(0, frames, any_solo)?;
+                self.wrap_to_loop_start()?;
+                let mixed_after_wrap = self.mix_streams(frames, FRAME_SIZE - frames, any_solo)?;
+                self.target_granule += (FRAME_SIZE - frames) as i64;
+                mixed || mixed_after_wrap
+            }
+            None => {
+                let mixed = self.mix_streams(0, FRAME_SIZE, any_solo)?;
+                if mixed {
+                    self.target_granule += FRAME_SIZE as i64;
+                }
+                mixed
+            }
+        };
+
+        // Keep the loop start decoded ahead, one stream per frame to spread out the work
+        if let Some((loop_start, _)) = self.loop_region {
+            if let Some(stream) = self
+                .streams
+                .iter_mut()
+                .find(|stream| stream.needs_loop_preparation(loop_start))
+            {
+                stream.prepare_loop(loop_start)?;
+            }
+        }
 
-        // Process each stream
+        if samples_mixed {
+            // Master bus: fixed headroom, then limit to the true peak ceiling
+            let master_gain = self.master_gain;
+            for sample in &mut self.mixed_buffer {
+                *sample *= master_gain;
+            }
+            self.limiter_tail_pending = true;
+        } else if self.limiter_tail_pending {
+            // The streams just ran out; the buffer is silent, so use it to flush the limiter
+            self.limiter_tail_pending = false;
+        } else {
+            return Ok(None);
+        }
+
+        self.limiter.process(&mut self.mixed_buffer);
+        Ok(Some(&self.mixed_buffer))
+    }
+
+    /// Add `stream` to the mix with its first frame at `offset` seconds on the timeline, and
+    /// return its id. It joins at the current position, in sync with the other streams, and
+    /// fades in if that's part way through its audio.
+    pub fn add_stream(
+        &mut self,
+        mut stream: AudioStream,
+        offset: f64,
+    ) -> Result<usize, MixerError> {
+        if !offset.is_finite() {
+            return Err(MixerError::new("Stream offsets must be finite numbers"));
+        }
+
+        let offset = (offset * SAMPLE_RATE as f64).round() as i64;
+        place_stream(&mut stream, offset, self.target_granule)?;
+
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        debug!(
+            "Adding stream {} at offset {} samples",
+            stream_id,
+            stream.offset()
+        );
+
+        self.streams.push(stream);
+        self.stream_ids.push(stream_id);
+        self.stream_finished.push(false);
+        self.active_streams += 1;
+
+        Ok(stream_id)
+    }
+
+    /// Remove a stream from the mix. With `fade`, it fades out over the next few frames first
+    /// rather than cutting off mid-sound, which is what's wanted while playing.
+    pub fn remove_stream(&mut self, stream_id: usize, fade: bool) -> Result<(), MixerError> {
+        let stream_idx = self.stream_index(stream_id)?;
+        if fade && !self.stream_finished[stream_idx] {
+            debug!("Fading out stream {} to remove it", stream_id);
+            let controls = &mut self.streams[stream_idx].controls;
+            controls.set_muted(true);
+            controls.set_solo(false);
+            if !self.removing_streams.contains(&stream_id) {
+                self.removing_streams.push(stream_id);
+            }
+        } else {
+            self.remove_stream_at(stream_idx);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the streams that have finished fading out
+    fn drop_removed_streams(&mut self) {
+        let mut idx = 0;
+        while idx < self.streams.len() {
+            let stream_id = self.stream_ids[idx];
+            let faded = self.stream_finished[idx] || self.streams[idx].controls.is_silent();
+            if faded && self.removing_streams.contains(&stream_id) {
+                self.remove_stream_at(idx);
+            } else {
+                idx += 1;
+            }
+        }
+    }
+
+    fn remove_stream_at(&mut self, stream_idx: usize) {
+        let stream_id = self.stream_ids.remove(stream_idx);
+        debug!("Removing stream {}", stream_id);
+        self.streams.remove(stream_idx);
+        self.removing_streams.retain(|&id| id != stream_id);
+        if !self.stream_finished.remove(stream_idx) {
+            self.active_streams -= 1;
+        }
+    }
+
+    /// Whether any active stream can't provide the next frame until more of its file arrives
+    fn is_waiting_for_data(&mut self) -> Result<bool, MixerError> {
         for (stream_idx, stream) in self.streams.iter_mut().enumerate() {
-            if self.stream_finished[stream_idx] {
-                continue;
+            if !self.stream_finished[stream_idx] && !stream.can_read(FRAME_SIZE)? {
+                return Ok(true);
             }
+        }
+
+        Ok(false)
+    }
 
-            // Check if this stream is ahead
-            if stream.current_granule_position > self.target_granule + FRAME_SIZE as i64 {
-                debug!(
-                    "Stream {} is ahead (granule: {}, target: {}), skipping",
-                    stream_idx, stream.current_granule_position, self.target_granule
-                );
+    /// Mix `frames` frames from each active stream into the mix buffer, starting `offset`
+    /// frames in. Returns whether any stream provided samples.
+    fn mix_streams(
+        &mut self,
+        offset: usize,
+        frames: usize,
+        any_solo: bool,
+    ) -> Result<bool, MixerError> {
+        if frames == 0 {
+            return Ok(false);
+        }
+
+        let mut samples_mixed = false;
+        let mixed_buffer = &mut self.mixed_buffer[offset * CHANNELS as usize..];
+
+        // Process each stream
+        for (stream_idx, stream) in self.streams.iter_mut().enumerate() {
+            if self.stream_finished[stream_idx] {
                 continue;
             }
 
             debug!(
                 "Processing stream {} at granule {}",
-                stream_idx, stream.current_granule_position
+                stream_idx,
+                stream.position()
             );
-            match stream.process_next_packet()? {
-                Some(decoded_samples) => {
-                    debug!("Stream {} provided {} samples", stream_idx, decoded_samples);
-
-                    let stream_channels = stream.get_channel_count();
-                    let compensation = stream.drift_compensation;
-                    let stream_samples = stream.get_decoded_samples();
-
-                    // Mix samples based on input channel configuration
-                    if stream_channels == 2 {
-                        // Stereo input - direct mix to stereo output
-                        let sample_count = decoded_samples * 2; // 2 channels
-                        for i in 0..sample_count {
-                            self.mixed_buffer[i] +=
-                                stream_samples[i] * compensation / self.active_streams as f32;
-                        }
-                    } else if stream_channels == 1 {
-                        // Mono input - upmix to stereo output
-                        for i in 0..decoded_samples {
-                            let mono_sample =
-                                stream_samples[i] * compensation / self.active_streams as f32;
-                            // Duplicate mono sample to both left and right channels
-                            self.mixed_buffer[i * 2] += mono_sample; // Left
-                            self.mixed_buffer[i * 2 + 1] += mono_sample; // Right
-                        }
-                    } else {
-                        debug!("Unsupported channel count: {}, cannot mix", stream_channels);
+            let produced = stream.read_frames(frames)?;
+
+            if produced > 0 {
+                debug!("Stream {} provided {} samples", stream_idx, produced);
+
+                let stream_channels = stream.output_channels();
+                let stream_samples = &stream.output;
+                let controls = &mut stream.controls;
+                controls.update_targets(any_solo);
+
+                // Mix samples based on input channel configuration
+                if stream_channels == 2 {
+                    // Stereo input - pan acts as a balance control on each channel
+                    for i in 0..produced {
+                        let (left_gain, right_gain) = controls.next_gains();
+                        mixed_buffer[i * 2] += stream_samples[i * 2] * left_gain;
+                        mixed_buffer[i * 2 + 1] += stream_samples[i * 2 + 1] * right_gain;
                     }
-
-                    samples_mixed = true;
-                }
-                None => {
-                    // Only mark the stream as finished if we've reached the end of the stream
-                    // AND we've already processed both headers
-                    if stream.header_processed && stream.comments_processed {
-                        match stream
-                            .packet_reader
-                            .read_packet()
-                            .map_err(|e| JsValue::from_str(&format!("Ogg read error: {}", e)))?
-                        {
-                            Some(_) => {
-                                debug!("Stream {} waiting for more packets", stream_idx);
-                            }
-                            None => {
-                                debug!("Stream {} reached end of file", stream_idx);
-                                self.stream_finished[stream_idx] = true;
-                                self.active_streams -= 1;
-                                debug!("Active streams remaining: {}", self.active_streams);
-                            }
-                        }
-                    } else {
-                        debug!(
-                            "Stream {} returned no samples (headers: {}/{}, decoder: {})",
-                            stream_idx,
-                            stream.header_processed,
-                            stream.comments_processed,
-                            stream.decoder.is_some()
-                        );
+                } else if stream_channels == 1 {
+                    // Mono input - pan into the stereo output
+                    for i in 0..produced {
+                        let (left_gain, right_gain) = controls.next_gains();
+                        let mono_sample = stream_samples[i];
+                        mixed_buffer[i * 2] += mono_sample * left_gain;
+                        mixed_buffer[i * 2 + 1] += mono_sample * right_gain;
                     }
+                } else {
+                    debug!("Unsupported channel count: {}, cannot mix", stream_channels);
                 }
+
+                samples_mixed = true;
+            }
+
+            // A stream that can't provide every frame asked for has run out of packets
+            if produced < frames && stream.end_of_stream {
+                debug!("Stream {} reached end of file", stream_idx);
+                self.stream_finished[stream_idx] = true;
+                self.active_streams -= 1;
+                debug!("Active streams remaining: {}", self.active_streams);
             }
         }
 
-        // Update target granule position
-        if samples_mixed {
-            self.target_granule += FRAME_SIZE as i64;
+        Ok(samples_mixed)
+    }
+
+    /// Move every stream back to the loop start, using the audio they've decoded ahead there
+    fn wrap_to_loop_start(&mut self) -> Result<(), MixerError> {
+        let loop_start = match self.loop_region {
+            Some((loop_start, _)) => loop_start,
+            None => return Ok(()),
+        };
+        debug!("Wrapping playback to loop start at granule {}", loop_start);
+
+        for stream in &mut self.streams {
+            stream.wrap_to_loop(loop_start)?;
+            stream.drift_compensation = 1.0;
         }
 
-        if samples_mixed {
-            Ok(Some(&self.mixed_buffer))
-        } else {
-            Ok(None)
+        // Every stream is playable again, even those that had already finished
+        self.stream_finished = vec![false; self.streams.len()];
+        self.active_streams = self.streams.len();
+        self.target_granule = loop_start;
+        self.last_sync_check = loop_start;
+
+        Ok(())
+    }
+
+    /// Loop playback between `start` and `end` seconds. Playback wraps from the end to the
+    /// start without a gap once it reaches the end; if it's already past the end it carries
+    /// on to the end of the files.
+    pub fn set_loop(&mut self, start: f64, end: f64) -> Result<(f64, f64), MixerError> {
+        if !start.is_finite() || !end.is_finite() {
+            return Err(MixerError::new("Loop points must be finite numbers"));
         }
+
+        let duration = (self.duration() * SAMPLE_RATE as f64).round() as i64;
+        let loop_start = ((start * SAMPLE_RATE as f64).round() as i64).max(0);
+        let loop_end = ((end * SAMPLE_RATE as f64).round() as i64).min(duration);
+
+        // Each frame can wrap at most once, so the region must be at least a frame long
+        if loop_end - loop_start < FRAME_SIZE as i64 {
+            return Err(MixerError::new(format!(
+                "Loop region {:.3}s - {:.3}s is too short, it must be at least {}ms long",
+                start,
+                end,
+                FRAME_SIZE as u32 * 1000 / SAMPLE_RATE
+            )));
+        }
+
+        debug!("Setting loop region: {} - {}", loop_start, loop_end);
+        self.loop_region = Some((loop_start, loop_end));
+
+        Ok((
+            loop_start as f64 / SAMPLE_RATE as f64,
+            loop_end as f64 / SAMPLE_RATE as f64,
+        ))
+    }
+
+    /// Stop looping; playback carries on to the end of the files
+    pub fn clear_loop(&mut self) {
+        debug!("Clearing loop region");
+        self.loop_region = None;
+        for stream in &mut self.streams {
+            stream.clear_loop();
+        }
+    }
+
+    /// Render the mix from `start` to `end` seconds as fast as the streams can be decoded, with
+    /// the current gain, pan, mute and solo settings, as interleaved stereo at 48kHz. The loop
+    /// region is ignored, and the mixer is left positioned at the end of the render.
+    pub fn render(&mut self, start: f64, end: f64) -> Result<Vec<f32>, MixerError> {
+        if !start.is_finite() || !end.is_finite() {
+            return Err(MixerError::new("Render points must be finite numbers"));
+        }
+
+        if self.is_loading() {
+            return Err(MixerError::new(
+                "Can't render until every file has finished loading",
+            ));
+        }
+
+        let start = self.seek_to_timestamp(start)?;
+        let end = end.min(self.duration());
+        if end <= start {
+            return Err(MixerError::new(format!(
+                "Render region {:.3}s - {:.3}s is empty",
+                start, end
+            )));
+        }
+        debug!("Rendering mix from {:.3}s to {:.3}s", start, end);
+
+        let frames = ((end - start) * SAMPLE_RATE as f64).round() as usize;
+        let loop_region = self.loop_region.take();
+        let rendered = self.render_samples(frames * CHANNELS as usize);
+        self.loop_region = loop_region;
+        rendered
+    }
+
+    /// Mix `len` interleaved samples from the current position, discarding the silence the
+    /// limiter's delay line puts in front of them
+    fn render_samples(&mut self, len: usize) -> Result<Vec<f32>, MixerError> {
+        let mut skip = self.limiter.latency_frames() * CHANNELS as usize;
+        let mut output = Vec::with_capacity(len);
+
+        while output.len() < len {
+            let samples = match self.mix_next_samples()? {
+                Some(samples) => samples,
+                None => break,
+            };
+            let skipped = skip.min(samples.len());
+            skip -= skipped;
+
+            let samples = &samples[skipped..];
+            let take = samples.len().min(len - output.len());
+            output.extend_from_slice(&samples[..take]);
+        }
+
+        // The streams can end a little before the longest one's duration after rounding
+        output.resize(len, 0.0);
+        Ok(output)
     }
 
     pub fn is_active(&self) -> bool {
         self.active_streams > 0
     }
 
-    /// Print detailed synchronization statistics
-    pub fn print_sync_stats(&self) {
-        println!("\nSynchronization Statistics:");
-        println!("Maximum Sync Drift: {:.3} ms", self.max_sync_drift * 1000.0);
+    /// Position of the next sample to come out of the mixer, in seconds. The limiter delays
+    /// the mix, so this is behind the next sample to be mixed.
+    pub fn position(&self) -> f64 {
+        (self.target_granule - self.limiter.latency_frames() as i64) as f64 / SAMPLE_RATE as f64
+    }
 
-        for (idx, stream) in self.streams.iter().enumerate() {
-            println!("\nStream {} Statistics:", idx);
-            stream.drift_stats.print_stats();
+    /// Length of the longest stream, in seconds
+    pub fn duration(&self) -> f64 {
+        self.streams
+            .iter()
+            .map(|stream| stream.duration())
+            .fold(0.0, f64::max)
+    }
+
+    /// Whether any stream's file is still arriving
+    pub fn is_loading(&self) -> bool {
+        self.streams.iter().any(|stream| stream.is_loading())
+    }
+
+    /// Whether playback is held up waiting for more of a stream's file to arrive
+    pub fn is_buffering(&self) -> bool {
+        self.streams
+            .iter()
+            .any(|stream| stream.is_waiting_for_data())
+    }
+
+    /// Add the next part of the file for a stream opened with `AudioStream::from_partial_bytes`
+    pub fn append_stream_bytes(&mut self, stream_id: usize, data: &[u8]) -> Result<(), MixerError> {
+        self.stream_mut(stream_id)?.append_bytes(data)
+    }
+
+    /// Mark a stream's file as complete, once all of it has been added
+    pub fn finish_stream_loading(&mut self, stream_id: usize) -> Result<(), MixerError> {
+        debug!("Stream {} finished loading", stream_id);
+        self.stream_mut(stream_id)?.finish_loading()
+    }
+
+    /// Number of loaded streams
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Ids of the loaded streams, in the order they were added
+    pub fn stream_ids(&self) -> &[usize] {
+        &self.stream_ids
+    }
+
+    /// Metadata from a stream's OpusTags header, if it had any
+    pub fn stream_tags(&self, stream_id: usize) -> Result<Option<&OpusTags>, MixerError> {
+        Ok(self.streams[self.stream_index(stream_id)?].tags())
+    }
+
+    fn stream_index(&self, stream_id: usize) -> Result<usize, MixerError> {
+        self.stream_ids
+            .iter()
+            .position(|&id| id == stream_id)
+            .ok_or_else(|| {
+                MixerError::new(format!(
+                    "Invalid stream id {} ({} streams loaded)",
+                    stream_id,
+                    self.streams.len()
+                ))
+            })
+    }
+
+    fn stream_mut(&mut self, stream_id: usize) -> Result<&mut AudioStream, MixerError> {
+        let stream_idx = self.stream_index(stream_id)?;
+        Ok(&mut self.streams[stream_idx])
+    }
+
+    /// Set the gain of a stream in decibels
+    pub fn set_stream_gain(&mut self, stream_id: usize, gain_db: f32) -> Result<(), MixerError> {
+        debug!("Setting stream {} gain to {:.1} dB", stream_id, gain_db);
+        self.stream_mut(stream_id)?.controls.set_gain_db(gain_db);
+        Ok(())
+    }
+
+    /// Set the pan position of a stream, from -1.0 (left) to 1.0 (right)
+    pub fn set_stream_pan(&mut self, stream_id: usize, pan: f32) -> Result<(), MixerError> {
+        debug!("Setting stream {} pan to {:.2}", stream_id, pan);
+        self.stream_mut(stream_id)?.controls.set_pan(pan);
+        Ok(())
+    }
+
+    /// Mute or unmute a stream
+    pub fn set_stream_mute(&mut self, stream_id: usize, muted: bool) -> Result<(), MixerError> {
+        debug!("Setting stream {} muted: {}", stream_id, muted);
+        self.stream_mut(stream_id)?.controls.set_muted(muted);
+        Ok(())
+    }
+
+    /// Solo or unsolo a stream. While any stream is soloed, only soloed streams are heard.
+    pub fn set_stream_solo(&mut self, stream_id: usize, solo: bool) -> Result<(), MixerError> {
+        debug!("Setting stream {} solo: {}", stream_id, solo);
+        self.stream_mut(stream_id)?.controls.set_solo(solo);
+        Ok(())
+    }
+
+    /// Move a stream along the timeline so its first frame plays `offset` samples in, with
+    /// silence before it, or so the start of its audio is skipped if `offset` is negative. It
+    /// carries on in sync with the other streams from the current position.
+    pub fn set_stream_offset(&mut self, stream_id: usize, offset: i64) -> Result<(), MixerError> {
+        debug!("Setting stream {} offset to {} samples", stream_id, offset);
+        let stream_idx = self.stream_index(stream_id)?;
+        place_stream(&mut self.streams[stream_idx], offset, self.target_granule)?;
+
+        // Moving a stream that had finished can bring it back into play
+        if self.stream_finished[stream_idx] {
+            self.stream_finished[stream_idx] = false;
+            self.active_streams += 1;
         }
+
+        Ok(())
+    }
+
+    /// Timeline position a stream's first frame plays at, in samples
+    pub fn stream_offset(&self, stream_id: usize) -> Result<i64, MixerError> {
+        Ok(self.streams[self.stream_index(stream_id)?].offset())
+    }
+
+    /// Choose whether a stream is folded down to stereo or has two of its raw channels routed
+    /// to the left and right outputs
+    pub fn set_stream_channel_mode(
+        &mut self,
+        stream_id: usize,
+        mode: ChannelMode,
+    ) -> Result<(), MixerError> {
+        debug!("Setting stream {} channel mode: {:?}", stream_id, mode);
+        self.stream_mut(stream_id)?.set_channel_mode(mode)
+    }
+
+    /// Synchronization statistics for the mix and each of its streams
+    pub fn sync_stats(&self) -> SyncStats {
+        SyncStats {
+            max_drift_seconds: self.max_sync_drift,
+            streams: self
+                .stream_ids
+                .iter()
+                .zip(&self.streams)
+                .map(|(&stream_id, stream)| {
+                    StreamSyncStats::new(stream_id, &stream.drift_stats, stream.drift_compensation)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Give `stream` timeline offset `offset` and line it up with the mix at `position` (both in
+/// samples). It fades in if that's part way through its audio, rather than clicking.
+fn place_stream(stream: &mut AudioStream, offset: i64, position: i64) -> Result<(), MixerError> {
+    stream.set_offset(offset);
+    stream.seek_to_position(position)?;
+    stream.drift_compensation = 1.0;
+    if position > offset {
+        stream.controls.fade_in();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::test_utils::{encode_ogg_opus, sine};
+    use crate::opus_mixer::wav_writer::{encode_wav, WavFormat};
+
+    fn mixer(lengths: &[f64]) -> AudioMixer {
+        let streams = lengths
+            .iter()
+            .map(|&seconds| {
+                let data = encode_ogg_opus(&sine(440.0, seconds, 2), 2);
+                AudioStream::from_bytes(data).unwrap()
+            })
+            .collect();
+        AudioMixer::new(streams, 0.0)
+    }
+
+    /// Mix until the mixer runs out, returning the number of frames produced
+    fn mix_to_end(mixer: &mut AudioMixer) -> usize {
+        let mut frames = 0;
+        while let Some(samples) = mixer.mix_next_samples().unwrap() {
+            frames += samples.len() / CHANNELS as usize;
+        }
+        frames
+    }
+
+    #[test]
+    fn plays_until_the_longest_stream_ends() {
+        let mut mixer = mixer(&[1.0, 0.5]);
+        assert_eq!(mixer.duration(), 1.0);
+
+        // The limiter's delay line is flushed with one extra frame once the streams end
+        assert_eq!(mix_to_end(&mut mixer), 48000 + FRAME_SIZE);
+        assert!(!mixer.is_active());
+    }
+
+    #[test]
+    fn waits_for_streams_that_are_still_loading() {
+        let data = encode_ogg_opus(&sine(440.0, 2.0, 2), 2);
+        // Most of the file, so the first page of audio has arrived in full
+        let (received, rest) = data.split_at(data.len() * 3 / 4);
+        let streams = vec![
+            AudioStream::from_bytes(data.clone()).unwrap(),
+            AudioStream::from_partial_bytes(received.to_vec()).unwrap(),
+        ];
+        let mut mixer = AudioMixer::new(streams, 0.0);
+        assert!(mixer.is_loading());
+        assert!(mixer.render(0.0, 0.5).is_err());
+
+        // Seeking only reaches as far as the loading stream has been received
+        let buffered = mixer.streams[1].duration();
+        assert!(buffered > 0.5 && buffered < 1.5);
+        let position = mixer.seek_to_timestamp(1.9).unwrap();
+        assert!(position <= buffered && position > buffered - 0.001);
+
+        // Playback stops, without ending, until the rest arrives
+        mixer.seek_to_timestamp(0.0).unwrap();
+        let mut frames = mix_to_end(&mut mixer);
+        assert!(frames < 96000);
+        assert!(mixer.is_buffering());
+        assert!(mixer.is_active());
+
+        mixer.append_stream_bytes(1, rest).unwrap();
+        mixer.finish_stream_loading(1).unwrap();
+        assert!(!mixer.is_loading());
+        assert_eq!(mixer.duration(), 2.0);
+
+        frames += mix_to_end(&mut mixer);
+        assert_eq!(frames, 96000 + FRAME_SIZE);
+    }
+
+    #[test]
+    fn sync_stats_cover_every_stream() {
+        let mut mixer = mixer(&[2.0, 2.0]);
+        mix_to_end(&mut mixer);
+
+        let stats = mixer.sync_stats();
+        assert_eq!(stats.streams.len(), 2);
+        assert!(stats.max_drift_seconds < 0.001);
+        for (stream_id, stream) in stats.streams.iter().enumerate() {
+            assert_eq!(stream.stream_id, stream_id);
+            assert!(stream.decoded_frames >= 96000);
+            assert_eq!(stream.resampled_frames, 96000);
+            assert_eq!(stream.drift_measurements, stream.history.len());
+            assert!(!stream.history.is_empty());
+            assert_eq!(stream.playback_rate, 1.0);
+        }
+    }
+
+    #[test]
+    fn streams_can_be_added_and_removed_while_playing() {
+        let mut mixer = mixer(&[2.0]);
+        for _ in 0..25 {
+            mixer.mix_next_samples().unwrap();
+        }
+
+        // The new stream joins in sync, part way through its audio
+        let data = encode_ogg_opus(&sine(220.0, 1.0, 2), 2);
+        let stream_id = mixer
+            .add_stream(AudioStream::from_bytes(data).unwrap(), 0.2)
+            .unwrap();
+        assert_eq!(stream_id, 1);
+        assert_eq!(mixer.streams[1].position(), mixer.target_granule);
+        assert_eq!(mixer.duration(), 2.0);
+
+        // The removed stream fades out over a few frames before it's dropped
+        mixer.remove_stream(0, true).unwrap();
+        assert_eq!(mixer.stream_count(), 2);
+        for _ in 0..10 {
+            mixer.mix_next_samples().unwrap();
+        }
+        assert_eq!(mixer.stream_ids(), &[1]);
+        assert!(mixer.set_stream_gain(0, -6.0).is_err());
+        mixer.set_stream_gain(1, -6.0).unwrap();
+
+        // Plays to the end of the added stream at 1.2s, 0.7s in
+        assert_eq!(mix_to_end(&mut mixer), 24000 + FRAME_SIZE);
+        assert!(!mixer.is_active());
+    }
+
+    #[test]
+    fn added_streams_start_at_their_offset() {
+        let mut mixer = AudioMixer::new(Vec::new(), 0.0);
+        let data = encode_ogg_opus(&sine(440.0, 1.0, 2), 2);
+        mixer
+            .add_stream(AudioStream::from_bytes(data.clone()).unwrap(), 0.5)
+            .unwrap();
+        assert_eq!(mixer.duration(), 1.5);
+
+        // Silence until the offset, then the stream
+        let samples = mixer.render(0.0, 1.5).unwrap();
+        let offset = 24000 * CHANNELS as usize;
+        assert!(samples[..offset].iter().all(|&sample| sample == 0.0));
+        assert!(samples[offset..offset + 100]
+            .iter()
+            .any(|&sample| sample != 0.0));
+
+        // Seeking keeps the stream lined up with the timeline
+        mixer.seek_to_timestamp(0.3).unwrap();
+        assert_eq!(mixer.streams[0].position(), 14400);
+        assert_eq!(mix_to_end(&mut mixer), 57600 + FRAME_SIZE);
+
+        // A negative offset skips the start of the stream
+        mixer.remove_stream(0, false).unwrap();
+        mixer
+            .add_stream(AudioStream::from_bytes(data).unwrap(), -0.5)
+            .unwrap();
+        assert_eq!(mixer.duration(), 0.5);
+        mixer.seek_to_timestamp(0.0).unwrap();
+        assert_eq!(mix_to_end(&mut mixer), 24000 + FRAME_SIZE);
+    }
+
+    #[test]
+    fn mixes_wav_and_opus_stems_together() {
+        let opus = encode_ogg_opus(&sine(440.0, 1.0, 2), 2);
+        let wav: Vec<f32> = (0..44100).map(|i| (i as f32 / 50.0).sin() * 0.5).collect();
+        let wav = encode_wav(&wav, 1, 44100, WavFormat::Pcm24);
+        let streams = vec![
+            AudioStream::from_bytes(opus).unwrap(),
+            AudioStream::from_bytes(wav).unwrap(),
+        ];
+        let mut mixer = AudioMixer::new(streams, 0.0);
+        assert_eq!(mixer.duration(), 1.0);
+
+        mixer.seek_to_timestamp(0.5).unwrap();
+        for stream in &mixer.streams {
+            assert_eq!(stream.position(), 24000);
+        }
+        assert_eq!(mix_to_end(&mut mixer), 24000 + FRAME_SIZE);
+    }
+
+    #[test]
+    fn output_stays_below_the_limiter_ceiling() {
+        // Four full-scale streams would clip without the master bus
+        let mut mixer = mixer(&[0.5, 0.5, 0.5, 0.5]);
+        while let Some(samples) = mixer.mix_next_samples().unwrap() {
+            assert!(samples.iter().all(|sample| sample.abs() <= 0.9));
+        }
+    }
+
+    #[test]
+    fn seeking_moves_every_stream() {
+        let mut mixer = mixer(&[2.0, 2.0]);
+        mixer.mix_next_samples().unwrap();
+
+        assert_eq!(mixer.seek_to_timestamp(1.5).unwrap(), 1.5);
+        for stream in &mixer.streams {
+            assert_eq!(stream.position(), 72000);
+        }
+
+        // Everything from the seek position on is still played
+        assert_eq!(mix_to_end(&mut mixer), 24000 + FRAME_SIZE);
+    }
+
+    #[test]
+    fn drifting_streams_are_pulled_back_into_sync() {
+        let mut mixer = mixer(&[6.0, 6.0]);
+
+        // Put the second stream 10ms ahead of the first
+        mixer.streams[1].seek_to_timestamp(0.01).unwrap();
+
+        let gap = |mixer: &AudioMixer| mixer.streams[1].position() - mixer.streams[0].position();
+        assert_eq!(gap(&mixer), 480);
+
+        // The first sync check happens after a second; the 0.5% rate change then takes 2
+        // seconds to cancel 10ms of drift
+        for _ in 0..(4 * SAMPLE_RATE as usize / FRAME_SIZE) {
+            mixer.mix_next_samples().unwrap();
+        }
+
+        assert!(mixer.max_sync_drift >= 0.01);
+        assert!(gap(&mixer).abs() <= 48, "gap is {} samples", gap(&mixer));
+    }
+
+    #[test]
+    fn loop_region_repeats() {
+        let mut mixer = mixer(&[2.0, 1.0]);
+        assert_eq!(mixer.set_loop(0.5, 0.75).unwrap(), (0.5, 0.75));
+
+        // Play past the end of the shorter stream, which the loop never reaches
+        for _ in 0..(3 * SAMPLE_RATE as usize / FRAME_SIZE) {
+            assert!(mixer.mix_next_samples().unwrap().is_some());
+        }
+
+        assert!((24000..36000).contains(&mixer.target_granule));
+        for stream in &mixer.streams {
+            assert_eq!(stream.position(), mixer.target_granule);
+        }
+    }
+
+    #[test]
+    fn offset_streams_stay_in_place_through_seeks_and_loops() {
+        let mut mixer = mixer(&[2.0, 1.0]);
+        mixer.set_stream_offset(1, 24000).unwrap();
+        assert_eq!(mixer.stream_offset(1).unwrap(), 24000);
+        assert_eq!(mixer.duration(), 2.0);
+
+        mixer.seek_to_timestamp(0.25).unwrap();
+        assert_eq!(mixer.streams[1].position(), 12000);
+
+        // The loop starts before the offset stream does and ends part way into it
+        mixer.set_loop(0.25, 0.75).unwrap();
+        for _ in 0..(3 * SAMPLE_RATE as usize / FRAME_SIZE) {
+            assert!(mixer.mix_next_samples().unwrap().is_some());
+            for stream in &mixer.streams {
+                assert_eq!(stream.position(), mixer.target_granule);
+            }
+        }
+
+        // Moving the stream later brings it back after it's finished
+        mixer.clear_loop();
+        mixer.seek_to_timestamp(1.9).unwrap();
+        mixer.mix_next_samples().unwrap();
+        assert!(mixer.stream_finished[1]);
+        mixer.set_stream_offset(1, 72000).unwrap();
+        assert!(!mixer.stream_finished[1]);
+        assert_eq!(mixer.duration(), 2.5);
+        assert_eq!(mixer.streams[1].position(), mixer.target_granule);
+    }
+
+    #[test]
+    fn render_covers_exactly_the_requested_region() {
+        let mut mixer = mixer(&[1.0, 0.5]);
+        mixer.set_loop(0.25, 0.5).unwrap();
+
+        let samples = mixer.render(0.25, 0.75).unwrap();
+        assert_eq!(samples.len(), 24000 * CHANNELS as usize);
+        assert!(samples.iter().any(|&sample| sample != 0.0));
+
+        // Renders past the end stop at the end of the longest stream, ignoring the loop
+        let samples = mixer.render(0.0, 5.0).unwrap();
+        assert_eq!(samples.len(), 48000 * CHANNELS as usize);
+        assert_eq!(mixer.loop_region, Some((12000, 24000)));
+
+        assert!(mixer.render(0.75, 0.25).is_err());
+    }
+
+    #[test]
+    fn render_applies_stream_controls() {
+        let mut mixer = mixer(&[1.0]);
+        mixer.set_stream_mute(0, true).unwrap();
+
+        // Allow time for the gain to fade out
+        let samples = mixer.render(0.0, 1.0).unwrap();
+        assert!(samples[4800 * CHANNELS as usize..]
+            .iter()
+            .all(|&sample| sample.abs() < 1e-4));
     }
 }
@@ -1,308 +1,891 @@
-use ogg::reading::PacketReader;
-use opus::{Channels, Decoder};
-use std::convert::TryInto;
+use std::collections::VecDeque;
 use std::fmt;
-use std::io::Cursor;
 use std::io::{Read, Seek, SeekFrom};
-use wasm_bindgen::JsValue;
-use web_sys::File;
+use std::iter;
+use std::mem;
 
-use crate::debug;
+use crate::opus_mixer::downmix::{ChannelMode, StereoMatrix};
 use crate::opus_mixer::drift_stats::DriftStats;
-use crate::opus_mixer::{is_opus_header, is_opus_tags, CHANNELS, FRAME_SIZE, SAMPLE_RATE};
-use crate::utils::read_file_to_array_buffer;
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::FileData;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::resampler::VariableResampler;
+use crate::opus_mixer::stream_controls::StreamControls;
+use crate::opus_mixer::stream_decoder::{open_decoder, StreamDecoder};
+use crate::opus_mixer::{FRAME_SIZE, SAMPLE_RATE};
+use crate::sample_rate_converter::{ResamplerQuality, SampleRateConverter};
+use crate::{debug, log_info};
+
+// Audio decoded ahead at the loop start, so wrapping around doesn't have to wait on a seek
+const LOOP_LOOKAHEAD_FRAMES: usize = FRAME_SIZE * 5; // 100ms
+
+/// Decoding state for one position in the file. A stream decodes from its own copy of these
+/// fields; a spare parked at the loop start is swapped in when playback wraps around.
+struct DecodeCursor {
+    decoder: Box<dyn StreamDecoder>,
+    converter: Option<SampleRateConverter>,
+    pending: VecDeque<f32>,
+    input_to_skip: usize,
+    samples_to_skip: usize,
+    end_of_stream: bool,
+    next_position: i64,
+    // Timeline position (in samples) the cursor has been prepared at, None if it's stale
+    loop_start: Option<i64>,
+}
+
+/// A file that's still arriving. It grows in the buffer the decoder reads from, but the decoder
+/// only sees the bytes received when it was opened, and is reopened as more arrive.
+#[derive(Debug)]
+struct IncomingFile {
+    file_data: FileData,
+    decoder_bytes: usize, // Bytes the decoder was opened with
+    // Whether the decoder has used up its bytes and is waiting on more
+    starved: bool,
+}
 
 // TODO: offload to separate web workers, ala https://github.com/rustwasm/wasm-bindgen/tree/main/examples/raytrace-parallel
 
-/// A single audio stream from an Opus file
+/// A single audio stream, decoded from any of the supported file formats and converted to
+/// 48kHz for mixing
 pub struct AudioStream {
-    pub(crate) packet_reader: PacketReader<Cursor<Vec<u8>>>,
-    pub(crate) decoder: Option<Decoder>,
-    pub(crate) header_processed: bool,
-    pub(crate) comments_processed: bool,
+    decoder: Box<dyn StreamDecoder>,
+    // Converts decoded audio to 48kHz, for files at any other sample rate
+    converter: Option<SampleRateConverter>,
     decoded_buffer: Vec<f32>,
-    total_samples_decoded: usize,
-    pub(crate) current_granule_position: i64,
+    // Decoded interleaved samples waiting to be resampled
+    pending: VecDeque<f32>,
+    resampler: VariableResampler,
+    // Interleaved samples produced by the last `read_frames` call, ready to mix
+    pub(crate) output: Vec<f32>,
+    // Sample frames to discard to land exactly on a seek target: decoded frames before sample
+    // rate conversion (pre-skip and pre-roll), then 48kHz frames after it
+    input_to_skip: usize,
+    samples_to_skip: usize,
+    pub(crate) end_of_stream: bool,
+    // Position (in samples) in the audio of the next frame to be added to `pending`
+    next_position: i64,
+    length: i64, // Length of the audio in samples at 48kHz
+    // Timeline position (in samples) the first frame of audio plays at, negative if the
+    // beginning of the audio is skipped
+    offset: i64,
+    // Frames of silence still to play before the audio starts
+    lead_in: usize,
     pub(crate) drift_compensation: f32,
     pub(crate) drift_stats: DriftStats,
     pub(crate) channel_count: u16, // Input channel count from the file header
+    channel_mode: ChannelMode,
+    // Folds decoded channels to stereo, if they can't be mixed as they are
+    stereo_matrix: Option<StereoMatrix>,
+    pub(crate) controls: StreamControls,
+    loop_cursor: Option<DecodeCursor>,
+    // The file as received so far, until all of it has arrived
+    incoming: Option<IncomingFile>,
 }
 
 impl fmt::Debug for AudioStream {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AudioStream")
-            .field("header_processed", &self.header_processed)
-            .field("comments_processed", &self.comments_processed)
-            .field("total_samples_decoded", &self.total_samples_decoded)
-            .field("current_granule_position", &self.current_granule_position)
+            .field("sample_rate", &self.decoder.sample_rate())
+            .field("pending_frames", &self.pending_frames())
+            .field("input_to_skip", &self.input_to_skip)
+            .field("samples_to_skip", &self.samples_to_skip)
+            .field("end_of_stream", &self.end_of_stream)
+            .field("next_position", &self.next_position)
+            .field("length", &self.length)
+            .field("offset", &self.offset)
+            .field("lead_in", &self.lead_in)
             .field("drift_compensation", &self.drift_compensation)
             .field("drift_stats", &self.drift_stats)
             .field("channel_count", &self.channel_count)
+            .field("channel_mode", &self.channel_mode)
+            .field("incoming", &self.incoming)
+            .field("tags", &self.tags())
+            .field("controls", &self.controls)
+            .field(
+                "loop_start",
+                &self
+                    .loop_cursor
+                    .as_ref()
+                    .and_then(|cursor| cursor.loop_start),
+            )
             .finish()
     }
 }
 
 impl AudioStream {
-    pub async fn new(file: File) -> Result<Self, JsValue> {
-        let array_buffer = read_file_to_array_buffer(file).await?;
-        let file_data = js_sys::Uint8Array::new(&array_buffer).to_vec();
-
-        Ok(Self {
-            packet_reader: PacketReader::new(Cursor::new(file_data)),
-            decoder: None,
-            header_processed: false,
-            comments_processed: false,
-            decoded_buffer: vec![0f32; FRAME_SIZE * CHANNELS as usize], // Initialize with stereo buffer size
-            total_samples_decoded: 0,
-            current_granule_position: 0,
+    /// Play the audio from `decoder`, starting at the beginning
+    pub fn new(decoder: Box<dyn StreamDecoder>) -> Result<Self, MixerError> {
+        let channel_count = decoder.channel_count();
+        let length = to_output_frames(decoder.length(), decoder.sample_rate());
+        let stereo_matrix = StereoMatrix::new(
+            ChannelMode::Downmix,
+            decoder.mapping_family(),
+            channel_count,
+        );
+        let output_channels = if stereo_matrix.is_some() {
+            2
+        } else {
+            channel_count
+        };
+
+        let mut stream = Self {
+            converter: create_converter(decoder.as_ref()),
+            decoder,
+            decoded_buffer: Vec::new(),
+            pending: VecDeque::new(),
+            resampler: VariableResampler::new(output_channels),
+            output: Vec::new(),
+            input_to_skip: 0,
+            samples_to_skip: 0,
+            end_of_stream: false,
+            next_position: 0,
+            length,
+            offset: 0,
+            lead_in: 0,
             drift_compensation: 1.0,
             drift_stats: DriftStats::new(),
-            channel_count: 1, // Default to mono, will be updated from header
-        })
+            channel_count: channel_count as u16,
+            channel_mode: ChannelMode::Downmix,
+            stereo_matrix,
+            controls: StreamControls::new(),
+            loop_cursor: None,
+            incoming: None,
+        };
+
+        // Seeking to the start skips any samples the format needs thrown away first
+        stream.seek_to_sample(0)?;
+        Ok(stream)
     }
 
-    pub fn current_timestamp(&self) -> f64 {
-        self.total_samples_decoded as f64 / SAMPLE_RATE as f64
+    /// Open an audio file held in memory, in any format `open_decoder` recognises
+    pub fn from_bytes(data: impl Into<FileData>) -> Result<Self, MixerError> {
+        Self::new(open_decoder(data.into())?)
+    }
+
+    /// Start playing a file that's still arriving, from the part of it received so far. Fails
+    /// if that doesn't include all the file's headers yet. The rest is added with
+    /// `append_bytes`, and `duration` grows as it arrives.
+    pub fn from_partial_bytes(data: Vec<u8>) -> Result<Self, MixerError> {
+        let file_data = FileData::from(data);
+        let mut stream = Self::from_bytes(file_data.clone())?;
+        stream.length = to_output_frames(
+            stream.decoder.available_length(),
+            stream.decoder.sample_rate(),
+        );
+        stream.incoming = Some(IncomingFile {
+            decoder_bytes: file_data.len(),
+            file_data,
+            starved: false,
+        });
+
+        Ok(stream)
+    }
+
+    /// Add the next part of a file opened with `from_partial_bytes`
+    pub fn append_bytes(&mut self, data: &[u8]) -> Result<(), MixerError> {
+        let incoming = self
+            .incoming
+            .as_mut()
+            .ok_or_else(|| MixerError::new("The stream's file has already been loaded"))?;
+        incoming.file_data.append(data);
+
+        // Reopening reads the headers again, so only do it when playback is waiting on it, or
+        // once there's twice as much to keep the work linear in the file size
+        if incoming.starved || incoming.file_data.received_len() >= incoming.decoder_bytes * 2 {
+            let file_data = incoming.file_data.latest();
+            self.reopen_decoder(file_data, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark a file opened with `from_partial_bytes` as complete, once all of it has been added
+    pub fn finish_loading(&mut self) -> Result<(), MixerError> {
+        match self.incoming.take() {
+            Some(incoming) => self.reopen_decoder(incoming.file_data.latest(), true),
+            None => Ok(()),
+        }
     }
 
-    /// Process the next packet in the stream, returning the number of samples if audio was decoded
-    pub fn process_next_packet(&mut self) -> Result<Option<usize>, JsValue> {
-        match self
-            .packet_reader
-            .read_packet()
-            .map_err(|e| JsValue::from_str(&format!("Ogg read error: {}", e)))?
+    /// Whether the stream's file is still arriving
+    pub fn is_loading(&self) -> bool {
+        self.incoming.is_some()
+    }
+
+    /// Whether playback is held up waiting for more of the file to arrive
+    pub fn is_waiting_for_data(&self) -> bool {
+        self.incoming
+            .as_ref()
+            .map_or(false, |incoming| incoming.starved)
+    }
+
+    /// Open the decoder again on `data`, which holds more of the file than before, and carry
+    /// on decoding from where the old one got to
+    fn reopen_decoder(&mut self, data: FileData, complete: bool) -> Result<(), MixerError> {
+        let bytes = data.len();
+        let decoder = open_decoder(data)?;
+        if decoder.sample_rate() != self.decoder.sample_rate()
+            || decoder.channel_count() != self.channel_count as usize
         {
-            Some(packet) => {
-                debug!("Got packet of size: {}", packet.data.len());
-
-                // Look for headers if we haven't found them yet
-                if !self.header_processed || !self.comments_processed {
-                    if !self.header_processed {
-                        if is_opus_header(&packet.data) {
-                            debug!("Found OpusHead packet");
-
-                            // Parse the Opus header to get the channel count
-                            // OpusHead format: "OpusHead" (8 bytes) + version (1 byte) + channel_count (1 byte) + ...
-                            if packet.data.len() >= 10 {
-                                self.channel_count = packet.data[9] as u16;
-                                debug!("Detected {} channels in input stream", self.channel_count);
-
-                                // Resize the decoded buffer based on the input channel count
-                                self.decoded_buffer =
-                                    vec![0f32; FRAME_SIZE * self.channel_count as usize];
-                            } else {
-                                debug!("Invalid OpusHead packet, using default channel count");
-                            }
-
-                            self.header_processed = true;
-                            return Ok(None);
-                        } else {
-                            debug!("Skipping non-header packet while looking for OpusHead");
-                            return Ok(None);
-                        }
-                    }
-
-                    if !self.comments_processed {
-                        if is_opus_tags(&packet.data) {
-                            debug!("Found OpusTags packet");
-                            self.comments_processed = true;
-
-                            // Create decoder with the correct channel count for this input stream
-                            let channels = match self.channel_count {
-                                1 => Channels::Mono,
-                                2 => Channels::Stereo,
-                                _ => {
-                                    debug!(
-                                        "Unsupported channel count: {}, defaulting to stereo",
-                                        self.channel_count
-                                    );
-                                    Channels::Stereo
-                                }
-                            };
-
-                            debug!("Creating decoder with {} channels", self.channel_count);
-
-                            self.decoder =
-                                Some(Decoder::new(SAMPLE_RATE, channels).map_err(|e| {
-                                    JsValue::from_str(&format!("Opus decoder error: {}", e))
-                                })?);
-
-                            return Ok(None);
-                        } else {
-                            debug!("Skipping non-tags packet while looking for OpusTags");
-                            return Ok(None);
-                        }
-                    }
-                }
+            return Err(MixerError::new("The stream's format changed while loading"));
+        }
+
+        let length = if complete {
+            decoder.length()
+        } else {
+            decoder.available_length()
+        };
+        self.length = to_output_frames(length, decoder.sample_rate());
+        self.decoder = decoder;
+        if let Some(incoming) = &mut self.incoming {
+            incoming.decoder_bytes = bytes;
+            incoming.starved = false;
+        }
+        debug!(
+            "Reopened the decoder with {} bytes, {} samples available",
+            bytes, self.length
+        );
+
+        // Seeking is sample-exact, so the audio carries on seamlessly after what's pending. The
+        // spare loop cursor still reads the old bytes, so it's prepared again.
+        let pending = mem::take(&mut self.pending);
+        self.seek_to_sample(self.next_position)?;
+        self.pending = pending;
+        self.loop_cursor = None;
+
+        Ok(())
+    }
+
+    /// The decoder has run out of the bytes it was opened with. Reopen it if more have
+    /// arrived since, otherwise wait for them.
+    fn starve(&mut self) -> Result<(), MixerError> {
+        let incoming = match &mut self.incoming {
+            Some(incoming) => incoming,
+            None => return Ok(()),
+        };
+
+        if incoming.file_data.received_len() > incoming.decoder_bytes {
+            let file_data = incoming.file_data.latest();
+            self.reopen_decoder(file_data, false)
+        } else {
+            log_info!(
+                "Waiting for more of the file at {} samples",
+                self.next_position
+            );
+            incoming.starved = true;
+            Ok(())
+        }
+    }
+
+    /// Open an audio file from any seekable source. The whole file is read into
+    /// memory, as seeking and looping need random access to it.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> Result<Self, MixerError> {
+        let mut data = Vec::new();
+        reader
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| reader.read_to_end(&mut data))
+            .map_err(|e| MixerError::new(format!("Read error: {}", e)))?;
+        Self::from_bytes(data)
+    }
+
+    /// Timeline position of the next sample frame to be mixed, in samples
+    pub fn position(&self) -> i64 {
+        self.offset + self.next_position - self.pending_frames() as i64 - self.lead_in as i64
+    }
+
+    pub fn current_timestamp(&self) -> f64 {
+        self.position() as f64 / SAMPLE_RATE as f64
+    }
+
+    /// Timeline position the stream ends at, in seconds
+    pub fn duration(&self) -> f64 {
+        (self.offset + self.length).max(0) as f64 / SAMPLE_RATE as f64
+    }
 
-                // At this point, we should have both headers processed
-                if let Some(decoder) = &mut self.decoder {
-                    match decoder.decode_float(&packet.data, &mut self.decoded_buffer, false) {
-                        Ok(decoded_samples) => {
-                            debug!("Decoded {} samples", decoded_samples);
-                            self.total_samples_decoded += decoded_samples;
-                            self.current_granule_position += decoded_samples as i64;
-                            Ok(Some(decoded_samples))
-                        }
-                        Err(e) => {
-                            eprintln!("Error decoding packet: {}", e);
-                            Ok(None)
-                        }
-                    }
-                } else {
-                    debug!("No decoder available for audio packet");
-                    Ok(None)
+    /// Timeline position the first frame of audio plays at, in samples
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Play the first frame of audio at timeline position `offset` (in samples), with silence
+    /// before it, or skip the beginning of the audio if it's negative. Takes effect from the
+    /// next seek.
+    pub fn set_offset(&mut self, offset: i64) {
+        self.offset = offset;
+
+        // The spare cursor was parked for the old offset
+        if let Some(cursor) = &mut self.loop_cursor {
+            cursor.loop_start = None;
+        }
+    }
+
+    /// Decode the next block of the file into `pending`, returning the number of sample frames
+    /// queued. Sets `end_of_stream` once the end of the audio is reached.
+    fn decode_next(&mut self) -> Result<usize, MixerError> {
+        let channels = self.channel_count as usize;
+        self.decoded_buffer.clear();
+
+        let result = self.decoder.decode_next(&mut self.decoded_buffer);
+
+        // Until the whole file has arrived, running out of bytes (or into a packet that's only
+        // partly there) means waiting for more rather than the end of the stream
+        if self.incoming.is_some() && !matches!(result, Ok(Some(_))) {
+            if let Err(e) = result {
+                debug!("Stopped at the end of the data received so far: {:?}", e);
+            }
+            self.starve()?;
+            return Ok(0);
+        }
+
+        let converted = match result? {
+            Some(frames) => {
+                self.drift_stats.record_decoded(frames);
+
+                // Drop any pre-skip or pre-roll frames before they reach the converter
+                let skipped = frames.min(self.input_to_skip);
+                self.input_to_skip -= skipped;
+                let decoded = &self.decoded_buffer[skipped * channels..];
+                match &mut self.converter {
+                    Some(converter) => converter.process(decoded),
+                    None => decoded,
                 }
             }
             None => {
                 debug!("End of stream reached");
-                Ok(None)
+                self.end_of_stream = true;
+                match &mut self.converter {
+                    Some(converter) => converter.flush(),
+                    None => return Ok(0),
+                }
+            }
+        };
+
+        // Drop any frames the converter produced before the seek target, then anything past
+        // the end of the audio, which is codec padding or the tail of the converter's filter
+        let frames = converted.len() / channels;
+        let skipped = frames.min(self.samples_to_skip);
+        self.samples_to_skip -= skipped;
+        let remaining = (self.length - self.next_position).max(0) as usize;
+        let frames = (frames - skipped).min(remaining);
+        let samples = &converted[skipped * channels..(skipped + frames) * channels];
+
+        match &self.stereo_matrix {
+            Some(matrix) => matrix.apply(samples, frames, 1.0, &mut self.pending),
+            None => self.pending.extend(samples),
+        }
+
+        self.next_position += frames as i64;
+        if self.next_position >= self.length {
+            if self.incoming.is_some() {
+                self.starve()?;
+            } else {
+                self.end_of_stream = true;
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Number of interleaved channels queued for mixing: 1 for mono, otherwise 2 once any
+    /// multichannel audio has been folded to stereo
+    pub fn output_channels(&self) -> usize {
+        if self.stereo_matrix.is_some() {
+            2
+        } else {
+            self.channel_count as usize
+        }
+    }
+
+    /// Choose how the decoded channels become the stereo pair that gets mixed. Raw routing
+    /// picks two of the file's channels, numbered in the file's channel order.
+    pub fn set_channel_mode(&mut self, mode: ChannelMode) -> Result<(), MixerError> {
+        let channel_count = self.get_channel_count() as usize;
+        if let ChannelMode::Raw { left, right } = mode {
+            if channel_count < 2 {
+                return Err(MixerError::new(
+                    "Raw channel routing needs a stream with at least 2 channels",
+                ));
             }
+            if left >= channel_count || right >= channel_count {
+                return Err(MixerError::new(format!(
+                    "Invalid channels {} and {} ({} channels in stream)",
+                    left, right, channel_count
+                )));
+            }
+        }
+
+        // Streams with 2 or more channels always queue stereo, so anything already
+        // decoded can stay where it is
+        self.channel_mode = mode;
+        self.stereo_matrix = StereoMatrix::new(mode, self.decoder.mapping_family(), channel_count);
+        Ok(())
+    }
+
+    /// Number of decoded sample frames waiting to be mixed
+    pub fn pending_frames(&self) -> usize {
+        self.pending.len() / self.output_channels()
+    }
+
+    /// Decode until at least `frames` sample frames are pending, the stream ends or it has to
+    /// wait for more of the file, returning the number of frames available (at most `frames`)
+    pub fn fill_pending(&mut self, frames: usize) -> Result<usize, MixerError> {
+        while self.pending_frames() < frames && !self.end_of_stream && !self.is_waiting_for_data() {
+            self.decode_next()?;
         }
+
+        Ok(self.pending_frames().min(frames))
+    }
+
+    /// Whether the next `frames` sample frames can be read without waiting for more of the file
+    pub fn can_read(&mut self, frames: usize) -> Result<bool, MixerError> {
+        if self.lead_in >= frames {
+            return Ok(true);
+        }
+
+        let rate = self.drift_compensation as f64;
+        let needed = self
+            .resampler
+            .input_frames_needed(frames - self.lead_in, rate);
+        let available = self.fill_pending(needed)?;
+
+        Ok(available == needed || !self.is_waiting_for_data())
+    }
+
+    /// Produce the next `frames` sample frames into `output`, played back at the
+    /// `drift_compensation` rate. Returns the number of frames produced, which is only fewer
+    /// than requested at the end of the stream, or while waiting for more of the file.
+    pub fn read_frames(&mut self, frames: usize) -> Result<usize, MixerError> {
+        // Play silence until the timeline reaches the stream's offset
+        let silence = self.lead_in.min(frames);
+        self.lead_in -= silence;
+
+        let rate = self.drift_compensation as f64;
+        let needed = self.resampler.input_frames_needed(frames - silence, rate);
+        self.fill_pending(needed)?;
+
+        let produced =
+            self.resampler
+                .process(&mut self.pending, &mut self.output, frames - silence, rate);
+        self.drift_stats.record_resampling(produced, rate);
+
+        if silence > 0 {
+            let channels = self.output_channels();
+            self.output
+                .splice(0..0, iter::repeat(0.0).take(silence * channels));
+        }
+
+        Ok(silence + produced)
     }
 
-    pub fn get_decoded_samples(&self) -> &[f32] {
-        &self.decoded_buffer
+    /// Metadata from the file, if it had any that could be parsed
+    pub fn tags(&self) -> Option<&OpusTags> {
+        self.decoder.tags()
     }
 
-    /// Get the channel count of this input stream (1 for mono, 2 for stereo)
+    /// Get the channel count of this input stream, from the file header
     pub fn get_channel_count(&self) -> u16 {
         self.channel_count
     }
 
-    /// Seek to a target timestamp using bisection search as specified in RFC 7845
-    pub fn seek_to_timestamp(&mut self, target_timestamp: f64) -> Result<(), JsValue> {
-        let target_granule = (target_timestamp * SAMPLE_RATE as f64) as i64;
+    /// Seek to a target timestamp. The samples decoded on the way to it are discarded, so the
+    /// next mixed sample is the one at `target_timestamp`.
+    pub fn seek_to_timestamp(&mut self, target_timestamp: f64) -> Result<(), MixerError> {
+        self.seek_to_position((target_timestamp.max(0.0) * SAMPLE_RATE as f64) as i64)
+    }
+
+    /// Seek to timeline position `position`, in samples
+    pub fn seek_to_position(&mut self, position: i64) -> Result<(), MixerError> {
+        let position = position.max(0);
+        self.lead_in = (self.offset - position).max(0) as usize;
+        self.seek_to_sample(position - self.offset)?;
+        self.resampler.reset();
+
+        Ok(())
+    }
+
+    /// Move the decode position to position `target` (in samples) in the audio. Only touches the
+    /// fields a `DecodeCursor` holds, so it can also position a swapped-in spare cursor.
+    fn seek_to_sample(&mut self, target: i64) -> Result<(), MixerError> {
+        let target = target.max(0);
+        let sample_rate = self.decoder.sample_rate() as i64;
+
+        // Decoding starts from a frame that lands exactly on a 48kHz frame, far enough before
+        // the target to fill the converter's filter with real audio
+        let (start, start_position) = match &mut self.converter {
+            Some(converter) => {
+                converter.reset();
+                let divisor = gcd(sample_rate, SAMPLE_RATE as i64);
+                let input_period = sample_rate / divisor;
+                let output_period = SAMPLE_RATE as i64 / divisor;
+                let earliest =
+                    target * sample_rate / SAMPLE_RATE as i64 - converter.history_frames() as i64;
+                let periods = earliest.max(0) / input_period;
+                (periods * input_period, periods * output_period)
+            }
+            None => (target, target),
+        };
+
+        let decode_position = self.decoder.seek(start)?;
         debug!(
-            "Seeking to granule position {} ({:.2}s)",
-            target_granule, target_timestamp
+            "Seeking to {} samples, decoding from frame {} at {}Hz",
+            target, decode_position, sample_rate
         );
 
-        // Get file size for bisection bounds
-        let file = self.packet_reader.get_mut();
-        let file_size = file
-            .seek(SeekFrom::End(0))
-            .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?;
-
-        // Initialize bisection search bounds
-        let mut left = 0;
-        let mut right = file_size;
-        let mut last_granule = 0;
-        let mut best_position = 0;
-
-        // Bisection search for the target granule position
-        while right - left > 4096 {
-            // Stop when we're within a page
-            let mid = left + (right - left) / 2;
-            file.seek(SeekFrom::Start(mid))
-                .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?;
-
-            // Sync to next page boundary
-            let mut buf = [0u8; 4];
-            let mut capture_pattern_found = false;
-            while !capture_pattern_found
-                && file
-                    .stream_position()
-                    .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?
-                    < right
-            {
-                match file.read_exact(&mut buf[..1]) {
-                    Ok(_) => {
-                        if buf[0] == 'O' as u8 {
-                            if let Ok(_) = file.read_exact(&mut buf[1..]) {
-                                if &buf == b"OggS" {
-                                    capture_pattern_found = true;
-                                    file.seek(SeekFrom::Current(-4)).map_err(|e| {
-                                        JsValue::from_str(&format!("Seek error: {}", e))
-                                    })?; // Rewind to start of page
-                                }
-                            }
-                        }
-                    }
-                    Err(_) => break,
+        self.pending.clear();
+        self.end_of_stream = false;
+        self.input_to_skip = (start - decode_position).max(0) as usize;
+        self.samples_to_skip = (target - start_position) as usize;
+        self.next_position = target;
+
+        Ok(())
+    }
+
+    /// Swap the decoding state with `cursor`'s
+    fn swap_cursor(&mut self, cursor: &mut DecodeCursor) {
+        mem::swap(&mut self.decoder, &mut cursor.decoder);
+        mem::swap(&mut self.converter, &mut cursor.converter);
+        mem::swap(&mut self.pending, &mut cursor.pending);
+        mem::swap(&mut self.input_to_skip, &mut cursor.input_to_skip);
+        mem::swap(&mut self.samples_to_skip, &mut cursor.samples_to_skip);
+        mem::swap(&mut self.end_of_stream, &mut cursor.end_of_stream);
+        mem::swap(&mut self.next_position, &mut cursor.next_position);
+    }
+
+    /// Whether the spare cursor still has to be parked at `loop_start`
+    pub fn needs_loop_preparation(&self, loop_start: i64) -> bool {
+        self.loop_cursor
+            .as_ref()
+            .map_or(true, |cursor| cursor.loop_start != Some(loop_start))
+    }
+
+    /// Park the spare cursor at timeline position `loop_start` (in samples) and decode a
+    /// little audio from there, ready to be swapped in by `wrap_to_loop`
+    pub fn prepare_loop(&mut self, loop_start: i64) -> Result<(), MixerError> {
+        debug!("Preparing loop lookahead at {} samples", loop_start);
+
+        let mut cursor = match self.loop_cursor.take() {
+            Some(cursor) => cursor,
+            None => {
+                let decoder = self.decoder.try_clone()?;
+                DecodeCursor {
+                    converter: create_converter(decoder.as_ref()),
+                    decoder,
+                    pending: VecDeque::new(),
+                    input_to_skip: 0,
+                    samples_to_skip: 0,
+                    end_of_stream: false,
+                    next_position: 0,
+                    loop_start: None,
                 }
             }
+        };
+
+        // Position and fill the spare using the stream's own decoding code
+        self.swap_cursor(&mut cursor);
+        let result = self
+            .seek_to_sample(loop_start - self.offset)
+            .and_then(|_| self.fill_pending(LOOP_LOOKAHEAD_FRAMES));
+        self.swap_cursor(&mut cursor);
+        result?;
+
+        cursor.loop_start = Some(loop_start);
+        self.loop_cursor = Some(cursor);
+        Ok(())
+    }
 
-            if !capture_pattern_found {
-                // No page found after mid, search in first half
-                right = mid;
-                continue;
-            }
+    /// Continue playback from `loop_start`, swapping in the prepared spare cursor. The cursor
+    /// being replaced becomes the spare for the next time round.
+    pub fn wrap_to_loop(&mut self, loop_start: i64) -> Result<(), MixerError> {
+        if self.needs_loop_preparation(loop_start) {
+            self.prepare_loop(loop_start)?;
+        }
 
-            // Read page header
-            let mut header = [0u8; 27];
-            file.read_exact(&mut header)
-                .map_err(|e| JsValue::from_str(&format!("Read error: {}", e)))?;
+        if let Some(mut cursor) = self.loop_cursor.take() {
+            self.swap_cursor(&mut cursor);
+            cursor.loop_start = None;
+            self.loop_cursor = Some(cursor);
+        }
+        self.lead_in = (self.offset - loop_start).max(0) as usize;
+
+        Ok(())
+    }
 
-            // Extract granule position (bytes 6-13, little endian)
-            let granule = i64::from_le_bytes(header[6..14].try_into().unwrap());
+    /// Drop the spare cursor once looping is switched off
+    pub fn clear_loop(&mut self) {
+        self.loop_cursor = None;
+    }
+}
 
-            if granule < 0 {
-                // Headers or invalid granule, search in second half
-                left = mid;
-                continue;
+/// Converter from the decoder's sample rate to 48kHz, if it needs one
+fn create_converter(decoder: &dyn StreamDecoder) -> Option<SampleRateConverter> {
+    if decoder.sample_rate() == SAMPLE_RATE {
+        return None;
+    }
+
+    debug!(
+        "Converting {}Hz audio to {}Hz",
+        decoder.sample_rate(),
+        SAMPLE_RATE
+    );
+    Some(SampleRateConverter::new(
+        decoder.sample_rate() as f64,
+        SAMPLE_RATE as f64,
+        decoder.channel_count(),
+        ResamplerQuality::Medium,
+    ))
+}
+
+/// Convert a length in frames at `sample_rate` to frames at 48kHz
+fn to_output_frames(frames: i64, sample_rate: u32) -> i64 {
+    (frames as f64 * SAMPLE_RATE as f64 / sample_rate as f64).round() as i64
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::test_utils::{encode_flac, encode_ogg_opus, sine};
+    use crate::opus_mixer::wav_writer::{encode_wav, WavFormat};
+    use std::io::Cursor;
+
+    fn stereo_stream(seconds: f64) -> AudioStream {
+        AudioStream::from_bytes(encode_ogg_opus(&sine(440.0, seconds, 2), 2)).unwrap()
+    }
+
+    /// Read the rest of the stream, returning the number of frames produced
+    fn read_to_end(stream: &mut AudioStream) -> usize {
+        let mut total = 0;
+        loop {
+            let produced = stream.read_frames(FRAME_SIZE).unwrap();
+            total += produced;
+            if produced < FRAME_SIZE {
+                return total;
             }
+        }
+    }
+
+    #[test]
+    fn decodes_every_sample_without_padding() {
+        let mut stream = stereo_stream(1.5);
 
-            debug!("Found granule {} at position {}", granule, mid);
-
-            // Update search bounds based on granule position
-            if granule < target_granule {
-                left = mid;
-                if granule > last_granule {
-                    last_granule = granule;
-                    best_position = file
-                        .stream_position()
-                        .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?
-                        - header.len() as u64;
+        assert_eq!(stream.duration(), 1.5);
+        assert_eq!(stream.output_channels(), 2);
+        assert_eq!(read_to_end(&mut stream), 72000);
+        assert!(stream.end_of_stream);
+    }
+
+    #[test]
+    fn reads_from_any_seekable_source() {
+        let data = encode_ogg_opus(&sine(440.0, 0.5, 1), 1);
+        let mut stream = AudioStream::from_reader(Cursor::new(data)).unwrap();
+
+        assert_eq!(stream.get_channel_count(), 1);
+        assert_eq!(stream.tags().map(|tags| tags.vendor.as_str()), Some("test"));
+        assert_eq!(read_to_end(&mut stream), 24000);
+    }
+
+    #[test]
+    fn rejects_data_in_unknown_formats() {
+        let error = AudioStream::from_bytes(vec![0u8; 1024]).unwrap_err();
+        assert!(error.to_string().starts_with("Unrecognised audio format"));
+    }
+
+    #[test]
+    fn seeks_to_the_exact_sample() {
+        let mut stream = stereo_stream(2.0);
+
+        for &timestamp in &[1.25, 0.01, 1.999, 0.5] {
+            stream.seek_to_timestamp(timestamp).unwrap();
+            let target = (timestamp * SAMPLE_RATE as f64) as i64;
+            assert_eq!(stream.position(), target);
+
+            stream.read_frames(FRAME_SIZE).unwrap();
+            assert_eq!(stream.position(), (target + FRAME_SIZE as i64).min(96000));
+        }
+
+        stream.seek_to_timestamp(1.5).unwrap();
+        assert_eq!(read_to_end(&mut stream), 24000);
+    }
+
+    #[test]
+    fn seeking_to_the_start_replays_the_whole_stream() {
+        let mut stream = stereo_stream(1.0);
+        read_to_end(&mut stream);
+
+        stream.seek_to_timestamp(0.0).unwrap();
+        assert_eq!(stream.position(), 0);
+        assert_eq!(read_to_end(&mut stream), 48000);
+    }
+
+    #[test]
+    fn plays_wav_files_at_other_sample_rates() {
+        // One second at 44.1kHz, which converts to exactly one second at 48kHz
+        let samples: Vec<f32> = (0..44100).map(|i| (i as f32 / 100.0).sin() * 0.5).collect();
+        let wav = encode_wav(&samples, 1, 44100, WavFormat::Pcm16);
+        let mut stream = AudioStream::from_bytes(wav).unwrap();
+
+        assert_eq!(stream.duration(), 1.0);
+        assert_eq!(stream.output_channels(), 1);
+        assert_eq!(read_to_end(&mut stream), 48000);
+
+        stream.seek_to_timestamp(0.75).unwrap();
+        assert_eq!(stream.position(), 36000);
+        assert_eq!(read_to_end(&mut stream), 12000);
+    }
+
+    #[test]
+    fn seeking_a_converted_stream_matches_playing_through() {
+        let samples: Vec<f32> = (0..22050 * 2)
+            .map(|i| ((i / 2) as f32 / 30.0).sin() * 0.5)
+            .collect();
+        let mut stream =
+            AudioStream::from_bytes(encode_wav(&samples, 2, 22050, WavFormat::Float32)).unwrap();
+
+        let mut played = Vec::new();
+        while stream.read_frames(FRAME_SIZE).unwrap() > 0 {
+            played.extend_from_slice(&stream.output);
+        }
+        assert_eq!(played.len(), 96000);
+
+        stream.seek_to_timestamp(0.6).unwrap();
+        stream.read_frames(FRAME_SIZE).unwrap();
+        let start = 28800 * 2;
+        for (sought, expected) in stream.output.iter().zip(&played[start..]) {
+            assert!(
+                (sought - expected).abs() < 1e-4,
+                "{} != {}",
+                sought,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn offsets_move_the_audio_along_the_timeline() {
+        let mut plain = stereo_stream(1.0);
+        plain.read_frames(FRAME_SIZE).unwrap();
+        let first_frame = plain.output.clone();
+
+        // Silence up to the offset, then the audio from its first frame
+        let mut stream = stereo_stream(1.0);
+        stream.set_offset(1440);
+        stream.seek_to_position(0).unwrap();
+        assert_eq!(stream.duration(), 1.03);
+        assert_eq!(stream.read_frames(FRAME_SIZE).unwrap(), FRAME_SIZE);
+        assert!(stream.output.iter().all(|&sample| sample == 0.0));
+        assert_eq!(stream.read_frames(FRAME_SIZE).unwrap(), FRAME_SIZE);
+        assert!(stream.output[..960].iter().all(|&sample| sample == 0.0));
+        assert_eq!(&stream.output[960..], &first_frame[..960]);
+        assert_eq!(stream.position(), 1920);
+
+        // Seeking past the offset lands in the audio
+        stream.seek_to_position(24000).unwrap();
+        assert_eq!(stream.position(), 24000);
+        assert_eq!(read_to_end(&mut stream), 25440);
+
+        // A negative offset skips the start of the audio
+        stream.set_offset(-24000);
+        stream.seek_to_position(0).unwrap();
+        assert_eq!(stream.duration(), 0.5);
+        assert_eq!(read_to_end(&mut stream), 24000);
+    }
+
+    #[test]
+    fn plays_a_file_as_it_arrives() {
+        let samples: Vec<f32> = (0..44100).map(|i| (i as f32 / 100.0).sin() * 0.5).collect();
+        let wav = encode_wav(&samples, 1, 44100, WavFormat::Pcm16);
+
+        let mut whole = AudioStream::from_bytes(wav.clone()).unwrap();
+        let mut expected = Vec::new();
+        while whole.read_frames(FRAME_SIZE).unwrap() > 0 {
+            expected.extend_from_slice(&whole.output);
+        }
+
+        let mut chunks = wav.chunks(5000);
+        let mut stream = AudioStream::from_partial_bytes(chunks.next().unwrap().to_vec()).unwrap();
+        assert!(stream.is_loading());
+        assert!(stream.duration() < 0.1);
+
+        let mut played = Vec::new();
+        loop {
+            let produced = stream.read_frames(FRAME_SIZE).unwrap();
+            played.extend_from_slice(&stream.output[..produced]);
+            if produced < FRAME_SIZE {
+                if !stream.is_loading() {
+                    break;
                 }
-            } else {
-                right = mid;
-                if granule < last_granule || last_granule == 0 {
-                    last_granule = granule;
-                    best_position = file
-                        .stream_position()
-                        .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?
-                        - header.len() as u64;
+
+                // Running out part way through waits for more rather than ending the stream
+                assert!(stream.is_waiting_for_data());
+                assert!(!stream.end_of_stream);
+                match chunks.next() {
+                    Some(chunk) => stream.append_bytes(chunk).unwrap(),
+                    None => stream.finish_loading().unwrap(),
                 }
             }
         }
 
-        // Seek to best position found
-        debug!(
-            "Seeking to best position: {} (granule: {})",
-            best_position, last_granule
-        );
+        // Reopening the decoder as the file grows is seamless
+        assert_eq!(stream.duration(), 1.0);
+        assert_eq!(played.len(), expected.len());
+        for (played, expected) in played.iter().zip(&expected) {
+            assert!(
+                (played - expected).abs() < 1e-4,
+                "{} != {}",
+                played,
+                expected
+            );
+        }
+    }
 
-        // Get the file handle and seek to start
-        let file = self.packet_reader.get_mut();
-        file.seek(SeekFrom::Start(0))
-            .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?;
-
-        // Reset decoder state
-        self.decoder = None;
-        self.header_processed = false;
-        self.comments_processed = false;
-
-        // Process until we find the OpusHead and OpusTags headers
-        while !self.header_processed || !self.comments_processed {
-            match self.process_next_packet()? {
-                Some(_) => {}
-                None => {
-                    if self.header_processed && self.comments_processed {
-                        break;
-                    }
-                }
+    #[test]
+    fn opens_once_the_headers_have_arrived() {
+        let data = encode_ogg_opus(&sine(440.0, 1.0, 2), 2);
+        let mut received = 64;
+        assert!(AudioStream::from_partial_bytes(data[..received].to_vec()).is_err());
+
+        let mut stream = loop {
+            received += 64;
+            if let Ok(stream) = AudioStream::from_partial_bytes(data[..received].to_vec()) {
+                break stream;
             }
+        };
+        assert_eq!(stream.read_frames(FRAME_SIZE).unwrap(), 0);
+        assert!(stream.is_waiting_for_data());
+
+        for chunk in data[received..].chunks(1000) {
+            stream.append_bytes(chunk).unwrap();
         }
+        stream.finish_loading().unwrap();
+        assert!(!stream.is_loading());
+        assert_eq!(read_to_end(&mut stream), 48000);
+        assert!(stream.end_of_stream);
+    }
 
-        // Now seek to the target position
-        let file = self.packet_reader.get_mut();
-        file.seek(SeekFrom::Start(best_position))
-            .map_err(|e| JsValue::from_str(&format!("Seek error: {}", e)))?;
+    #[test]
+    fn flac_streams_seek_like_opus_streams() {
+        let data = encode_flac(&sine(440.0, 2.0, 2), 2, 48000, 4096, Some(4));
+        let mut stream = AudioStream::from_bytes(data).unwrap();
+        assert_eq!(stream.duration(), 2.0);
 
-        // Update the total samples decoded based on the granule position
-        self.total_samples_decoded = (last_granule as f64 * SAMPLE_RATE as f64 / 48000.0) as usize;
+        for &timestamp in &[1.25, 0.01, 1.999, 0.5] {
+            stream.seek_to_timestamp(timestamp).unwrap();
+            let target = (timestamp * SAMPLE_RATE as f64) as i64;
+            assert_eq!(stream.position(), target);
 
-        Ok(())
+            stream.read_frames(FRAME_SIZE).unwrap();
+            assert_eq!(stream.position(), (target + FRAME_SIZE as i64).min(96000));
+        }
+
+        stream.seek_to_timestamp(1.5).unwrap();
+        assert_eq!(read_to_end(&mut stream), 24000);
+        assert!(stream.end_of_stream);
     }
 }
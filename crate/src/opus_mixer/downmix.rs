@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// How a stream's decoded channels are turned into the stereo pair that gets mixed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelMode {
+    /// Fold every channel into stereo using the standard matrix for the channel layout
+    Downmix,
+    /// Route two of the raw decoded channels straight to the left and right outputs
+    Raw { left: usize, right: usize },
+}
+
+/// Left and right output coefficients for each input channel
+#[derive(Debug)]
+pub struct StereoMatrix {
+    coefficients: Vec<(f32, f32)>,
+}
+
+impl StereoMatrix {
+    /// Build the matrix for a stream, or None if its decoded channels can be mixed as they are
+    pub fn new(mode: ChannelMode, mapping_family: u8, channel_count: usize) -> Option<Self> {
+        let coefficients = match mode {
+            ChannelMode::Raw { left, right } => {
+                let mut coefficients = vec![(0.0, 0.0); channel_count];
+                coefficients[left].0 = 1.0;
+                coefficients[right].1 = 1.0;
+                coefficients
+            }
+            ChannelMode::Downmix if channel_count <= 2 => return None,
+            ChannelMode::Downmix => match itu_downmix(mapping_family, channel_count) {
+                Some(coefficients) => coefficients,
+                None => {
+                    // No defined layout (e.g. family 255), so pass the first two channels through
+                    let mut coefficients = vec![(0.0, 0.0); channel_count];
+                    coefficients[0].0 = 1.0;
+                    coefficients[1].1 = 1.0;
+                    coefficients
+                }
+            },
+        };
+
+        Some(Self { coefficients })
+    }
+
+    /// Fold `frames` interleaved frames from `input` into stereo, appending them to `output`
+    pub fn apply(&self, input: &[f32], frames: usize, gain: f32, output: &mut VecDeque<f32>) {
+        let channels = self.coefficients.len();
+        for frame in input.chunks_exact(channels).take(frames) {
+            let (mut left, mut right) = (0.0, 0.0);
+            for (sample, (left_coeff, right_coeff)) in frame.iter().zip(&self.coefficients) {
+                left += sample * left_coeff;
+                right += sample * right_coeff;
+            }
+            output.push_back(left * gain);
+            output.push_back(right * gain);
+        }
+    }
+}
+
+/// ITU-R BS.775 downmix coefficients for the Vorbis channel orders used by mapping family 1,
+/// scaled so fully correlated full-scale input can't clip. The LFE channel is dropped.
+fn itu_downmix(mapping_family: u8, channel_count: usize) -> Option<Vec<(f32, f32)>> {
+    if mapping_family != 1 {
+        return None;
+    }
+
+    const C: f32 = FRAC_1_SQRT_2;
+    let coefficients: Vec<(f32, f32)> = match channel_count {
+        // L, C, R
+        3 => vec![(1.0, 0.0), (C, C), (0.0, 1.0)],
+        // FL, FR, RL, RR
+        4 => vec![(1.0, 0.0), (0.0, 1.0), (C, 0.0), (0.0, C)],
+        // FL, C, FR, RL, RR
+        5 => vec![(1.0, 0.0), (C, C), (0.0, 1.0), (C, 0.0), (0.0, C)],
+        // FL, C, FR, RL, RR, LFE
+        6 => vec![
+            (1.0, 0.0),
+            (C, C),
+            (0.0, 1.0),
+            (C, 0.0),
+            (0.0, C),
+            (0.0, 0.0),
+        ],
+        // FL, C, FR, SL, SR, RC, LFE
+        7 => vec![
+            (1.0, 0.0),
+            (C, C),
+            (0.0, 1.0),
+            (C, 0.0),
+            (0.0, C),
+            (0.5, 0.5),
+            (0.0, 0.0),
+        ],
+        // FL, C, FR, SL, SR, RL, RR, LFE
+        8 => vec![
+            (1.0, 0.0),
+            (C, C),
+            (0.0, 1.0),
+            (C, 0.0),
+            (0.0, C),
+            (C, 0.0),
+            (0.0, C),
+            (0.0, 0.0),
+        ],
+        _ => return None,
+    };
+
+    // The layouts are symmetric, so the left sum is the largest possible output
+    let scale = 1.0 / coefficients.iter().map(|(left, _)| left).sum::<f32>();
+    Some(
+        coefficients
+            .into_iter()
+            .map(|(left, right)| (left * scale, right * scale))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(matrix: &StereoMatrix, frame: &[f32]) -> (f32, f32) {
+        let mut output = VecDeque::new();
+        matrix.apply(frame, 1, 1.0, &mut output);
+        (output[0], output[1])
+    }
+
+    #[test]
+    fn folds_5_1_down_with_itu_coefficients() {
+        let coefficients = itu_downmix(1, 6).unwrap();
+
+        // FL, C, FR, RL, RR at 1, 1/√2, 0, 1/√2, 0 on the left, mirrored on the right, no LFE
+        let scale = 1.0 / (1.0 + 2.0 * FRAC_1_SQRT_2);
+        let expected = [
+            (1.0, 0.0),
+            (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            (0.0, 1.0),
+            (FRAC_1_SQRT_2, 0.0),
+            (0.0, FRAC_1_SQRT_2),
+            (0.0, 0.0),
+        ];
+        for (actual, expected) in coefficients.iter().zip(&expected) {
+            assert!((actual.0 - expected.0 * scale).abs() < 1e-6);
+            assert!((actual.1 - expected.1 * scale).abs() < 1e-6);
+        }
+
+        // Full-scale input on every channel comes out at exactly full scale
+        let matrix = StereoMatrix::new(ChannelMode::Downmix, 1, 6).unwrap();
+        let (left, right) = apply(&matrix, &[1.0; 6]);
+        assert!((left - 1.0).abs() < 1e-6);
+        assert!((right - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normalises_every_family_1_layout() {
+        for channels in 3..=8 {
+            let matrix = StereoMatrix::new(ChannelMode::Downmix, 1, channels).unwrap();
+            let (left, right) = apply(&matrix, &vec![1.0; channels]);
+            assert!((left - 1.0).abs() < 1e-6, "{} channels", channels);
+            assert!((right - 1.0).abs() < 1e-6, "{} channels", channels);
+        }
+        assert!(itu_downmix(1, 9).is_none());
+    }
+
+    #[test]
+    fn passes_the_first_two_channels_through_without_a_layout() {
+        let frame = [0.1, 0.2, 0.3, 0.4];
+        let matrix = StereoMatrix::new(ChannelMode::Downmix, 255, 4).unwrap();
+        assert_eq!(apply(&matrix, &frame), (0.1, 0.2));
+
+        // Mono and stereo are mixed as they are
+        assert!(StereoMatrix::new(ChannelMode::Downmix, 0, 2).is_none());
+        assert!(StereoMatrix::new(ChannelMode::Downmix, 1, 1).is_none());
+    }
+
+    #[test]
+    fn routes_raw_channels_to_the_outputs() {
+        let frame = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let mode = ChannelMode::Raw { left: 3, right: 1 };
+        let matrix = StereoMatrix::new(mode, 1, 6).unwrap();
+        assert_eq!(apply(&matrix, &frame), (0.4, 0.2));
+
+        // Even for stereo, where the channels can be swapped or doubled up
+        let matrix = StereoMatrix::new(ChannelMode::Raw { left: 1, right: 1 }, 0, 2).unwrap();
+        assert_eq!(apply(&matrix, &[0.1, 0.2]), (0.2, 0.2));
+
+        let mut output = VecDeque::new();
+        matrix.apply(&[0.1, 0.2, 0.3, 0.4], 2, 0.5, &mut output);
+        assert_eq!(output, [0.1, 0.1, 0.2, 0.2]);
+    }
+}
@@ -1,3 +1,18 @@
+use std::collections::VecDeque;
+
+/// Sync checks kept in each stream's history (one a second, so about the last minute)
+const HISTORY_LENGTH: usize = 60;
+
+/// One sync check of a stream: how far it had drifted from the others, and the playback rate
+/// chosen to pull it back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftMeasurement {
+    /// Mix position of the check, in seconds
+    pub time: f64,
+    pub drift_seconds: f64,
+    pub playback_rate: f32,
+}
+
 /// Drift statistics for a single stream
 #[derive(Debug)]
 pub struct DriftStats {
@@ -7,6 +22,11 @@ pub struct DriftStats {
     pub(crate) max_compensation: f32,
     pub(crate) total_compensation: f32,
     pub(crate) compensation_samples: usize,
+    pub(crate) resampled_input_frames: f64,
+    pub(crate) resampled_output_frames: u64,
+    // Sample frames produced by the decoder, at the file's sample rate
+    pub(crate) decoded_frames: u64,
+    history: VecDeque<DriftMeasurement>,
 }
 
 impl DriftStats {
@@ -18,6 +38,10 @@ impl DriftStats {
             max_compensation: 1.0,
             total_compensation: 0.0,
             compensation_samples: 0,
+            resampled_input_frames: 0.0,
+            resampled_output_frames: 0,
+            decoded_frames: 0,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
         }
     }
 
@@ -35,28 +59,132 @@ impl DriftStats {
         }
     }
 
-    pub fn print_stats(&self) {
-        if self.drift_samples > 0 {
-            println!("  Maximum Drift: {:.3} ms", self.max_drift_seconds * 1000.0);
-            println!(
-                "  Average Drift: {:.3} ms",
-                self.total_drift_seconds * 1000.0 / self.drift_samples as f64
-            );
-            println!("  Drift Measurements: {}", self.drift_samples);
-        }
-        if self.compensation_samples > 0 {
-            println!(
-                "  Maximum Speed Adjustment: {:.2}%",
-                (self.max_compensation - 1.0) * 100.0
-            );
-            println!(
-                "  Average Speed Adjustment: {:.2}%",
-                ((self.total_compensation / self.compensation_samples as f32) - 1.0) * 100.0
-            );
-            println!(
-                "  Compensation Applied: {} times",
-                self.compensation_samples
-            );
+    /// Record `output_frames` frames produced by the resampler at `rate`
+    pub fn record_resampling(&mut self, output_frames: usize, rate: f64) {
+        self.resampled_input_frames += output_frames as f64 * rate;
+        self.resampled_output_frames += output_frames as u64;
+    }
+
+    /// Record `frames` sample frames coming out of the decoder
+    pub fn record_decoded(&mut self, frames: usize) {
+        self.decoded_frames += frames as u64;
+    }
+
+    /// Add a sync check to the history, dropping the oldest once it's full
+    pub fn record_measurement(&mut self, measurement: DriftMeasurement) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(measurement);
+    }
+
+    /// The most recent sync checks, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &DriftMeasurement> {
+        self.history.iter()
+    }
+
+    /// Mean size of the drift measured, in seconds
+    pub fn average_drift_seconds(&self) -> f64 {
+        if self.drift_samples == 0 {
+            0.0
+        } else {
+            self.total_drift_seconds / self.drift_samples as f64
+        }
+    }
+
+    /// Mean playback rate over the sync checks that adjusted it, 1.0 if none did
+    pub fn average_compensation(&self) -> f32 {
+        if self.compensation_samples == 0 {
+            1.0
+        } else {
+            self.total_compensation / self.compensation_samples as f32
+        }
+    }
+
+    /// Overall speed correction actually applied by the resampler, as a fraction of 1x
+    pub fn applied_correction(&self) -> f64 {
+        if self.resampled_output_frames == 0 {
+            0.0
+        } else {
+            self.resampled_input_frames / self.resampled_output_frames as f64 - 1.0
         }
     }
 }
+
+/// Synchronization statistics for the whole mix, for showing in a diagnostics panel
+#[derive(Debug)]
+pub struct SyncStats {
+    /// Largest spread seen between the streams' positions, in seconds
+    pub max_drift_seconds: f64,
+    pub streams: Vec<StreamSyncStats>,
+}
+
+/// Synchronization statistics for one stream in the mix
+#[derive(Debug)]
+pub struct StreamSyncStats {
+    pub stream_id: usize,
+    pub max_drift_seconds: f64,
+    pub average_drift_seconds: f64,
+    pub drift_measurements: usize,
+    pub max_compensation: f32,
+    pub average_compensation: f32,
+    pub compensation_count: usize,
+    /// Speed correction the resampler has actually applied, as a fraction of 1x
+    pub applied_correction: f64,
+    pub playback_rate: f32,
+    pub decoded_frames: u64,
+    pub resampled_frames: u64,
+    pub history: Vec<DriftMeasurement>,
+}
+
+impl StreamSyncStats {
+    pub fn new(stream_id: usize, stats: &DriftStats, playback_rate: f32) -> Self {
+        Self {
+            stream_id,
+            max_drift_seconds: stats.max_drift_seconds,
+            average_drift_seconds: stats.average_drift_seconds(),
+            drift_measurements: stats.drift_samples,
+            max_compensation: stats.max_compensation,
+            average_compensation: stats.average_compensation(),
+            compensation_count: stats.compensation_samples,
+            applied_correction: stats.applied_correction(),
+            playback_rate,
+            decoded_frames: stats.decoded_frames,
+            resampled_frames: stats.resampled_output_frames,
+            history: stats.history().copied().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_most_recent_measurements() {
+        let mut stats = DriftStats::new();
+        for check in 0..HISTORY_LENGTH + 5 {
+            stats.record_measurement(DriftMeasurement {
+                time: check as f64,
+                drift_seconds: 0.002,
+                playback_rate: 0.999,
+            });
+        }
+
+        let history: Vec<_> = stats.history().collect();
+        assert_eq!(history.len(), HISTORY_LENGTH);
+        assert_eq!(history[0].time, 5.0);
+        assert_eq!(
+            history[HISTORY_LENGTH - 1].time,
+            (HISTORY_LENGTH + 4) as f64
+        );
+
+        stats.record_decoded(960);
+        stats.record_decoded(960);
+        let summary = StreamSyncStats::new(3, &stats, 1.0);
+        assert_eq!(summary.stream_id, 3);
+        assert_eq!(summary.decoded_frames, 1920);
+        assert_eq!(summary.history.len(), HISTORY_LENGTH);
+        assert_eq!(summary.average_compensation, 1.0);
+    }
+}
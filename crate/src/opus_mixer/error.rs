@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// Error from loading, decoding or mixing streams. The mixer doesn't depend on the browser, so
+/// it has its own error type; the wasm bindings turn it into a JavaScript string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixerError(String);
+
+impl MixerError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl fmt::Display for MixerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MixerError {}
@@ -0,0 +1,150 @@
+use std::cell::{Ref, RefCell};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::rc::Rc;
+
+/// The bytes of an audio file, shared by the decoders reading it. A file that's still arriving
+/// grows in place as more of it is appended, and each handle sees the bytes that had arrived
+/// when it was made, so the file never changes under a decoder.
+#[derive(Clone)]
+pub struct FileData {
+    buffer: Rc<RefCell<Vec<u8>>>,
+    len: usize,
+}
+
+impl FileData {
+    /// Number of bytes visible through this handle
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The bytes visible through this handle. They borrow the shared buffer, so they have to
+    /// be dropped before more of the file is appended.
+    pub fn bytes(&self) -> Ref<'_, [u8]> {
+        let len = self.len;
+        Ref::map(self.buffer.borrow(), |buffer| &buffer[..len])
+    }
+
+    /// Add the next part of the file to the shared buffer. Handles made before it don't see it.
+    pub fn append(&self, data: &[u8]) {
+        self.buffer.borrow_mut().extend_from_slice(data);
+    }
+
+    /// Number of bytes appended to the shared buffer so far
+    pub fn received_len(&self) -> usize {
+        self.buffer.borrow().len()
+    }
+
+    /// A handle that sees all of the bytes appended so far
+    pub fn latest(&self) -> Self {
+        Self {
+            buffer: self.buffer.clone(),
+            len: self.received_len(),
+        }
+    }
+
+    /// Read the visible bytes from the start, for decoders that take a `Read + Seek` source
+    pub fn reader(&self) -> FileReader {
+        FileReader {
+            data: self.clone(),
+            position: 0,
+        }
+    }
+}
+
+impl fmt::Debug for FileData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileData")
+            .field("len", &self.len)
+            .field("received_len", &self.received_len())
+            .finish()
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            len: data.len(),
+            buffer: Rc::new(RefCell::new(data)),
+        }
+    }
+}
+
+/// Reads the bytes visible through a `FileData` handle, like a `Cursor` over a slice
+pub struct FileReader {
+    data: FileData,
+    position: u64,
+}
+
+impl FileReader {
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+}
+
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.data.bytes();
+        let start = usize::try_from(self.position).map_or(bytes.len(), |pos| pos.min(bytes.len()));
+        let count = (&bytes[start..]).read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for FileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::End(offset) => (self.data.len() as u64, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        let position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_keep_their_view_as_the_file_grows() {
+        let data = FileData::from(b"OggS".to_vec());
+        let mut reader = data.reader();
+        data.append(b" more");
+        assert_eq!(&*data.bytes(), b"OggS");
+        assert_eq!(data.received_len(), 9);
+
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"OggS");
+
+        let latest = data.latest();
+        assert_eq!(&*latest.bytes(), b"OggS more");
+        let mut reader = latest.reader();
+        assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap(), 5);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"more");
+        assert!(reader.seek(SeekFrom::Current(-10)).is_err());
+    }
+}
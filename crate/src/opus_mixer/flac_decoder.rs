@@ -0,0 +1,500 @@
+use claxon::frame::FrameReader;
+use claxon::input::BufferedReader;
+use std::convert::TryInto;
+use std::mem;
+
+use crate::debug;
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::{FileData, FileReader};
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::stream_decoder::StreamDecoder;
+
+const BLOCK_STREAMINFO: u8 = 0;
+const BLOCK_SEEKTABLE: u8 = 3;
+const BLOCK_VORBIS_COMMENT: u8 = 4;
+
+// Seek table entries with this sample number are placeholders, reserving space for later
+const PLACEHOLDER_POINT: u64 = u64::MAX;
+
+// Once bisection has narrowed the search down to this many bytes, scan the frames in order
+const SCAN_BYTES: usize = 64 * 1024;
+
+/// The fields of the STREAMINFO block the decoder needs
+#[derive(Debug, Clone, Copy)]
+struct StreamInfo {
+    // Samples per frame; only fixed block size streams number their frames rather than samples
+    block_size: u16,
+    sample_rate: u32,
+    channel_count: usize,
+    bits_per_sample: u32,
+    // 0 if the encoder didn't know the length up front
+    total_samples: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SeekPoint {
+    sample: i64,
+    // Byte offset of the frame starting at `sample`, from the first frame
+    offset: usize,
+}
+
+/// Decoder for FLAC files, using claxon for the frames themselves
+pub struct FlacDecoder {
+    file_data: FileData,
+    frame_reader: FrameReader<BufferedReader<FileReader>>,
+    // Reused between frames, claxon hands it back with each decoded block
+    block_buffer: Vec<i32>,
+    stream_info: StreamInfo,
+    seek_table: Vec<SeekPoint>,
+    tags: Option<OpusTags>,
+    first_frame: usize, // Byte offset of the first frame, after the metadata blocks
+    length: i64,
+    // Sample number of the next frame to be decoded
+    position: i64,
+}
+
+impl FlacDecoder {
+    /// Open a FLAC file, reading the metadata blocks up front
+    pub fn new(file_data: FileData) -> Result<Self, MixerError> {
+        let data = file_data.bytes();
+        if !data.starts_with(b"fLaC") {
+            return Err(invalid("missing fLaC signature"));
+        }
+
+        let mut stream_info = None;
+        let mut seek_table = Vec::new();
+        let mut tags = None;
+
+        let mut pos = 4;
+        loop {
+            let header = data
+                .get(pos..pos + 4)
+                .ok_or_else(|| invalid("metadata runs past the end of the file"))?;
+            let is_last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7f;
+            let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let body = data
+                .get(pos + 4..pos + 4 + size)
+                .ok_or_else(|| invalid("metadata runs past the end of the file"))?;
+
+            match block_type {
+                BLOCK_STREAMINFO => stream_info = Some(parse_stream_info(body)?),
+                BLOCK_SEEKTABLE => seek_table = parse_seek_table(body),
+                BLOCK_VORBIS_COMMENT => {
+                    // Tags are informational, so a malformed block doesn't stop playback
+                    tags = match OpusTags::parse_comments(body) {
+                        Ok(tags) => {
+                            debug!("Stream tags: {:?}", tags);
+                            Some(tags)
+                        }
+                        Err(e) => {
+                            debug!("Ignoring FLAC comments: {:?}", e);
+                            None
+                        }
+                    }
+                }
+                _ => debug!("Skipping FLAC metadata block type {}", block_type),
+            }
+
+            pos += 4 + size;
+            if is_last {
+                break;
+            }
+        }
+
+        let stream_info = stream_info.ok_or_else(|| invalid("no STREAMINFO block"))?;
+        debug!(
+            "FLAC file: {} channels at {}Hz, {} bits per sample, {} samples, {} seek points",
+            stream_info.channel_count,
+            stream_info.sample_rate,
+            stream_info.bits_per_sample,
+            stream_info.total_samples,
+            seek_table.len()
+        );
+        drop(data);
+
+        let mut decoder = Self {
+            frame_reader: frame_reader_at(&file_data, pos),
+            file_data,
+            block_buffer: Vec::new(),
+            stream_info,
+            seek_table,
+            tags,
+            first_frame: pos,
+            length: stream_info.total_samples as i64,
+            position: 0,
+        };
+
+        // Encoders writing to a stream can't fill in the total, so find it from the last frame
+        if decoder.length == 0 {
+            decoder.length = decoder.find_length();
+            debug!("Found FLAC length from the last frame: {}", decoder.length);
+        }
+
+        Ok(decoder)
+    }
+
+    /// Parse the frame header starting at byte `pos`, returning the number of the frame's first
+    /// sample if it's valid. The header's CRC-8 and its agreement with the STREAMINFO block
+    /// rule out sync codes that turn up by chance in the audio data.
+    fn parse_frame_header(&self, pos: usize) -> Option<i64> {
+        let bytes = self.file_data.bytes();
+        let data = &bytes[pos..];
+        let header = data.get(..5)?;
+        if header[0] != 0xff || header[1] & 0xfe != 0xf8 {
+            return None;
+        }
+
+        let variable_block_size = header[1] & 1 != 0;
+        let block_size_code = header[2] >> 4;
+        let sample_rate_code = header[2] & 0x0f;
+        let channel_code = header[3] >> 4;
+        if block_size_code == 0 || sample_rate_code == 15 || header[3] & 1 != 0 {
+            return None;
+        }
+
+        let channel_count = match channel_code {
+            0..=7 => channel_code as usize + 1,
+            8..=10 => 2, // Stereo stored as left/side, side/right or mid/side
+            _ => return None,
+        };
+        if channel_count != self.stream_info.channel_count {
+            return None;
+        }
+
+        // The frame or sample number is coded like a UTF-8 character, the leading ones giving
+        // the number of bytes
+        let (mut number, extra_bytes) = match header[4].leading_ones() {
+            0 => (header[4] as u64, 0),
+            ones @ 2..=7 => ((header[4] & (0x7f >> ones)) as u64, ones as usize - 1),
+            _ => return None,
+        };
+        for index in 0..extra_bytes {
+            let byte = *data.get(5 + index)?;
+            if byte & 0xc0 != 0x80 {
+                return None;
+            }
+            number = (number << 6) | (byte & 0x3f) as u64;
+        }
+
+        // Uncommon block sizes and sample rates are stored at the end of the header
+        let mut len = 5 + extra_bytes;
+        len += match block_size_code {
+            6 => 1,
+            7 => 2,
+            _ => 0,
+        };
+        len += match sample_rate_code {
+            12 => 1,
+            13 | 14 => 2,
+            _ => 0,
+        };
+        if crc8(data.get(..len)?) != *data.get(len)? {
+            return None;
+        }
+
+        Some(if variable_block_size {
+            number as i64
+        } else {
+            number as i64 * self.stream_info.block_size as i64
+        })
+    }
+
+    /// Find the first frame starting between `start` and `end`, returning its byte offset and
+    /// first sample
+    fn find_next_frame(&self, start: usize, end: usize) -> Option<(usize, i64)> {
+        (start..end.min(self.file_data.len()))
+            .find_map(|pos| self.parse_frame_header(pos).map(|sample| (pos, sample)))
+    }
+
+    /// Find the frame with the highest first sample at or before `target`, somewhere between
+    /// the frame at `start` and byte offset `end`. Bisects while the range is large, then scans
+    /// the last stretch frame by frame.
+    fn find_frame_before(&self, target: i64, start: (usize, i64), end: usize) -> (usize, i64) {
+        let mut best = start;
+        let mut right = end;
+
+        while right - best.0 > SCAN_BYTES {
+            let mid = best.0 + (right - best.0) / 2;
+            match self.find_next_frame(mid, right) {
+                Some((offset, sample)) if sample <= target => best = (offset, sample),
+                _ => right = mid,
+            }
+        }
+
+        let mut pos = best.0 + 1;
+        while let Some((offset, sample)) = self.find_next_frame(pos, self.file_data.len()) {
+            if sample > target {
+                break;
+            }
+            if sample > best.1 {
+                best = (offset, sample);
+            }
+            pos = offset + 1;
+        }
+
+        best
+    }
+
+    /// Find where the audio ends by decoding the last frame in the file
+    fn find_length(&self) -> i64 {
+        let mut end = self.file_data.len();
+        while let Some((offset, sample)) = (self.first_frame..end)
+            .rev()
+            .find_map(|pos| self.parse_frame_header(pos).map(|sample| (pos, sample)))
+        {
+            let mut frame_reader = frame_reader_at(&self.file_data, offset);
+            if let Ok(Some(block)) = frame_reader.read_next_or_eof(Vec::new()) {
+                return sample + block.duration() as i64;
+            }
+            end = offset;
+        }
+
+        0
+    }
+}
+
+impl StreamDecoder for FlacDecoder {
+    fn sample_rate(&self) -> u32 {
+        self.stream_info.sample_rate
+    }
+
+    fn channel_count(&self) -> usize {
+        self.stream_info.channel_count
+    }
+
+    fn mapping_family(&self) -> u8 {
+        0
+    }
+
+    fn length(&self) -> i64 {
+        self.length
+    }
+
+    /// STREAMINFO gives the length of the whole file, so find the last frame that's all there
+    fn available_length(&self) -> i64 {
+        self.find_length().min(self.length)
+    }
+
+    fn tags(&self) -> Option<&OpusTags> {
+        self.tags.as_ref()
+    }
+
+    fn decode_next(&mut self, output: &mut Vec<f32>) -> Result<Option<usize>, MixerError> {
+        // Anything after the last frame, like an ID3 tag, isn't audio
+        if self.position >= self.length {
+            return Ok(None);
+        }
+
+        let buffer = mem::take(&mut self.block_buffer);
+        let block = match self
+            .frame_reader
+            .read_next_or_eof(buffer)
+            .map_err(|e| MixerError::new(format!("FLAC decode error: {}", e)))?
+        {
+            Some(block) => block,
+            None => {
+                debug!("End of stream reached");
+                return Ok(None);
+            }
+        };
+
+        let frames = block.duration() as usize;
+        let scale = 1.0 / (1u64 << (self.stream_info.bits_per_sample - 1)) as f32;
+        output.reserve(frames * self.stream_info.channel_count);
+        for frame in 0..frames {
+            for channel in 0..self.stream_info.channel_count {
+                output.push(block.sample(channel as u32, frame as u32) as f32 * scale);
+            }
+        }
+
+        self.position += frames as i64;
+        self.block_buffer = block.into_buffer();
+        Ok(Some(frames))
+    }
+
+    /// Seek to the frame holding `target`. FLAC frames decode independently, so no pre-roll
+    /// is needed. The seek table, if there is one, narrows down the search to the stretch
+    /// between the seek points either side of the target.
+    fn seek(&mut self, target: i64) -> Result<i64, MixerError> {
+        let target = target.max(0);
+        let mut start = (self.first_frame, 0);
+        let mut end = self.file_data.len();
+
+        for point in &self.seek_table {
+            // Ignore seek points that don't land on a frame header
+            let offset = self.first_frame + point.offset;
+            if offset >= self.file_data.len()
+                || self.parse_frame_header(offset) != Some(point.sample)
+            {
+                debug!("Ignoring invalid seek point {:?}", point);
+                continue;
+            }
+
+            if point.sample <= target {
+                start = (offset, point.sample);
+            } else {
+                end = offset;
+                break;
+            }
+        }
+
+        let (offset, sample) = self.find_frame_before(target, start, end);
+        debug!(
+            "Seeking to sample {}, decoding from frame at {} (sample {})",
+            target, offset, sample
+        );
+
+        self.frame_reader = frame_reader_at(&self.file_data, offset);
+        self.position = sample;
+        Ok(sample)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StreamDecoder>, MixerError> {
+        Ok(Box::new(Self::new(self.file_data.clone())?))
+    }
+}
+
+fn frame_reader_at(file_data: &FileData, offset: usize) -> FrameReader<BufferedReader<FileReader>> {
+    let mut reader = file_data.reader();
+    reader.set_position(offset as u64);
+    FrameReader::new(BufferedReader::new(reader))
+}
+
+fn parse_stream_info(body: &[u8]) -> Result<StreamInfo, MixerError> {
+    if body.len() < 18 {
+        return Err(invalid("STREAMINFO block is truncated"));
+    }
+
+    // Sample rate, channels, bits per sample and total samples are packed into 64 bits
+    let packed = u64::from_be_bytes(body[10..18].try_into().unwrap());
+    let stream_info = StreamInfo {
+        block_size: u16::from_be_bytes([body[2], body[3]]),
+        sample_rate: (packed >> 44) as u32,
+        channel_count: ((packed >> 41) & 0x7) as usize + 1,
+        bits_per_sample: ((packed >> 36) & 0x1f) as u32 + 1,
+        total_samples: packed & 0xf_ffff_ffff,
+    };
+
+    if stream_info.channel_count > 2 {
+        return Err(invalid(&format!(
+            "{} channels, only mono and stereo are supported",
+            stream_info.channel_count
+        )));
+    }
+    if stream_info.sample_rate == 0 {
+        return Err(invalid("sample rate is 0"));
+    }
+
+    Ok(stream_info)
+}
+
+fn parse_seek_table(body: &[u8]) -> Vec<SeekPoint> {
+    body.chunks_exact(18)
+        .filter_map(|point| {
+            let sample = u64::from_be_bytes(point[..8].try_into().unwrap());
+            let offset = u64::from_be_bytes(point[8..16].try_into().unwrap());
+            if sample == PLACEHOLDER_POINT {
+                None
+            } else {
+                Some(SeekPoint {
+                    sample: sample as i64,
+                    offset: offset as usize,
+                })
+            }
+        })
+        .collect()
+}
+
+/// CRC-8 of a frame header (polynomial x^8 + x^2 + x + 1)
+pub(crate) fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn invalid(reason: &str) -> MixerError {
+    MixerError::new(format!("Invalid FLAC file: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::test_utils::encode_flac;
+
+    fn ramp(frames: usize, channels: usize) -> Vec<f32> {
+        (0..frames * channels)
+            .map(|i| ((i / channels) % 2000) as f32 / 2000.0 - 0.5)
+            .collect()
+    }
+
+    fn decode_all(decoder: &mut FlacDecoder) -> Vec<f32> {
+        let mut output = Vec::new();
+        while decoder.decode_next(&mut output).unwrap().is_some() {}
+        output
+    }
+
+    #[test]
+    fn decodes_every_sample() {
+        let samples = ramp(10_000, 2);
+        let mut decoder =
+            FlacDecoder::new(encode_flac(&samples, 2, 44100, 1152, None).into()).unwrap();
+
+        assert_eq!(decoder.sample_rate(), 44100);
+        assert_eq!(decoder.channel_count(), 2);
+        assert_eq!(decoder.length(), 10_000);
+
+        let decoded = decode_all(&mut decoder);
+        assert_eq!(decoded.len(), samples.len());
+        for (decoded, expected) in decoded.iter().zip(&samples) {
+            assert!(
+                (decoded - expected).abs() < 1e-4,
+                "{} != {}",
+                decoded,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn seeks_with_and_without_a_seek_table() {
+        let samples = ramp(200_000, 1);
+        for &seek_interval in &[None, Some(16)] {
+            let data = encode_flac(&samples, 1, 48000, 1024, seek_interval);
+            let mut decoder = FlacDecoder::new(data.into()).unwrap();
+
+            for &target in &[150_000, 1000, 199_999, 0] {
+                let start = decoder.seek(target).unwrap();
+                assert_eq!(start, target / 1024 * 1024);
+
+                let mut output = Vec::new();
+                decoder.decode_next(&mut output).unwrap();
+                assert!((output[0] - samples[start as usize]).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn finds_the_length_when_streaminfo_leaves_it_unset() {
+        let mut data = encode_flac(&ramp(5000, 1), 1, 48000, 4096, None);
+
+        // The total is the low 36 bits of the 8 bytes at offset 10 into the STREAMINFO body
+        data[21] &= 0xf0;
+        data[22..26].fill(0);
+
+        let decoder = FlacDecoder::new(data.into()).unwrap();
+        assert_eq!(decoder.length(), 5000);
+    }
+
+    #[test]
+    fn rejects_files_without_streaminfo() {
+        assert!(FlacDecoder::new(b"fLaC\x81\0\0\0".to_vec().into()).is_err());
+        assert!(FlacDecoder::new(b"fLaC".to_vec().into()).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use libm::{cosf, expf, powf, sinf};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::opus_mixer::{CHANNELS, SAMPLE_RATE};
+
+/// Highest true peak level the limiter lets through
+const CEILING_DB: f32 = -1.0;
+
+/// How far ahead the limiter looks, which is also how long it takes to pull the gain down
+const LOOKAHEAD_FRAMES: usize = 240; // 5ms at 48kHz
+
+/// Time constant for the gain to recover once a peak has passed
+const RELEASE_TIME_SECONDS: f32 = 0.1;
+
+/// Peaks between samples are found by interpolating this many points per sample
+const OVERSAMPLING: usize = 4;
+
+/// Taps either side of the interpolation point in the oversampling filter
+const HALF_TAPS: usize = 4;
+
+/// Stereo-linked look-ahead limiter that keeps the true (inter-sample) peak level below the
+/// ceiling.
+///
+/// Each frame's required gain is held for the look-ahead window and then averaged over it,
+/// so the gain ramps down smoothly and reaches the required level by the time the frame
+/// comes out of the delay line.
+#[derive(Debug)]
+pub struct TruePeakLimiter {
+    ceiling: f32,
+    release_coeff: f32,
+    // Interpolation filter for each in-between oversampled position
+    phases: Vec<[f32; HALF_TAPS * 2]>,
+    // The last HALF_TAPS * 2 interleaved input frames, for peak detection
+    history: VecDeque<f32>,
+    // Peak of the interval ending at the frame currently being detected
+    previous_interval_peak: f32,
+    // Increasing (frame, required gain) pairs giving the minimum over the look-ahead window
+    window_min: VecDeque<(u64, f32)>,
+    released_gain: f32,
+    // The last LOOKAHEAD_FRAMES released gains and their sum, for the moving average
+    smoothing: VecDeque<f32>,
+    smoothing_sum: f64,
+    // Interleaved audio waiting for its gain to be known
+    delay: VecDeque<f32>,
+    frame_index: u64,
+}
+
+impl TruePeakLimiter {
+    pub fn new() -> Self {
+        let mut limiter = Self {
+            ceiling: powf(10.0, CEILING_DB / 20.0),
+            release_coeff: expf(-1.0 / (RELEASE_TIME_SECONDS * SAMPLE_RATE as f32)),
+            phases: build_oversampling_filter(),
+            history: VecDeque::new(),
+            previous_interval_peak: 0.0,
+            window_min: VecDeque::new(),
+            released_gain: 1.0,
+            smoothing: VecDeque::new(),
+            smoothing_sum: 0.0,
+            delay: VecDeque::new(),
+            frame_index: 0,
+        };
+        limiter.reset();
+        limiter
+    }
+
+    /// Frames of delay the limiter adds
+    pub fn latency_frames(&self) -> usize {
+        HALF_TAPS + LOOKAHEAD_FRAMES - 1
+    }
+
+    /// Discard all buffered audio and gain state, e.g. after a seek
+    pub fn reset(&mut self) {
+        let channels = CHANNELS as usize;
+
+        self.history.clear();
+        self.history.resize(HALF_TAPS * 2 * channels, 0.0);
+        self.previous_interval_peak = 0.0;
+        self.window_min.clear();
+        self.released_gain = 1.0;
+        self.smoothing.clear();
+        self.smoothing.resize(LOOKAHEAD_FRAMES, 1.0);
+        self.smoothing_sum = LOOKAHEAD_FRAMES as f64;
+        self.delay.clear();
+        self.delay.resize(self.latency_frames() * channels, 0.0);
+        self.frame_index = 0;
+    }
+
+    /// Limit a block of interleaved stereo audio in place. The output lags the input by
+    /// `latency_frames`.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let channels = CHANNELS as usize;
+
+        for frame in samples.chunks_exact_mut(channels) {
+            self.history.drain(..channels);
+            self.history.extend(frame.iter());
+            self.delay.extend(frame.iter());
+
+            // Required gain for the frame HALF_TAPS behind the input
+            let peak = self.detect_peak();
+            let required_gain = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+
+            // Hold the lowest required gain for the whole look-ahead window
+            let index = self.frame_index;
+            self.frame_index += 1;
+            while self
+                .window_min
+                .back()
+                .map_or(false, |&(_, gain)| gain >= required_gain)
+            {
+                self.window_min.pop_back();
+            }
+            self.window_min.push_back((index, required_gain));
+            while self.window_min.front().map_or(false, |&(start, _)| {
+                start + LOOKAHEAD_FRAMES as u64 <= index
+            }) {
+                self.window_min.pop_front();
+            }
+            let held_gain = self.window_min.front().map_or(1.0, |&(_, gain)| gain);
+
+            // Recover slowly once the held gain rises again
+            self.released_gain = if held_gain < self.released_gain {
+                held_gain
+            } else {
+                held_gain + (self.released_gain - held_gain) * self.release_coeff
+            };
+
+            // Averaging over the window turns the held steps into smooth ramps
+            self.smoothing.push_back(self.released_gain);
+            self.smoothing_sum += self.released_gain as f64;
+            self.smoothing_sum -= self.smoothing.pop_front().unwrap_or(1.0) as f64;
+            let gain = (self.smoothing_sum / LOOKAHEAD_FRAMES as f64) as f32;
+
+            for sample in frame.iter_mut() {
+                *sample = self.delay.pop_front().unwrap_or(0.0) * gain;
+            }
+        }
+    }
+
+    /// Peak level around the frame HALF_TAPS behind the newest one, including the
+    /// interpolated points either side of it
+    fn detect_peak(&mut self) -> f32 {
+        let channels = CHANNELS as usize;
+        let taps = HALF_TAPS * 2;
+
+        // The interval between the frame being detected and the one after it
+        let mut interval_peak = 0.0f32;
+        for channel in 0..channels {
+            let sample = |tap: usize| self.history[tap * channels + channel];
+            interval_peak = interval_peak
+                .max(sample(HALF_TAPS - 1).abs())
+                .max(sample(HALF_TAPS).abs());
+
+            for coefficients in &self.phases {
+                let interpolated: f32 = (0..taps).map(|tap| coefficients[tap] * sample(tap)).sum();
+                interval_peak = interval_peak.max(interpolated.abs());
+            }
+        }
+
+        let peak = interval_peak.max(self.previous_interval_peak);
+        self.previous_interval_peak = interval_peak;
+        peak
+    }
+}
+
+/// Windowed sinc coefficients for each oversampled point between the two centre taps
+fn build_oversampling_filter() -> Vec<[f32; HALF_TAPS * 2]> {
+    (1..OVERSAMPLING)
+        .map(|phase| {
+            let fraction = phase as f32 / OVERSAMPLING as f32;
+            let mut coefficients = [0.0; HALF_TAPS * 2];
+
+            for (tap, coefficient) in coefficients.iter_mut().enumerate() {
+                let x = tap as f32 - (HALF_TAPS - 1) as f32 - fraction;
+                let sinc = sinf(PI * x) / (PI * x);
+                let window = 0.5 * (1.0 + cosf(PI * x / HALF_TAPS as f32));
+                *coefficient = sinc * window;
+            }
+
+            // Normalise to unity gain at DC
+            let sum: f32 = coefficients.iter().sum();
+            for coefficient in &mut coefficients {
+                *coefficient /= sum;
+            }
+            coefficients
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    const CHANNEL_COUNT: usize = CHANNELS as usize;
+
+    /// Run interleaved stereo audio through the limiter in mixer-sized blocks
+    fn limit(limiter: &mut TruePeakLimiter, input: &[f32]) -> Vec<f32> {
+        let mut output = input.to_vec();
+        for block in output.chunks_mut(960 * CHANNEL_COUNT) {
+            limiter.process(block);
+        }
+        output
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples
+            .iter()
+            .fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn delays_audio_by_the_reported_latency() {
+        let mut limiter = TruePeakLimiter::new();
+        let latency = limiter.latency_frames();
+
+        // A quiet impulse comes out unchanged, exactly `latency` frames later
+        let mut input = vec![0.0; (latency + 100) * CHANNEL_COUNT];
+        input[10 * CHANNEL_COUNT] = 0.5;
+        input[10 * CHANNEL_COUNT + 1] = -0.25;
+        let output = limit(&mut limiter, &input);
+
+        let at = (10 + latency) * CHANNEL_COUNT;
+        assert_eq!(output[at], 0.5);
+        assert_eq!(output[at + 1], -0.25);
+        assert_eq!(peak(&output[..at]), 0.0);
+        assert_eq!(peak(&output[at + 2..]), 0.0);
+    }
+
+    #[test]
+    fn pulls_a_loud_impulse_down_to_the_ceiling() {
+        let mut limiter = TruePeakLimiter::new();
+        let latency = limiter.latency_frames();
+        let mut input = vec![0.0; (1000 + latency) * CHANNEL_COUNT];
+        input[500 * CHANNEL_COUNT] = 2.0;
+        let output = limit(&mut limiter, &input);
+
+        let ceiling = powf(10.0, CEILING_DB / 20.0);
+        let at = (500 + latency) * CHANNEL_COUNT;
+        assert!(output[at] > 0.0 && output[at] <= ceiling + 1e-6);
+        assert!(peak(&output) <= ceiling + 1e-6);
+    }
+
+    #[test]
+    fn catches_peaks_between_samples() {
+        // A quarter of the sample rate at 45° puts every sample at ±0.707 of the sine's
+        // amplitude, so it peaks at 0dBFS between samples while no sample reaches the ceiling
+        let input: Vec<f32> = (0..9600)
+            .flat_map(|frame| {
+                let sample = sinf(PI / 2.0 * frame as f32 + PI / 4.0);
+                vec![sample; CHANNEL_COUNT]
+            })
+            .collect();
+        let ceiling = powf(10.0, CEILING_DB / 20.0);
+        assert!(peak(&input) < ceiling);
+
+        let output = limit(&mut TruePeakLimiter::new(), &input);
+        let steady = peak(&output[4800 * CHANNEL_COUNT..]);
+        assert!(steady < FRAC_1_SQRT_2 * ceiling * 1.02, "{}", steady);
+    }
+
+    #[test]
+    fn releases_back_to_unity_gain() {
+        let mut limiter = TruePeakLimiter::new();
+        let latency = limiter.latency_frames();
+
+        // A loud burst then a steady quiet level
+        let burst = 480;
+        let frames = burst + 48_000 + latency;
+        let input: Vec<f32> = (0..frames)
+            .flat_map(|frame| vec![if frame < burst { 2.0 } else { 0.5 }; CHANNEL_COUNT])
+            .collect();
+        let output = limit(&mut limiter, &input);
+
+        let gain_at = |frame: usize| output[(frame + latency) * CHANNEL_COUNT] / 0.5;
+
+        // The gain recovers gradually rather than jumping back up
+        let just_after = gain_at(burst + LOOKAHEAD_FRAMES + 240);
+        assert!(just_after < 0.9, "{}", just_after);
+        assert!(gain_at(burst + 4800) > just_after);
+
+        // A second later it's back to unity
+        assert!((gain_at(burst + 47_000) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reset_forgets_buffered_audio_and_gain() {
+        let mut input = vec![0.0; 2000 * CHANNEL_COUNT];
+        input[100 * CHANNEL_COUNT] = 0.5;
+        let expected = limit(&mut TruePeakLimiter::new(), &input);
+
+        // Audio and gain reduction from before the reset don't affect what follows
+        let mut limiter = TruePeakLimiter::new();
+        limit(&mut limiter, &vec![3.0; 1000 * CHANNEL_COUNT]);
+        limiter.reset();
+        assert_eq!(limit(&mut limiter, &input), expected);
+    }
+}
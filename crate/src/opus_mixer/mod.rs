@@ -1,12 +1,35 @@
 pub mod audio_mixer;
 pub mod audio_stream;
-mod drift_stats;
+pub mod downmix;
+pub mod drift_stats;
+pub mod error;
+pub mod file_data;
+mod flac_decoder;
+mod limiter;
+mod multistream_decoder;
+mod ogg_decoder;
+pub mod ogg_opus_writer;
+mod opus_codec;
+mod opus_head;
+pub mod opus_tags;
+mod packet_codec;
+mod resampler;
+pub mod stream_controls;
+pub mod stream_decoder;
+#[cfg(test)]
+mod test_utils;
+mod vorbis_codec;
+mod wav_decoder;
+pub mod wav_writer;
+mod webm_decoder;
 
 // Constants
 pub const SAMPLE_RATE: u32 = 48000; // Opus default sample rate
 pub const CHANNELS: u16 = 2; // STEREO (always use stereo output)
 pub const FRAME_SIZE: usize = 960; // 20ms at 48kHz
 pub const RING_BUFFER_SIZE: usize = FRAME_SIZE * 8; // Store 8 frames worth of samples
+pub const MAX_PACKET_FRAMES: usize = 5760; // 120ms at 48kHz, the longest Opus packet
+pub const PRE_ROLL_SAMPLES: i64 = 3840; // 80ms at 48kHz, decoder convergence time per RFC 7845
 
 // Opus header magic signatures
 const OPUS_HEAD_MAGIC: &[u8] = b"OpusHead";
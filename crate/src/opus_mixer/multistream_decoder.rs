@@ -0,0 +1,394 @@
+use opus::{Channels, Decoder};
+
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::{MAX_PACKET_FRAMES, SAMPLE_RATE};
+
+/// Decoder for Opus multistream packets (RFC 7845 section 5.1.1).
+///
+/// Each packet holds one Opus packet per elementary stream: the coupled (stereo) streams first,
+/// then the mono ones. All but the last use self-delimiting framing (RFC 6716 appendix B).
+/// The decoded channels are rearranged into output order using the header's channel mapping.
+pub struct MultistreamDecoder {
+    // One decoder per elementary stream, coupled streams first
+    decoders: Vec<Decoder>,
+    coupled_count: usize,
+    channel_mapping: Vec<u8>,
+    // Scratch space for a single stream's decoded samples
+    stream_buffer: Vec<f32>,
+}
+
+impl MultistreamDecoder {
+    pub fn new(head: &OpusHead) -> Result<Self, MixerError> {
+        let stream_count = head.stream_count as usize;
+        let coupled_count = head.coupled_count as usize;
+
+        let mut decoders = Vec::with_capacity(stream_count);
+        for stream in 0..stream_count {
+            let channels = if stream < coupled_count {
+                Channels::Stereo
+            } else {
+                Channels::Mono
+            };
+            decoders.push(
+                Decoder::new(SAMPLE_RATE, channels)
+                    .map_err(|e| MixerError::new(format!("Opus decoder error: {}", e)))?,
+            );
+        }
+
+        Ok(Self {
+            decoders,
+            coupled_count,
+            channel_mapping: head.channel_mapping.clone(),
+            stream_buffer: vec![0f32; MAX_PACKET_FRAMES * 2],
+        })
+    }
+
+    fn channel_count(&self) -> usize {
+        self.channel_mapping.len()
+    }
+
+    /// A single stream whose channels are already in output order can be decoded in place
+    fn is_single_stream(&self) -> bool {
+        self.decoders.len() == 1
+            && self
+                .channel_mapping
+                .iter()
+                .enumerate()
+                .all(|(channel, &mapped)| mapped as usize == channel)
+    }
+
+    /// Decode a packet into `output` as interleaved samples in output channel order, returning
+    /// the number of sample frames decoded
+    pub fn decode_float(&mut self, packet: &[u8], output: &mut [f32]) -> Result<usize, MixerError> {
+        if self.is_single_stream() {
+            return self.decoders[0]
+                .decode_float(packet, output, false)
+                .map_err(|e| MixerError::new(format!("Opus decode error: {}", e)));
+        }
+
+        let channel_count = self.channel_count();
+        let last_stream = self.decoders.len() - 1;
+        let mut remaining = packet;
+        let mut frames = None;
+
+        for stream in 0..self.decoders.len() {
+            let stream_packet = if stream < last_stream {
+                let (stream_packet, used) = split_self_delimited(remaining)?;
+                remaining = &remaining[used..];
+                stream_packet
+            } else {
+                remaining.to_vec()
+            };
+
+            let stream_frames = self.decoders[stream]
+                .decode_float(&stream_packet, &mut self.stream_buffer, false)
+                .map_err(|e| {
+                    MixerError::new(format!("Opus decode error in stream {}: {}", stream, e))
+                })?;
+
+            match frames {
+                None => {
+                    frames = Some(stream_frames);
+
+                    // Unmapped channels are silent
+                    output[..stream_frames * channel_count].fill(0.0);
+                }
+                Some(frames) if frames != stream_frames => {
+                    return Err(MixerError::new(format!(
+                        "Multistream packet has streams of {} and {} samples",
+                        frames, stream_frames
+                    )));
+                }
+                Some(_) => {}
+            }
+
+            // Copy this stream's channels to every output channel mapped to them
+            let stream_channels = if stream < self.coupled_count { 2 } else { 1 };
+            for (channel, &mapped) in self.channel_mapping.iter().enumerate() {
+                let source = match self.stream_channel(mapped) {
+                    Some((mapped_stream, source)) if mapped_stream == stream => source,
+                    _ => continue,
+                };
+
+                for frame in 0..stream_frames {
+                    output[frame * channel_count + channel] =
+                        self.stream_buffer[frame * stream_channels + source];
+                }
+            }
+        }
+
+        Ok(frames.unwrap_or(0))
+    }
+
+    /// The (stream, channel within stream) a channel mapping entry refers to, or None if the
+    /// output channel is silent
+    fn stream_channel(&self, mapped: u8) -> Option<(usize, usize)> {
+        let mapped = mapped as usize;
+        if mapped == 255 {
+            None
+        } else if mapped < self.coupled_count * 2 {
+            Some((mapped / 2, mapped % 2))
+        } else {
+            Some((mapped - self.coupled_count, 0))
+        }
+    }
+}
+
+/// Split the self-delimited packet at the front of `data` (RFC 6716 appendix B), returning
+/// it in standard framing along with the number of bytes it took up in `data`
+fn split_self_delimited(data: &[u8]) -> Result<(Vec<u8>, usize), MixerError> {
+    let toc = *data.first().ok_or_else(|| malformed("missing TOC byte"))?;
+    let mut packet = vec![toc];
+    let mut pos = 1;
+    let mut padding = 0;
+
+    let frames_len = match toc & 0x3 {
+        // One frame
+        0 => read_length(data, &mut pos)?,
+        // Two frames of equal size
+        1 => read_length(data, &mut pos)? * 2,
+        // Two frames of different sizes; only the second length is added for self-delimiting
+        2 => {
+            let start = pos;
+            let first = read_length(data, &mut pos)?;
+            packet.extend_from_slice(&data[start..pos]);
+            first + read_length(data, &mut pos)?
+        }
+        // An arbitrary number of frames
+        _ => {
+            let count_byte = *data
+                .get(pos)
+                .ok_or_else(|| malformed("missing frame count"))?;
+            pos += 1;
+            let frame_count = (count_byte & 0x3f) as usize;
+            let vbr = count_byte & 0x80 != 0;
+            if frame_count == 0 {
+                return Err(malformed("zero frame count"));
+            }
+
+            // The padding is skipped rather than copied, so clear the padding flag
+            packet.push(count_byte & !0x40);
+            if count_byte & 0x40 != 0 {
+                loop {
+                    let byte = *data
+                        .get(pos)
+                        .ok_or_else(|| malformed("truncated padding"))?;
+                    pos += 1;
+                    if byte == 255 {
+                        padding += 254;
+                    } else {
+                        padding += byte as usize;
+                        break;
+                    }
+                }
+            }
+
+            if vbr {
+                // All but the last frame length are part of the standard framing too
+                let start = pos;
+                let mut total = 0;
+                for _ in 0..frame_count - 1 {
+                    total += read_length(data, &mut pos)?;
+                }
+                packet.extend_from_slice(&data[start..pos]);
+                total + read_length(data, &mut pos)?
+            } else {
+                read_length(data, &mut pos)? * frame_count
+            }
+        }
+    };
+
+    let end = pos + frames_len;
+    if end + padding > data.len() {
+        return Err(malformed("frames run past the end of the packet"));
+    }
+    packet.extend_from_slice(&data[pos..end]);
+
+    Ok((packet, end + padding))
+}
+
+/// Read a one or two byte frame length (RFC 6716 section 3.2.1), advancing `pos`
+fn read_length(data: &[u8], pos: &mut usize) -> Result<usize, MixerError> {
+    let first = *data
+        .get(*pos)
+        .ok_or_else(|| malformed("truncated frame length"))? as usize;
+    if first < 252 {
+        *pos += 1;
+        Ok(first)
+    } else {
+        let second = *data
+            .get(*pos + 1)
+            .ok_or_else(|| malformed("truncated frame length"))? as usize;
+        *pos += 2;
+        Ok(first + second * 4)
+    }
+}
+
+fn malformed(reason: &str) -> MixerError {
+    MixerError::new(format!("Malformed multistream packet: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::test_utils::{encode_opus_packets, sine};
+    use crate::opus_mixer::FRAME_SIZE;
+
+    const TOC: u8 = 31 << 3; // One 20ms CELT frame per code 0 packet
+
+    /// A frame length as one byte below 252, or two bytes above
+    fn length_bytes(len: usize) -> Vec<u8> {
+        if len < 252 {
+            vec![len as u8]
+        } else {
+            vec![(252 + (len - 252) % 4) as u8, ((len - 252) / 4) as u8]
+        }
+    }
+
+    #[test]
+    fn splits_self_delimited_packets_of_each_frame_code() {
+        // Code 0: one frame, with the next stream's packet after it
+        let data = [TOC, 3, 1, 2, 3, 0xff];
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC, 1, 2, 3], 5)
+        );
+
+        // Code 1: two frames of the length given once
+        let data = [TOC | 1, 2, 1, 2, 3, 4, 0xff];
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC | 1, 1, 2, 3, 4], 6)
+        );
+
+        // Code 2: the first length is kept, the added second one is dropped
+        let data = [TOC | 2, 1, 2, 1, 2, 3, 0xff];
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC | 2, 1, 1, 2, 3], 6)
+        );
+
+        // Code 3 CBR with two bytes of padding, which are skipped along with the flag
+        let data = [TOC | 3, 0x40 | 2, 2, 1, 1, 2, 0, 0, 0xff];
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC | 3, 2, 1, 2], 8)
+        );
+
+        // Code 3 VBR: all but the last length are kept
+        let data = [TOC | 3, 0x80 | 2, 1, 2, 1, 2, 3, 0xff];
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC | 3, 0x80 | 2, 1, 1, 2, 3], 7)
+        );
+
+        // A padding byte of 255 adds 254 and carries on to the next
+        let mut data = vec![TOC | 3, 0x40 | 1, 255, 1, 1, 7];
+        data.extend_from_slice(&[0; 255]);
+        assert_eq!(
+            split_self_delimited(&data).unwrap(),
+            (vec![TOC | 3, 1, 7], data.len())
+        );
+    }
+
+    #[test]
+    fn reads_one_and_two_byte_lengths() {
+        for &len in &[0, 251, 252, 300, 1275] {
+            let mut data = length_bytes(len);
+            data.push(0xff);
+            let mut pos = 0;
+            assert_eq!(read_length(&data, &mut pos).unwrap(), len);
+            assert_eq!(pos, data.len() - 1);
+        }
+
+        let frame: Vec<u8> = (0..300).map(|byte| byte as u8).collect();
+        let mut data = vec![TOC];
+        data.extend(length_bytes(frame.len()));
+        data.extend_from_slice(&frame);
+        let (packet, used) = split_self_delimited(&data).unwrap();
+        assert_eq!(packet[1..], frame[..]);
+        assert_eq!(used, data.len());
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_packets() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[], "missing TOC byte"),
+            (&[TOC], "truncated frame length"),
+            (&[TOC, 252], "truncated frame length"),
+            (&[TOC | 2, 1], "truncated frame length"),
+            (&[TOC | 3], "missing frame count"),
+            (&[TOC | 3, 0], "zero frame count"),
+            (&[TOC | 3, 0x41, 255], "truncated padding"),
+            (&[TOC, 3, 1, 2], "frames run past the end of the packet"),
+            (
+                &[TOC | 3, 0x41, 2, 1, 7, 0],
+                "frames run past the end of the packet",
+            ),
+        ];
+        for (data, reason) in cases {
+            let error = split_self_delimited(data).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Malformed multistream packet: {}", reason)
+            );
+        }
+    }
+
+    /// RMS of one channel of interleaved samples
+    fn rms(samples: &[f32], channels: usize, channel: usize) -> f32 {
+        let frames = samples.len() / channels;
+        let sum: f32 = samples
+            .chunks_exact(channels)
+            .map(|f| f[channel].powi(2))
+            .sum();
+        (sum / frames as f32).sqrt()
+    }
+
+    #[test]
+    fn maps_stream_channels_to_output_channels() {
+        // A coupled stream with audio only on the left, and a mono stream
+        let stereo: Vec<f32> = sine(440.0, 0.2, 1)
+            .into_iter()
+            .flat_map(|sample| vec![sample, 0.0])
+            .collect();
+        let (_, coupled_packets) = encode_opus_packets(&stereo, 2);
+        let (_, mono_packets) = encode_opus_packets(&sine(440.0, 0.2, 1), 1);
+
+        // Output channels: the coupled right, silence, the mono stream, the coupled left
+        let head = OpusHead {
+            version: 1,
+            channel_count: 4,
+            pre_skip: 0,
+            input_sample_rate: SAMPLE_RATE,
+            output_gain: 0,
+            mapping_family: 255,
+            stream_count: 2,
+            coupled_count: 1,
+            channel_mapping: vec![1, 255, 2, 0],
+        };
+        let mut decoder = MultistreamDecoder::new(&head).unwrap();
+        let mut output = vec![0f32; MAX_PACKET_FRAMES * 4];
+
+        let mut decoded = Vec::new();
+        for (coupled, mono) in coupled_packets.iter().zip(&mono_packets) {
+            assert_eq!(coupled[0] & 3, 0, "expected single frame packets");
+            let mut packet = vec![coupled[0]];
+            packet.extend(length_bytes(coupled.len() - 1));
+            packet.extend_from_slice(&coupled[1..]);
+            packet.extend_from_slice(mono);
+
+            let frames = decoder.decode_float(&packet, &mut output).unwrap();
+            assert_eq!(frames, FRAME_SIZE);
+            decoded.extend_from_slice(&output[..frames * 4]);
+        }
+
+        // Skip the encoder's lookahead at the start
+        let steady = &decoded[FRAME_SIZE * 4..];
+        assert!(rms(steady, 4, 0) < 0.01);
+        assert_eq!(rms(steady, 4, 1), 0.0);
+        assert!(rms(steady, 4, 2) > 0.2);
+        assert!(rms(steady, 4, 3) > 0.2);
+    }
+}
@@ -0,0 +1,399 @@
+use ogg::reading::PacketReader;
+use std::convert::TryInto;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::debug;
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::{FileData, FileReader};
+use crate::opus_mixer::opus_codec::OpusCodec;
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::packet_codec::PacketCodec;
+use crate::opus_mixer::stream_decoder::StreamDecoder;
+use crate::opus_mixer::vorbis_codec::{
+    is_vorbis_comment, is_vorbis_ident, is_vorbis_setup, VorbisCodec,
+};
+use crate::opus_mixer::{is_opus_header, is_opus_tags};
+
+// Largest possible Ogg page: 27 byte header + 255 segment lacing values + 255 * 255 bytes of data
+const MAX_PAGE_SIZE: u64 = 27 + 255 + 255 * 255;
+
+/// Decoder for Opus (RFC 7845) or Vorbis audio in an Ogg container
+pub struct OggDecoder {
+    file_data: FileData,
+    packet_reader: PacketReader<FileReader>,
+    codec: Box<dyn PacketCodec>,
+    tags: Option<OpusTags>,
+    // Granule position of the next sample to be decoded
+    current_granule_position: i64,
+    last_granule: i64, // Granule position of the final page in the file, where the audio ends
+}
+
+impl OggDecoder {
+    /// Open an Ogg file, parsing the headers up front so a malformed file fails to load rather
+    /// than to play. The codec is picked from the first header packet.
+    pub fn new(file_data: FileData) -> Result<Self, MixerError> {
+        let last_granule = last_granule_position(&file_data.bytes());
+        let mut packet_reader = PacketReader::new(file_data.reader());
+
+        let first_header = loop {
+            match read_packet(&mut packet_reader)? {
+                Some(packet) if is_opus_header(&packet.data) || is_vorbis_ident(&packet.data) => {
+                    break packet.data;
+                }
+                Some(_) => debug!("Skipping non-header packet while looking for a codec header"),
+                None => {
+                    return Err(MixerError::new(
+                        "No OpusHead or Vorbis identification header found in file",
+                    ))
+                }
+            }
+        };
+
+        let (codec, tags): (Box<dyn PacketCodec>, _) = if is_opus_header(&first_header) {
+            let codec = OpusCodec::new(OpusHead::parse(&first_header)?)?;
+            let tags = find_packet(&mut packet_reader, is_opus_tags, "OpusTags")?;
+            debug!("Found OpusTags packet");
+            (Box::new(codec), parse_tags(&tags[8..]))
+        } else {
+            let tags = find_packet(&mut packet_reader, is_vorbis_comment, "Vorbis comment")?;
+            let setup = find_packet(&mut packet_reader, is_vorbis_setup, "Vorbis setup")?;
+            let codec = VorbisCodec::new(&first_header, &setup)?;
+            (Box::new(codec), parse_tags(&tags[7..]))
+        };
+
+        Ok(Self {
+            packet_reader,
+            file_data,
+            codec,
+            tags,
+            current_granule_position: 0,
+            last_granule,
+        })
+    }
+
+    /// Bisect the file for the page with the highest granule position at or before
+    /// `target_granule`, returning its byte offset and granule position
+    fn find_page_before(&mut self, target_granule: i64) -> Result<Option<(u64, i64)>, MixerError> {
+        let file = self.packet_reader.get_mut();
+        let file_size = file
+            .seek(SeekFrom::End(0))
+            .map_err(|e| MixerError::new(format!("Seek error: {}", e)))?;
+
+        // Initialize bisection search bounds
+        let mut left = 0;
+        let mut right = file_size;
+        let mut best_page = None;
+
+        // Bisection search for the target granule position
+        while right - left > 4096 {
+            // Stop when we're within a page
+            let mid = left + (right - left) / 2;
+
+            let (page_position, granule) = match find_next_page(file, mid, right)? {
+                Some(page) => page,
+                None => {
+                    // No page found after mid, search in first half
+                    right = mid;
+                    continue;
+                }
+            };
+
+            if granule < 0 {
+                // No packet finishes on this page, search in second half
+                left = mid;
+                continue;
+            }
+
+            debug!("Found granule {} at position {}", granule, page_position);
+
+            // Update search bounds based on granule position
+            if granule <= target_granule {
+                left = mid;
+                if best_page.map_or(true, |(_, best_granule)| granule > best_granule) {
+                    best_page = Some((page_position, granule));
+                }
+            } else {
+                right = mid;
+            }
+        }
+
+        Ok(best_page)
+    }
+}
+
+impl StreamDecoder for OggDecoder {
+    fn sample_rate(&self) -> u32 {
+        self.codec.sample_rate()
+    }
+
+    fn channel_count(&self) -> usize {
+        self.codec.channel_count()
+    }
+
+    fn mapping_family(&self) -> u8 {
+        self.codec.mapping_family()
+    }
+
+    /// Granule positions count the pre-skip samples, so the audio ends `pre_skip` samples
+    /// before the final page's granule position
+    fn length(&self) -> i64 {
+        (self.last_granule - self.codec.pre_skip()).max(0)
+    }
+
+    fn tags(&self) -> Option<&OpusTags> {
+        self.tags.as_ref()
+    }
+
+    fn decode_next(&mut self, output: &mut Vec<f32>) -> Result<Option<usize>, MixerError> {
+        let packet = match read_packet(&mut self.packet_reader)? {
+            Some(packet) => packet,
+            None => {
+                debug!("End of stream reached");
+                return Ok(None);
+            }
+        };
+        debug!("Got packet of size: {}", packet.data.len());
+
+        let start = output.len();
+        let decoded_samples = self.codec.decode(&packet.data, output)?;
+
+        // The final page's granule position marks the true end of the audio, anything the
+        // last packet decodes beyond it is padding
+        let decoded_samples = if self.last_granule > 0 {
+            let remaining = (self.last_granule - self.current_granule_position).max(0);
+            if (decoded_samples as i64) > remaining {
+                debug!(
+                    "Trimming {} padding samples from end of stream",
+                    decoded_samples as i64 - remaining
+                );
+            }
+            decoded_samples.min(remaining as usize)
+        } else {
+            decoded_samples
+        };
+        output.truncate(start + decoded_samples * self.codec.channel_count());
+        self.current_granule_position += decoded_samples as i64;
+
+        Ok(Some(decoded_samples))
+    }
+
+    /// Seek using bisection search as specified in RFC 7845, restarting the codec's pre-roll
+    /// before the target
+    fn seek(&mut self, target: i64) -> Result<i64, MixerError> {
+        // Granule positions include the pre-skip, so offset the target by it
+        let pre_skip = self.codec.pre_skip();
+        let target_granule = target.max(0) + pre_skip;
+        let preroll_granule = (target_granule - self.codec.pre_roll()).max(0);
+        debug!(
+            "Seeking to granule position {} ({} samples), decoding from {}",
+            target_granule, target, preroll_granule
+        );
+
+        // Pages with granule position 0 only hold headers, so start from the top for those
+        let previous_packet = match self
+            .find_page_before(preroll_granule)?
+            .filter(|(_, granule)| *granule > 0)
+        {
+            Some((page_position, page_granule)) => {
+                debug!(
+                    "Seeking to page at {} (granule: {})",
+                    page_position, page_granule
+                );
+
+                self.packet_reader
+                    .seek_bytes(SeekFrom::Start(page_position))
+                    .map_err(|e| MixerError::new(format!("Seek error: {}", e)))?;
+
+                // Skip the packets that complete on this page, so decoding resumes exactly at
+                // the page's granule position
+                let mut previous_packet = None;
+                while let Some(packet) = read_packet(&mut self.packet_reader)? {
+                    let last_in_page = packet.last_in_page();
+                    previous_packet = Some(packet.data);
+                    if last_in_page {
+                        break;
+                    }
+                }
+
+                self.current_granule_position = page_granule;
+                previous_packet
+            }
+            None => {
+                debug!("Seeking to start of stream");
+
+                self.packet_reader
+                    .seek_bytes(SeekFrom::Start(0))
+                    .map_err(|e| MixerError::new(format!("Seek error: {}", e)))?;
+
+                // Skip past the headers, which have already been parsed
+                while let Some(packet) = read_packet(&mut self.packet_reader)? {
+                    if self.codec.is_last_header(&packet.data) {
+                        break;
+                    }
+                }
+
+                self.current_granule_position = 0;
+                None
+            }
+        };
+
+        // Reset decoder state
+        self.codec.reset(previous_packet.as_deref())?;
+
+        Ok(self.current_granule_position - pre_skip)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StreamDecoder>, MixerError> {
+        Ok(Box::new(Self::new(self.file_data.clone())?))
+    }
+}
+
+fn read_packet(
+    packet_reader: &mut PacketReader<FileReader>,
+) -> Result<Option<ogg::Packet>, MixerError> {
+    packet_reader
+        .read_packet()
+        .map_err(|e| MixerError::new(format!("Ogg read error: {}", e)))
+}
+
+/// Read packets until one matches `is_wanted`, returning its data
+fn find_packet(
+    packet_reader: &mut PacketReader<FileReader>,
+    is_wanted: fn(&[u8]) -> bool,
+    name: &str,
+) -> Result<Vec<u8>, MixerError> {
+    loop {
+        match read_packet(packet_reader)? {
+            Some(packet) if is_wanted(&packet.data) => return Ok(packet.data),
+            Some(_) => debug!("Skipping packet while looking for {} header", name),
+            None => return Err(MixerError::new(format!("No {} packet found in file", name))),
+        }
+    }
+}
+
+/// Parse the Vorbis comments that follow a comment header's signature. Tags are
+/// informational, so a malformed block doesn't stop playback.
+fn parse_tags(comments: &[u8]) -> Option<OpusTags> {
+    match OpusTags::parse_comments(comments) {
+        Ok(tags) => {
+            debug!("Stream tags: {:?}", tags);
+            Some(tags)
+        }
+        Err(e) => {
+            debug!("Ignoring stream tags: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Find the granule position of the last page in the file that has one
+fn last_granule_position(data: &[u8]) -> i64 {
+    let mut end = data.len();
+    while let Some(offset) = data[..end].windows(4).rposition(|window| window == b"OggS") {
+        // Skip a last page that's cut short, as it is while the file is still loading
+        if page_end(data, offset).map_or(false, |page_end| page_end <= data.len()) {
+            let granule = i64::from_le_bytes(data[offset + 6..offset + 14].try_into().unwrap());
+            if granule >= 0 {
+                return granule;
+            }
+        }
+        end = offset;
+    }
+
+    0
+}
+
+/// Where the page starting at `offset` ends, from the segment table in its header
+fn page_end(data: &[u8], offset: usize) -> Option<usize> {
+    let segment_count = *data.get(offset + 26)? as usize;
+    let segment_table = data.get(offset + 27..offset + 27 + segment_count)?;
+    let body_len: usize = segment_table.iter().map(|&size| size as usize).sum();
+    Some(offset + 27 + segment_count + body_len)
+}
+
+/// Find the first Ogg page starting between `start` and `end`, returning its byte offset and
+/// granule position
+fn find_next_page<R: Read + Seek>(
+    file: &mut R,
+    start: u64,
+    end: u64,
+) -> Result<Option<(u64, i64)>, MixerError> {
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| MixerError::new(format!("Seek error: {}", e)))?;
+
+    // A page must begin within one maximum page size of any position in the stream
+    let window = (end - start).min(MAX_PAGE_SIZE) as usize;
+    let mut buf = vec![0u8; window + 27];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) => return Err(MixerError::new(format!("Read error: {}", e))),
+        }
+    }
+    let buf = &buf[..filled];
+
+    // Sync to next page boundary
+    let offset = match buf.windows(4).position(|window| window == b"OggS") {
+        Some(offset) if offset < window && offset + 27 <= buf.len() => offset,
+        _ => return Ok(None),
+    };
+
+    // Extract granule position (bytes 6-13 of the page header, little endian)
+    let granule = i64::from_le_bytes(buf[offset + 6..offset + 14].try_into().unwrap());
+
+    Ok(Some((start + offset as u64, granule)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    fn ogg_file(packets: Vec<Vec<u8>>) -> FileData {
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        for packet in packets {
+            writer
+                .write_packet(packet, 1, PacketWriteEndInfo::EndPage, 0)
+                .unwrap();
+        }
+        drop(writer);
+        data.into()
+    }
+
+    fn open_error(data: FileData) -> String {
+        OggDecoder::new(data).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn picks_the_codec_from_the_first_header() {
+        let mut ident = b"\x01vorbis".to_vec();
+        ident.extend_from_slice(&0u32.to_le_bytes());
+        ident.push(2);
+        ident.extend_from_slice(&44100u32.to_le_bytes());
+        ident.extend_from_slice(&[0; 12]); // No bitrate hints
+        ident.push(0xb8); // Block sizes of 256 and 2048 samples
+        ident.push(1);
+
+        let mut comments = b"\x03vorbis".to_vec();
+        comments.extend_from_slice(&OpusTags::default().to_packet()[8..]);
+        comments.push(1);
+
+        // The headers are recognised as Vorbis, so it's the setup header that gets rejected
+        let setup = b"\x05vorbis\xff".to_vec();
+        let error = open_error(ogg_file(vec![ident.clone(), comments, setup]));
+        assert!(error.starts_with("Invalid Vorbis header"), "{}", error);
+
+        let error = open_error(ogg_file(vec![ident]));
+        assert_eq!(error, "No Vorbis comment packet found in file");
+
+        let error = open_error(ogg_file(vec![b"fishead\0".to_vec()]));
+        assert_eq!(
+            error,
+            "No OpusHead or Vorbis identification header found in file"
+        );
+    }
+}
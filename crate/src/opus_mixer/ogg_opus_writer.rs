@@ -0,0 +1,187 @@
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels, Encoder};
+
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::{FRAME_SIZE, SAMPLE_RATE};
+
+// Only one logical stream is written, so the serial number doesn't need to be unique
+const SERIAL: u32 = 0x4d697821;
+
+// Audio packets per page; a second per page keeps the overhead low while still giving
+// players somewhere to seek to every second
+const PACKETS_PER_PAGE: usize = 50;
+
+// Largest packet the encoder is allowed to produce, as recommended by the libopus docs
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// Encoder settings for an Ogg Opus export
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpusEncoderSettings {
+    /// Target bitrate in bits per second
+    pub bitrate: i32,
+    /// Encoder complexity from 0 (fastest) to 10 (best quality)
+    pub complexity: i32,
+}
+
+impl OpusEncoderSettings {
+    pub fn new(bitrate: i32, complexity: i32) -> Result<Self, MixerError> {
+        if !(6000..=510_000).contains(&bitrate) {
+            return Err(MixerError::new(format!(
+                "Invalid Opus bitrate {} (expected 6000 to 510000 bits per second)",
+                bitrate
+            )));
+        }
+        if !(0..=10).contains(&complexity) {
+            return Err(MixerError::new(format!(
+                "Invalid Opus complexity {} (expected 0 to 10)",
+                complexity
+            )));
+        }
+
+        Ok(Self {
+            bitrate,
+            complexity,
+        })
+    }
+}
+
+impl Default for OpusEncoderSettings {
+    fn default() -> Self {
+        Self {
+            bitrate: 128_000,
+            complexity: 10,
+        }
+    }
+}
+
+/// Encode interleaved mono or stereo samples at 48kHz as an Ogg Opus file (RFC 7845). The
+/// encoder's lookahead is recorded as the pre-skip, and the last page's granule position marks
+/// the end of the input, so decoding gives back exactly `samples.len()` samples.
+pub fn encode_ogg_opus(
+    samples: &[f32],
+    channels: u8,
+    tags: &OpusTags,
+    settings: OpusEncoderSettings,
+) -> Result<Vec<u8>, MixerError> {
+    let opus_channels = match channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        _ => {
+            return Err(MixerError::new(format!(
+                "Can't encode {} channels of Opus (expected 1 or 2)",
+                channels
+            )))
+        }
+    };
+    let mut encoder =
+        Encoder::new(SAMPLE_RATE, opus_channels, Application::Audio).map_err(encoder_error)?;
+    encoder
+        .set_bitrate(Bitrate::Bits(settings.bitrate))
+        .map_err(encoder_error)?;
+    encoder
+        .set_complexity(settings.complexity)
+        .map_err(encoder_error)?;
+    let pre_skip = encoder.get_lookahead().map_err(encoder_error)? as u16;
+
+    let head = OpusHead {
+        version: 1,
+        channel_count: channels,
+        pre_skip,
+        input_sample_rate: SAMPLE_RATE,
+        output_gain: 0,
+        mapping_family: 0,
+        stream_count: 1,
+        coupled_count: channels - 1,
+        channel_mapping: (0..channels).collect(),
+    };
+
+    // Each header goes on a page of its own, as the spec requires
+    let mut data = Vec::new();
+    let mut writer = PacketWriter::new(&mut data);
+    writer
+        .write_packet(head.to_packet(), SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(write_error)?;
+    writer
+        .write_packet(tags.to_packet(), SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(write_error)?;
+
+    // The decoder discards the first pre_skip samples, so encode that much silence past the end
+    let channels = channels as usize;
+    let total_frames = samples.len() / channels;
+    let end_granule = total_frames + pre_skip as usize;
+    let packet_count = ((end_granule + FRAME_SIZE - 1) / FRAME_SIZE).max(1);
+
+    let mut frame = vec![0f32; FRAME_SIZE * channels];
+    for index in 0..packet_count {
+        let start = (index * FRAME_SIZE * channels).min(samples.len());
+        let chunk = &samples[start..(start + frame.len()).min(samples.len())];
+        frame.fill(0.0);
+        frame[..chunk.len()].copy_from_slice(chunk);
+        let packet = encoder
+            .encode_vec_float(&frame, MAX_PACKET_SIZE)
+            .map_err(encoder_error)?;
+
+        let end_info = if index + 1 == packet_count {
+            PacketWriteEndInfo::EndStream
+        } else if (index + 1) % PACKETS_PER_PAGE == 0 {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        let granule = ((index + 1) * FRAME_SIZE).min(end_granule);
+        writer
+            .write_packet(packet, SERIAL, end_info, granule as u64)
+            .map_err(write_error)?;
+    }
+
+    drop(writer);
+    Ok(data)
+}
+
+fn encoder_error(error: opus::Error) -> MixerError {
+    MixerError::new(format!("Opus encoder error: {}", error))
+}
+
+fn write_error(error: std::io::Error) -> MixerError {
+    MixerError::new(format!("Ogg write error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::audio_stream::AudioStream;
+    use crate::opus_mixer::test_utils::sine;
+
+    #[test]
+    fn round_trips_through_the_decoder() {
+        let tags = OpusTags {
+            vendor: "mixer".to_string(),
+            comments: vec![("TITLE".to_string(), "Bounce".to_string())],
+        };
+        let settings = OpusEncoderSettings::new(96_000, 5).unwrap();
+        let data = encode_ogg_opus(&sine(440.0, 1.25, 2), 2, &tags, settings).unwrap();
+
+        let mut stream = AudioStream::from_bytes(data).unwrap();
+        assert_eq!(stream.duration(), 1.25);
+        assert_eq!(stream.tags(), Some(&tags));
+
+        let mut frames = 0;
+        loop {
+            let produced = stream.read_frames(FRAME_SIZE).unwrap();
+            frames += produced;
+            if produced < FRAME_SIZE {
+                break;
+            }
+        }
+        assert_eq!(frames, 60000);
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        assert!(OpusEncoderSettings::new(1000, 10).is_err());
+        assert!(OpusEncoderSettings::new(64_000, 11).is_err());
+        assert!(OpusEncoderSettings::new(64_000, -1).is_err());
+    }
+}
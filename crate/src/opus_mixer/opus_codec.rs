@@ -0,0 +1,96 @@
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::is_opus_tags;
+use crate::opus_mixer::multistream_decoder::MultistreamDecoder;
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::packet_codec::PacketCodec;
+use crate::opus_mixer::{MAX_PACKET_FRAMES, PRE_ROLL_SAMPLES, SAMPLE_RATE};
+use crate::{debug, log_warn};
+
+/// Opus audio in an Ogg container (RFC 7845)
+pub struct OpusCodec {
+    decoder: MultistreamDecoder,
+    head: OpusHead,
+    decoded_buffer: Vec<f32>,
+    output_gain: f32, // Linear gain to apply to decoded samples, from the file header
+}
+
+impl OpusCodec {
+    pub fn new(head: OpusHead) -> Result<Self, MixerError> {
+        debug!(
+            "Found OpusHead packet: version {}, {} channels, pre-skip {}, input rate {}Hz, output gain {}/256 dB",
+            head.version,
+            head.channel_count,
+            head.pre_skip,
+            head.input_sample_rate,
+            head.output_gain
+        );
+        debug!(
+            "Channel mapping family {}: {} streams, {} coupled, mapping {:?}",
+            head.mapping_family, head.stream_count, head.coupled_count, head.channel_mapping
+        );
+        debug!(
+            "Creating decoder with {} channels in {} streams",
+            head.channel_count, head.stream_count
+        );
+
+        Ok(Self {
+            decoder: MultistreamDecoder::new(&head)?,
+            decoded_buffer: vec![0f32; MAX_PACKET_FRAMES * head.channel_count as usize],
+            output_gain: head.output_gain_linear(),
+            head,
+        })
+    }
+}
+
+impl PacketCodec for OpusCodec {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channel_count(&self) -> usize {
+        self.head.channel_count as usize
+    }
+
+    fn mapping_family(&self) -> u8 {
+        self.head.mapping_family
+    }
+
+    fn pre_skip(&self) -> i64 {
+        self.head.pre_skip as i64
+    }
+
+    fn pre_roll(&self) -> i64 {
+        PRE_ROLL_SAMPLES
+    }
+
+    fn is_last_header(&self, packet: &[u8]) -> bool {
+        is_opus_tags(packet)
+    }
+
+    fn decode(&mut self, packet: &[u8], output: &mut Vec<f32>) -> Result<usize, MixerError> {
+        let decoded_samples = match self.decoder.decode_float(packet, &mut self.decoded_buffer) {
+            Ok(decoded_samples) => decoded_samples,
+            Err(e) => {
+                log_warn!("Skipping a packet that failed to decode: {:?}", e);
+                return Ok(0);
+            }
+        };
+        debug!("Decoded {} samples", decoded_samples);
+
+        let channels = self.head.channel_count as usize;
+        let output_gain = self.output_gain;
+        output.extend(
+            self.decoded_buffer[..decoded_samples * channels]
+                .iter()
+                .map(|sample| sample * output_gain),
+        );
+
+        Ok(decoded_samples)
+    }
+
+    /// Opus converges over the pre-roll, so the previous packet isn't needed
+    fn reset(&mut self, _previous_packet: Option<&[u8]>) -> Result<(), MixerError> {
+        self.decoder = MultistreamDecoder::new(&self.head)?;
+        Ok(())
+    }
+}
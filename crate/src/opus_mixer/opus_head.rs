@@ -0,0 +1,136 @@
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::is_opus_header;
+
+/// The Opus identification header (RFC 7845 section 5.1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpusHead {
+    pub version: u8,
+    pub channel_count: u8,
+    /// Samples (at 48kHz) to discard from the start of the decoded stream
+    pub pre_skip: u16,
+    /// Sample rate of the original input, for information only
+    pub input_sample_rate: u32,
+    /// Gain to apply to the decoded output, in Q7.8 fixed point dB
+    pub output_gain: i16,
+    pub mapping_family: u8,
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    /// Decoded stream channel for each output channel
+    pub channel_mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    pub fn parse(packet: &[u8]) -> Result<Self, MixerError> {
+        if !is_opus_header(packet) {
+            return Err(invalid("missing OpusHead signature"));
+        }
+        if packet.len() < 19 {
+            return Err(invalid(&format!(
+                "header is {} bytes, expected at least 19",
+                packet.len()
+            )));
+        }
+
+        // Only the major version (upper four bits) signals an incompatible format
+        let version = packet[8];
+        if version >> 4 != 0 {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let channel_count = packet[9];
+        if channel_count == 0 {
+            return Err(invalid("channel count is 0"));
+        }
+
+        let pre_skip = u16::from_le_bytes([packet[10], packet[11]]);
+        let input_sample_rate =
+            u32::from_le_bytes([packet[12], packet[13], packet[14], packet[15]]);
+        let output_gain = i16::from_le_bytes([packet[16], packet[17]]);
+        let mapping_family = packet[18];
+
+        let (stream_count, coupled_count, channel_mapping) = if mapping_family == 0 {
+            // Family 0 has an implicit mapping: a single mono or stereo stream
+            if channel_count > 2 {
+                return Err(invalid(&format!(
+                    "mapping family 0 supports 1 or 2 channels, got {}",
+                    channel_count
+                )));
+            }
+            (1, channel_count - 1, (0..channel_count).collect())
+        } else {
+            let table_end = 21 + channel_count as usize;
+            if packet.len() < table_end {
+                return Err(invalid("channel mapping table is truncated"));
+            }
+
+            let stream_count = packet[19];
+            let coupled_count = packet[20];
+            if stream_count == 0 || coupled_count > stream_count {
+                return Err(invalid(&format!(
+                    "{} streams with {} coupled is not a valid layout",
+                    stream_count, coupled_count
+                )));
+            }
+            if mapping_family == 1 && channel_count > 8 {
+                return Err(invalid(&format!(
+                    "mapping family 1 supports up to 8 channels, got {}",
+                    channel_count
+                )));
+            }
+
+            // Each output channel is silent (255) or one of the decoded stream channels
+            let channel_mapping = packet[21..table_end].to_vec();
+            let decoded_channels = stream_count as usize + coupled_count as usize;
+            if let Some(&mapped) = channel_mapping
+                .iter()
+                .find(|&&mapped| mapped != 255 && mapped as usize >= decoded_channels)
+            {
+                return Err(invalid(&format!(
+                    "channel mapping refers to channel {} of {}",
+                    mapped, decoded_channels
+                )));
+            }
+            (stream_count, coupled_count, channel_mapping)
+        };
+
+        Ok(Self {
+            version,
+            channel_count,
+            pre_skip,
+            input_sample_rate,
+            output_gain,
+            mapping_family,
+            stream_count,
+            coupled_count,
+            channel_mapping,
+        })
+    }
+
+    /// Serialise the header as an OpusHead packet
+    pub fn to_packet(&self) -> Vec<u8> {
+        let mut packet = b"OpusHead".to_vec();
+        packet.push(self.version);
+        packet.push(self.channel_count);
+        packet.extend_from_slice(&self.pre_skip.to_le_bytes());
+        packet.extend_from_slice(&self.input_sample_rate.to_le_bytes());
+        packet.extend_from_slice(&self.output_gain.to_le_bytes());
+        packet.push(self.mapping_family);
+
+        // Family 0's mapping is implicit, so it has no mapping table
+        if self.mapping_family != 0 {
+            packet.push(self.stream_count);
+            packet.push(self.coupled_count);
+            packet.extend_from_slice(&self.channel_mapping);
+        }
+        packet
+    }
+
+    /// Output gain as a linear factor to multiply decoded samples by
+    pub fn output_gain_linear(&self) -> f32 {
+        libm::powf(10.0, self.output_gain as f32 / (20.0 * 256.0))
+    }
+}
+
+fn invalid(reason: &str) -> MixerError {
+    MixerError::new(format!("Invalid OpusHead packet: {}", reason))
+}
@@ -0,0 +1,100 @@
+use std::convert::TryInto;
+
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::is_opus_tags;
+
+/// The Opus comment header (RFC 7845 section 5.2), a Vorbis comment block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpusTags {
+    pub vendor: String,
+    /// (KEY, value) pairs in file order. Keys are case-insensitive, so they're stored
+    /// upper case; a key can appear more than once.
+    pub comments: Vec<(String, String)>,
+}
+
+impl OpusTags {
+    pub fn parse(packet: &[u8]) -> Result<Self, MixerError> {
+        if !is_opus_tags(packet) {
+            return Err(invalid("missing OpusTags signature"));
+        }
+
+        Self::parse_comments(&packet[8..])
+    }
+
+    /// Parse a bare Vorbis comment block, as found after the OpusTags signature and in other
+    /// formats' metadata
+    pub fn parse_comments(packet: &[u8]) -> Result<Self, MixerError> {
+        let mut pos = 0;
+        let vendor = String::from_utf8_lossy(read_field(packet, &mut pos)?).into_owned();
+
+        let comment_count = read_u32(packet, &mut pos)?;
+        let mut comments = Vec::new();
+        for _ in 0..comment_count {
+            let comment = String::from_utf8_lossy(read_field(packet, &mut pos)?);
+
+            // Comments without a '=' separator are malformed; skip them rather than fail
+            if let Some((key, value)) = comment.split_once('=') {
+                comments.push((key.to_ascii_uppercase(), value.to_string()));
+            }
+        }
+
+        Ok(Self { vendor, comments })
+    }
+
+    /// Serialise the comments as an OpusTags packet
+    pub fn to_packet(&self) -> Vec<u8> {
+        let mut packet = b"OpusTags".to_vec();
+        write_field(&mut packet, self.vendor.as_bytes());
+        packet.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for (key, value) in &self.comments {
+            write_field(&mut packet, format!("{}={}", key, value).as_bytes());
+        }
+        packet
+    }
+
+    /// The first value for `key`, compared case-insensitively
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.comments
+            .iter()
+            .find(|(comment_key, _)| comment_key.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// R128_TRACK_GAIN in dB: the gain that brings the track to -23 LUFS on top of the
+    /// OpusHead output gain. Stored as a Q7.8 integer.
+    pub fn track_gain_db(&self) -> Option<f32> {
+        self.get("R128_TRACK_GAIN")
+            .and_then(|value| value.trim().parse::<i16>().ok())
+            .map(|gain| gain as f32 / 256.0)
+    }
+}
+
+fn read_u32(packet: &[u8], pos: &mut usize) -> Result<u32, MixerError> {
+    let bytes = packet
+        .get(*pos..*pos + 4)
+        .ok_or_else(|| invalid("truncated length field"))?;
+    *pos += 4;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Read a length-prefixed field, advancing `pos` past it
+fn read_field<'a>(packet: &'a [u8], pos: &mut usize) -> Result<&'a [u8], MixerError> {
+    let len = read_u32(packet, pos)? as usize;
+    let end = pos
+        .checked_add(len)
+        .filter(|&end| end <= packet.len())
+        .ok_or_else(|| invalid("field runs past the end of the packet"))?;
+    let field = &packet[*pos..end];
+    *pos = end;
+    Ok(field)
+}
+
+/// Append a length-prefixed field
+fn write_field(packet: &mut Vec<u8>, field: &[u8]) {
+    packet.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    packet.extend_from_slice(field);
+}
+
+fn invalid(reason: &str) -> MixerError {
+    MixerError::new(format!("Invalid OpusTags packet: {}", reason))
+}
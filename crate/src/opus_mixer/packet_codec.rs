@@ -0,0 +1,30 @@
+use crate::opus_mixer::error::MixerError;
+
+/// A codec whose audio comes in packets, as the Ogg and WebM containers carry it. Positions
+/// (like Ogg granule positions) count samples at the codec's sample rate.
+pub trait PacketCodec {
+    fn sample_rate(&self) -> u32;
+
+    fn channel_count(&self) -> usize;
+
+    fn mapping_family(&self) -> u8;
+
+    /// Samples at the start of the stream that are decoded but aren't part of the audio.
+    /// Positions count them.
+    fn pre_skip(&self) -> i64;
+
+    /// Samples to decode before a seek target so the decoder has converged by the time it
+    /// reaches it
+    fn pre_roll(&self) -> i64;
+
+    /// Whether `packet` is the last of the codec's header packets
+    fn is_last_header(&self, packet: &[u8]) -> bool;
+
+    /// Decode an audio packet, appending interleaved samples to `output` and returning the
+    /// number of sample frames
+    fn decode(&mut self, packet: &[u8], output: &mut Vec<f32>) -> Result<usize, MixerError>;
+
+    /// Reset the decoder state after a seek. `previous_packet` is the packet before the one
+    /// decoding resumes from, if there is one.
+    fn reset(&mut self, previous_packet: Option<&[u8]>) -> Result<(), MixerError>;
+}
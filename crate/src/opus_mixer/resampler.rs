@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+/// Variable-rate resampler used to nudge a stream's playback speed for drift compensation.
+///
+/// Reads interleaved frames from the front of a queue at `rate` input frames per output frame,
+/// using 4-point cubic Hermite interpolation. Rates are expected to stay within a few percent
+/// of 1.0, so no anti-aliasing filter is needed.
+#[derive(Debug)]
+pub struct VariableResampler {
+    channels: usize,
+    // Fractional read position into the input queue, in frames
+    position: f64,
+    // The frame before the front of the queue, needed by the interpolator
+    history: Vec<f32>,
+}
+
+impl VariableResampler {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            channels,
+            position: 0.0,
+            history: vec![0.0; channels],
+        }
+    }
+
+    /// Discard interpolation state, e.g. after a seek
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.history = vec![0.0; self.channels];
+    }
+
+    /// Number of input frames that must be queued to produce `frames` output frames at `rate`
+    pub fn input_frames_needed(&self, frames: usize, rate: f64) -> usize {
+        if self.is_passthrough(rate) {
+            frames
+        } else {
+            // Two extra frames of lookahead for the interpolator
+            (self.position + frames as f64 * rate).ceil() as usize + 2
+        }
+    }
+
+    fn is_passthrough(&self, rate: f64) -> bool {
+        rate == 1.0 && self.position == 0.0
+    }
+
+    /// Produce up to `frames` output frames from `input` into `output`, removing input frames
+    /// that are no longer needed. Returns the number of frames produced, which is only fewer
+    /// than requested if `input` runs out.
+    pub fn process(
+        &mut self,
+        input: &mut VecDeque<f32>,
+        output: &mut Vec<f32>,
+        frames: usize,
+        rate: f64,
+    ) -> usize {
+        let channels = self.channels;
+        let available = input.len() / channels;
+        output.clear();
+
+        // At exactly 1x there's nothing to interpolate, so copy straight through
+        if self.is_passthrough(rate) {
+            let produced = frames.min(available);
+            output.extend(input.drain(..produced * channels));
+            if produced > 0 {
+                let start = output.len() - channels;
+                self.history.copy_from_slice(&output[start..]);
+            }
+            return produced;
+        }
+
+        let mut produced = 0;
+        while produced < frames {
+            let index = self.position as usize;
+            if index + 2 >= available {
+                break;
+            }
+
+            let t = (self.position - index as f64) as f32;
+            for channel in 0..channels {
+                let y0 = if index == 0 {
+                    self.history[channel]
+                } else {
+                    input[(index - 1) * channels + channel]
+                };
+                let y1 = input[index * channels + channel];
+                let y2 = input[(index + 1) * channels + channel];
+                let y3 = input[(index + 2) * channels + channel];
+                output.push(hermite(t, y0, y1, y2, y3));
+            }
+
+            self.position += rate;
+            produced += 1;
+        }
+
+        // Drop the input frames we've moved past, keeping the last one as history
+        let consumed = (self.position as usize).min(available);
+        if consumed > 0 {
+            for channel in 0..channels {
+                self.history[channel] = input[(consumed - 1) * channels + channel];
+            }
+            input.drain(..consumed * channels);
+            self.position -= consumed as f64;
+        }
+
+        produced
+    }
+}
+
+/// 4-point, 3rd-order Hermite interpolation between `y1` and `y2`
+fn hermite(t: f32, y0: f32, y1: f32, y2: f32, y3: f32) -> f32 {
+    let c0 = y1;
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + c0
+}
@@ -0,0 +1,125 @@
+use libm::{cosf, expf, powf, sinf};
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+use crate::opus_mixer::SAMPLE_RATE;
+
+/// Gain range accepted by `set_gain_db`; anything at the minimum is treated as silence
+pub const MIN_GAIN_DB: f32 = -96.0;
+pub const MAX_GAIN_DB: f32 = 24.0;
+
+/// Time constant for parameter smoothing (10ms is fast enough to feel live, slow enough not to click)
+const SMOOTHING_TIME_SECONDS: f32 = 0.01;
+
+/// Gain below which a fading stream can't be heard (-80 dB)
+const SILENCE_GAIN: f32 = 1e-4;
+
+/// Gain, pan, mute and solo state for a single stream
+#[derive(Debug)]
+pub struct StreamControls {
+    gain_db: f32,
+    pan: f32,
+    muted: bool,
+    solo: bool,
+    // Per-channel gains the smoother is moving towards
+    target_left: f32,
+    target_right: f32,
+    // Per-channel gains currently being applied
+    current_left: f32,
+    current_right: f32,
+    smoothing_coeff: f32,
+}
+
+impl StreamControls {
+    pub fn new() -> Self {
+        let mut controls = Self {
+            gain_db: 0.0,
+            pan: 0.0,
+            muted: false,
+            solo: false,
+            target_left: 1.0,
+            target_right: 1.0,
+            current_left: 1.0,
+            current_right: 1.0,
+            smoothing_coeff: expf(-1.0 / (SMOOTHING_TIME_SECONDS * SAMPLE_RATE as f32)),
+        };
+        controls.update_targets(false);
+        controls.current_left = controls.target_left;
+        controls.current_right = controls.target_right;
+        controls
+    }
+
+    pub fn is_solo(&self) -> bool {
+        self.solo
+    }
+
+    /// Set the stream gain in decibels, clamped to [MIN_GAIN_DB, MAX_GAIN_DB]
+    pub fn set_gain_db(&mut self, gain_db: f32) {
+        self.gain_db = if gain_db.is_nan() {
+            0.0
+        } else {
+            gain_db.clamp(MIN_GAIN_DB, MAX_GAIN_DB)
+        };
+    }
+
+    /// Set the stream pan position, from -1.0 (hard left) to 1.0 (hard right)
+    pub fn set_pan(&mut self, pan: f32) {
+        self.pan = if pan.is_nan() {
+            0.0
+        } else {
+            pan.clamp(-1.0, 1.0)
+        };
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn set_solo(&mut self, solo: bool) {
+        self.solo = solo;
+    }
+
+    /// Whether this stream should be heard, given whether any stream in the mix is soloed
+    pub fn is_audible(&self, any_solo: bool) -> bool {
+        !self.muted && (!any_solo || self.solo)
+    }
+
+    /// Recalculate the target channel gains from the current settings.
+    /// Called once per mixed frame; the per-sample smoothing happens in `next_gains`.
+    pub fn update_targets(&mut self, any_solo: bool) {
+        if !self.is_audible(any_solo) || self.gain_db <= MIN_GAIN_DB {
+            self.target_left = 0.0;
+            self.target_right = 0.0;
+            return;
+        }
+
+        let linear_gain = powf(10.0, self.gain_db / 20.0);
+
+        // Equal-power pan law, normalised so the centre position is unity gain
+        let angle = (self.pan + 1.0) * FRAC_PI_4;
+        self.target_left = linear_gain * cosf(angle) * SQRT_2;
+        self.target_right = linear_gain * sinf(angle) * SQRT_2;
+    }
+
+    /// Start the smoother from silence, so a stream joining part way through its audio fades
+    /// in rather than clicking
+    pub fn fade_in(&mut self) {
+        self.current_left = 0.0;
+        self.current_right = 0.0;
+    }
+
+    /// Whether the gains have faded out to silence and are staying there
+    pub fn is_silent(&self) -> bool {
+        self.target_left == 0.0
+            && self.target_right == 0.0
+            && self.current_left.abs() < SILENCE_GAIN
+            && self.current_right.abs() < SILENCE_GAIN
+    }
+
+    /// Advance the smoother by one sample frame and return the (left, right) gains to apply
+    pub fn next_gains(&mut self) -> (f32, f32) {
+        let coeff = self.smoothing_coeff;
+        self.current_left = self.target_left + (self.current_left - self.target_left) * coeff;
+        self.current_right = self.target_right + (self.current_right - self.target_right) * coeff;
+        (self.current_left, self.current_right)
+    }
+}
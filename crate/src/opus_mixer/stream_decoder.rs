@@ -0,0 +1,64 @@
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::FileData;
+use crate::opus_mixer::flac_decoder::FlacDecoder;
+use crate::opus_mixer::ogg_decoder::OggDecoder;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::wav_decoder::WavDecoder;
+use crate::opus_mixer::webm_decoder::WebmDecoder;
+
+/// Decodes one audio file format for an `AudioStream`, which takes care of everything that
+/// doesn't depend on the format: sample rate conversion, downmixing, looping and drift.
+///
+/// Positions are in sample frames at the decoder's own sample rate, counted from the first
+/// frame of audio.
+pub trait StreamDecoder {
+    fn sample_rate(&self) -> u32;
+
+    fn channel_count(&self) -> usize;
+
+    /// Channel mapping family (RFC 7845 section 5.1.1) giving the channel order, used to pick
+    /// the downmix matrix: 0 for mono or stereo, 1 for the Vorbis channel order
+    fn mapping_family(&self) -> u8;
+
+    /// Length of the audio in sample frames
+    fn length(&self) -> i64;
+
+    /// Length of the audio that can be decoded from a file cut short, as it is while it's
+    /// still loading. Formats with the total length in their headers override this.
+    fn available_length(&self) -> i64 {
+        self.length()
+    }
+
+    /// Metadata from the file, if it had any
+    fn tags(&self) -> Option<&OpusTags>;
+
+    /// Decode the next block of audio, appending it to `output` as interleaved samples.
+    /// Returns the number of frames decoded, or None once the end of the file is reached.
+    fn decode_next(&mut self, output: &mut Vec<f32>) -> Result<Option<usize>, MixerError>;
+
+    /// Restart decoding at or before frame `target`, returning the position of the next frame
+    /// that will be decoded. It can be before the start of the audio, if the format needs
+    /// samples decoded and thrown away first.
+    fn seek(&mut self, target: i64) -> Result<i64, MixerError>;
+
+    /// A new decoder for the same file, positioned at the start
+    fn try_clone(&self) -> Result<Box<dyn StreamDecoder>, MixerError>;
+}
+
+/// Open a decoder for the file in `data`, choosing the format from its signature
+pub fn open_decoder(data: FileData) -> Result<Box<dyn StreamDecoder>, MixerError> {
+    let signature = data.bytes().iter().take(12).copied().collect::<Vec<u8>>();
+    if signature.starts_with(b"OggS") {
+        Ok(Box::new(OggDecoder::new(data)?))
+    } else if signature.starts_with(b"fLaC") {
+        Ok(Box::new(FlacDecoder::new(data)?))
+    } else if signature.starts_with(b"RIFF") && signature.get(8..12) == Some(&b"WAVE"[..]) {
+        Ok(Box::new(WavDecoder::new(data)?))
+    } else if signature.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Ok(Box::new(WebmDecoder::new(data)?))
+    } else {
+        Err(MixerError::new(
+            "Unrecognised audio format (expected Ogg Opus, Ogg Vorbis, WebM Opus, FLAC or WAV)",
+        ))
+    }
+}
@@ -0,0 +1,264 @@
+//! Audio files generated in memory for the native tests
+
+use opus::{Application, Channels, Encoder};
+use std::f32::consts::PI;
+
+use crate::opus_mixer::flac_decoder::crc8;
+use crate::opus_mixer::ogg_opus_writer::{self, OpusEncoderSettings};
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::{FRAME_SIZE, SAMPLE_RATE};
+
+// Opus blocks per WebM cluster, so files have several clusters to seek between
+const PACKETS_PER_CLUSTER: usize = 25;
+
+/// Interleaved sine wave at `frequency`, the same in every channel
+pub fn sine(frequency: f32, seconds: f64, channels: u8) -> Vec<f32> {
+    let frames = (seconds * SAMPLE_RATE as f64) as usize;
+    (0..frames)
+        .flat_map(|frame| {
+            let sample = 0.5 * (2.0 * PI * frequency * frame as f32 / SAMPLE_RATE as f32).sin();
+            std::iter::repeat(sample).take(channels as usize)
+        })
+        .collect()
+}
+
+/// Encode interleaved mono or stereo samples as an Ogg Opus file with the exporter's writer
+pub fn encode_ogg_opus(samples: &[f32], channels: u8) -> Vec<u8> {
+    ogg_opus_writer::encode_ogg_opus(
+        samples,
+        channels,
+        &OpusTags {
+            vendor: "test".to_string(),
+            comments: Vec::new(),
+        },
+        OpusEncoderSettings::default(),
+    )
+    .unwrap()
+}
+
+/// Encode interleaved mono or stereo samples as Opus packets of `FRAME_SIZE` frames, returning
+/// the encoder's pre-skip and the packets
+pub fn encode_opus_packets(samples: &[f32], channels: u8) -> (u16, Vec<Vec<u8>>) {
+    let opus_channels = if channels == 1 {
+        Channels::Mono
+    } else {
+        Channels::Stereo
+    };
+    let mut encoder = Encoder::new(SAMPLE_RATE, opus_channels, Application::Audio).unwrap();
+    let pre_skip = encoder.get_lookahead().unwrap() as u16;
+
+    // The decoder discards the first pre_skip frames, so encode that much silence past the end
+    let channels = channels as usize;
+    let total_frames = samples.len() / channels;
+    let packet_count = (total_frames + pre_skip as usize + FRAME_SIZE - 1) / FRAME_SIZE;
+    let mut frame = vec![0f32; FRAME_SIZE * channels];
+    let packets = (0..packet_count)
+        .map(|index| {
+            let start = (index * FRAME_SIZE * channels).min(samples.len());
+            let chunk = &samples[start..(start + frame.len()).min(samples.len())];
+            frame.fill(0.0);
+            frame[..chunk.len()].copy_from_slice(chunk);
+            encoder.encode_vec_float(&frame, 4000).unwrap()
+        })
+        .collect();
+
+    (pre_skip, packets)
+}
+
+/// Encode interleaved mono or stereo samples as a WebM file with one Opus track, in clusters of
+/// `PACKETS_PER_CLUSTER` blocks. The last block's discard padding marks the end of the input.
+///
+/// Files written `live` look like `MediaRecorder` output: the segment and clusters have unknown
+/// sizes and there are no cues. Otherwise cues after the clusters point at every cluster.
+pub fn encode_webm(samples: &[f32], channels: u8, live: bool) -> Vec<u8> {
+    let (pre_skip, packets) = encode_opus_packets(samples, channels);
+    let total_frames = samples.len() / channels as usize;
+    let padding = packets.len() * FRAME_SIZE - total_frames - pre_skip as usize;
+
+    let head = OpusHead {
+        version: 1,
+        channel_count: channels,
+        pre_skip,
+        input_sample_rate: SAMPLE_RATE,
+        output_gain: 0,
+        mapping_family: 0,
+        stream_count: 1,
+        coupled_count: channels - 1,
+        channel_mapping: if channels == 1 { vec![0] } else { vec![0, 1] },
+    };
+
+    let mut data = ebml_element(0x1a45dfa3, &ebml_element(0x4282, b"webm", false), false);
+
+    let mut segment = ebml_element(0x1549a966, &ebml_uint(0x2ad7b1, 1_000_000), false);
+    let audio = ebml_uint(0x9f, channels as u64);
+    let track_entry = [
+        ebml_uint(0xd7, 1),
+        ebml_uint(0x83, 2), // Audio
+        ebml_element(0x86, b"A_OPUS", false),
+        ebml_element(0x63a2, &head.to_packet(), false),
+        ebml_element(0xe1, &audio, false),
+    ]
+    .concat();
+    segment.extend(ebml_element(
+        0x1654ae6b,
+        &ebml_element(0xae, &track_entry, false),
+        false,
+    ));
+
+    // Blocks are 20ms long, in clusters with millisecond timecodes
+    let mut cues = Vec::new();
+    let packet_count = packets.len();
+    for (cluster_index, cluster_packets) in packets.chunks(PACKETS_PER_CLUSTER).enumerate() {
+        let cluster_time = (cluster_index * PACKETS_PER_CLUSTER * 20) as u64;
+        let mut cluster = ebml_uint(0xe7, cluster_time);
+        for (index, packet) in cluster_packets.iter().enumerate() {
+            // Track 1, timecode relative to the cluster, keyframe flag
+            let mut block = vec![0x81];
+            block.extend_from_slice(&((index * 20) as i16).to_be_bytes());
+            block.push(0x80);
+            block.extend_from_slice(packet);
+
+            if cluster_index * PACKETS_PER_CLUSTER + index + 1 == packet_count {
+                // The last block goes in a group to carry its discard padding, in nanoseconds
+                block[3] = 0;
+                let discard_padding = (padding as u64 * 1_000_000_000 + 24_000) / 48_000;
+                let group = [
+                    ebml_element(0xa1, &block, false),
+                    ebml_uint(0x75a2, discard_padding),
+                ]
+                .concat();
+                cluster.extend(ebml_element(0xa0, &group, false));
+            } else {
+                cluster.extend(ebml_element(0xa3, &block, false));
+            }
+        }
+
+        let cue_track_positions =
+            [ebml_uint(0xf7, 1), ebml_uint(0xf1, segment.len() as u64)].concat();
+        let cue_point = [
+            ebml_uint(0xb3, cluster_time),
+            ebml_element(0xb7, &cue_track_positions, false),
+        ]
+        .concat();
+        cues.extend(ebml_element(0xbb, &cue_point, false));
+        segment.extend(ebml_element(0x1f43b675, &cluster, live));
+    }
+
+    if !live {
+        segment.extend(ebml_element(0x1c53bb6b, &cues, false));
+    }
+    data.extend(ebml_element(0x18538067, &segment, live));
+    data
+}
+
+/// An EBML element with an 8 byte size, or the reserved unknown size if `unknown_size`
+fn ebml_element(id: u32, body: &[u8], unknown_size: bool) -> Vec<u8> {
+    let id_bytes = id.to_be_bytes();
+    let id_start = id_bytes.iter().position(|&byte| byte != 0).unwrap();
+    let mut element = id_bytes[id_start..].to_vec();
+    if unknown_size {
+        element.extend_from_slice(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    } else {
+        element.push(0x01);
+        element.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+    }
+    element.extend_from_slice(body);
+    element
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    ebml_element(id, &value.to_be_bytes(), false)
+}
+
+/// Encode interleaved samples as a 16-bit FLAC file of uncompressed (verbatim) frames with a
+/// fixed block size. If `seek_interval` is given, the file has a seek table with a point at
+/// every `seek_interval`th frame.
+pub fn encode_flac(
+    samples: &[f32],
+    channels: u8,
+    sample_rate: u32,
+    block_size: usize,
+    seek_interval: Option<usize>,
+) -> Vec<u8> {
+    let channel_count = channels as usize;
+    let total_frames = samples.len() / channel_count;
+
+    let mut frames = Vec::new();
+    let mut frame_positions = Vec::new();
+    for (index, block) in samples.chunks(block_size * channel_count).enumerate() {
+        let start = frames.len();
+        let block_frames = block.len() / channel_count;
+        frame_positions.push((index * block_size, start, block_frames));
+
+        // Fixed block size, block size at the end of the header, sample rate from STREAMINFO,
+        // independent channels, 16 bits per sample
+        frames.extend_from_slice(&[0xff, 0xf8, 0x70, ((channels - 1) << 4) | 0x08]);
+
+        // Frame numbers are coded the same way as UTF-8 characters
+        let mut number = [0; 4];
+        let number = char::from_u32(index as u32)
+            .unwrap()
+            .encode_utf8(&mut number);
+        frames.extend_from_slice(number.as_bytes());
+        frames.extend_from_slice(&(block_frames as u16 - 1).to_be_bytes());
+        let header_crc = crc8(&frames[start..]);
+        frames.push(header_crc);
+
+        for channel in 0..channel_count {
+            // Verbatim subframe, no wasted bits
+            frames.push(0x02);
+            for frame in 0..block_frames {
+                let sample = (block[frame * channel_count + channel] * 32767.0).round() as i16;
+                frames.extend_from_slice(&sample.to_be_bytes());
+            }
+        }
+
+        let frame_crc = crc16(&frames[start..]);
+        frames.extend_from_slice(&frame_crc.to_be_bytes());
+    }
+
+    let mut data = b"fLaC".to_vec();
+    let last_flag = if seek_interval.is_some() { 0 } else { 0x80 };
+    data.extend_from_slice(&[last_flag, 0, 0, 34]);
+    data.extend_from_slice(&(block_size as u16).to_be_bytes());
+    data.extend_from_slice(&(block_size as u16).to_be_bytes());
+    data.extend_from_slice(&[0; 6]); // Frame sizes unknown
+    let packed =
+        (sample_rate as u64) << 44 | ((channels - 1) as u64) << 41 | 15 << 36 | total_frames as u64;
+    data.extend_from_slice(&packed.to_be_bytes());
+    data.extend_from_slice(&[0; 16]); // No MD5 signature
+
+    if let Some(interval) = seek_interval {
+        let mut points: Vec<(u64, u64, u16)> = frame_positions
+            .iter()
+            .step_by(interval)
+            .map(|&(sample, offset, frames)| (sample as u64, offset as u64, frames as u16))
+            .collect();
+        points.push((u64::MAX, 0, 0)); // Placeholder
+
+        data.push(0x80 | 3);
+        data.extend_from_slice(&((points.len() * 18) as u32).to_be_bytes()[1..]);
+        for (sample, offset, frames) in points {
+            data.extend_from_slice(&sample.to_be_bytes());
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.extend_from_slice(&frames.to_be_bytes());
+        }
+    }
+
+    data.extend_from_slice(&frames);
+    data
+}
+
+/// CRC-16 of a FLAC frame (polynomial x^16 + x^15 + x^2 + 1)
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
@@ -0,0 +1,166 @@
+use lewton::audio::{read_audio_packet_generic, PreviousWindowRight};
+use lewton::header::{read_header_ident, read_header_setup, IdentHeader, SetupHeader};
+use lewton::samples::InterleavedSamples;
+
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::packet_codec::PacketCodec;
+use crate::{debug, log_warn};
+
+// Vorbis header packets start with their type and the codec name
+const VORBIS_IDENT_MAGIC: &[u8] = b"\x01vorbis";
+const VORBIS_COMMENT_MAGIC: &[u8] = b"\x03vorbis";
+const VORBIS_SETUP_MAGIC: &[u8] = b"\x05vorbis";
+
+pub fn is_vorbis_ident(packet: &[u8]) -> bool {
+    packet.starts_with(VORBIS_IDENT_MAGIC)
+}
+
+pub fn is_vorbis_comment(packet: &[u8]) -> bool {
+    packet.starts_with(VORBIS_COMMENT_MAGIC)
+}
+
+pub fn is_vorbis_setup(packet: &[u8]) -> bool {
+    packet.starts_with(VORBIS_SETUP_MAGIC)
+}
+
+/// Vorbis audio in an Ogg container, decoded with lewton
+pub struct VorbisCodec {
+    ident: IdentHeader,
+    setup: SetupHeader,
+    // The second half of the last decoded block, overlapped with the next one
+    window: PreviousWindowRight,
+}
+
+impl VorbisCodec {
+    pub fn new(ident_packet: &[u8], setup_packet: &[u8]) -> Result<Self, MixerError> {
+        let ident = read_header_ident(ident_packet).map_err(header_error)?;
+        let setup = read_header_setup(
+            setup_packet,
+            ident.audio_channels,
+            (ident.blocksize_0, ident.blocksize_1),
+        )
+        .map_err(header_error)?;
+        debug!(
+            "Found Vorbis headers: {} channels at {}Hz, nominal bitrate {}",
+            ident.audio_channels, ident.audio_sample_rate, ident.bitrate_nominal
+        );
+
+        Ok(Self {
+            ident,
+            setup,
+            window: PreviousWindowRight::new(),
+        })
+    }
+
+    fn decode_packet(&mut self, packet: &[u8]) -> Result<Vec<f32>, MixerError> {
+        read_audio_packet_generic::<InterleavedSamples<f32>>(
+            &self.ident,
+            &self.setup,
+            packet,
+            &mut self.window,
+        )
+        .map(|decoded| decoded.samples)
+        .map_err(|e| MixerError::new(format!("Vorbis decode error: {}", e)))
+    }
+}
+
+impl PacketCodec for VorbisCodec {
+    fn sample_rate(&self) -> u32 {
+        self.ident.audio_sample_rate
+    }
+
+    fn channel_count(&self) -> usize {
+        self.ident.audio_channels as usize
+    }
+
+    /// Vorbis defines the channel order that mapping family 1 uses for more than 2 channels
+    fn mapping_family(&self) -> u8 {
+        if self.ident.audio_channels <= 2 {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn pre_skip(&self) -> i64 {
+        0
+    }
+
+    fn pre_roll(&self) -> i64 {
+        0
+    }
+
+    fn is_last_header(&self, packet: &[u8]) -> bool {
+        is_vorbis_setup(packet)
+    }
+
+    fn decode(&mut self, packet: &[u8], output: &mut Vec<f32>) -> Result<usize, MixerError> {
+        let samples = match self.decode_packet(packet) {
+            Ok(samples) => samples,
+            Err(e) => {
+                log_warn!("Skipping a packet that failed to decode: {:?}", e);
+                return Ok(0);
+            }
+        };
+        debug!("Decoded {} samples", samples.len() / self.channel_count());
+
+        output.extend_from_slice(&samples);
+        Ok(samples.len() / self.channel_count())
+    }
+
+    /// Each Vorbis block overlaps the one before, so the first packet after a reset gives no
+    /// audio. Decoding the packet before the seek point fills in the overlap, so decoding
+    /// resumes exactly at the page's granule position.
+    fn reset(&mut self, previous_packet: Option<&[u8]>) -> Result<(), MixerError> {
+        self.window = PreviousWindowRight::new();
+        if let Some(packet) = previous_packet {
+            // At worst the first block after the seek comes out silent, so carry on regardless
+            if let Err(e) = self.decode_packet(packet) {
+                debug!("Couldn't prime the decoder after seeking: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn header_error(error: lewton::header::HeaderReadError) -> MixerError {
+    MixerError::new(format!("Invalid Vorbis header: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::opus_mixer::ogg_decoder::OggDecoder;
+    use crate::opus_mixer::stream_decoder::StreamDecoder;
+
+    // Ten seconds of a mono 44.1kHz beep, from rodio's examples (MIT OR Apache-2.0)
+    const BEEP: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/beep.ogg"));
+
+    fn decode_to_end(decoder: &mut OggDecoder) -> Vec<f32> {
+        let mut output = Vec::new();
+        while decoder.decode_next(&mut output).unwrap().is_some() {}
+        output
+    }
+
+    #[test]
+    fn decodes_and_seeks_in_a_vorbis_file() {
+        let mut decoder = OggDecoder::new(BEEP.to_vec().into()).unwrap();
+        assert_eq!(decoder.sample_rate(), 44100);
+        assert_eq!(decoder.channel_count(), 1);
+        assert_eq!(decoder.length(), 441_000);
+
+        let samples = decode_to_end(&mut decoder);
+        assert_eq!(samples.len(), 441_000);
+        assert!(samples.iter().any(|sample| sample.abs() > 0.1));
+
+        // Decoding resumes from the page before the target, with the same samples
+        for &(target, page) in &[(0, 0), (150_000, 103_104), (350_000, 314_048)] {
+            let position = decoder.seek(target).unwrap();
+            assert_eq!(position, page);
+            let resumed = decode_to_end(&mut decoder);
+            assert_eq!(resumed.len(), samples.len() - position as usize);
+            for (resumed, original) in resumed.iter().zip(&samples[position as usize..]) {
+                assert!((resumed - original).abs() < 1e-4);
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::convert::TryInto;
+
+use crate::debug;
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::FileData;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::stream_decoder::StreamDecoder;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+// Sample frames converted per `decode_next` call
+const DECODE_FRAMES: usize = 4096;
+
+// RIFF INFO chunk IDs and the Vorbis comment keys they're reported under
+const INFO_KEYS: &[(&[u8], &str)] = &[
+    (b"INAM", "TITLE"),
+    (b"IART", "ARTIST"),
+    (b"IPRD", "ALBUM"),
+    (b"IGNR", "GENRE"),
+    (b"ICRD", "DATE"),
+    (b"ICMT", "COMMENT"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> usize {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Int24 => 3,
+            SampleFormat::Int32 | SampleFormat::Float32 => 4,
+            SampleFormat::Float64 => 8,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::Int24 => {
+                // Shift into the top of an i32 so the sign bit lands in the right place
+                i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2_147_483_648.0
+            }
+            SampleFormat::Int32 => {
+                i32::from_le_bytes(bytes[..4].try_into().unwrap()) as f32 / 2_147_483_648.0
+            }
+            SampleFormat::Float32 => f32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            SampleFormat::Float64 => f64::from_le_bytes(bytes[..8].try_into().unwrap()) as f32,
+        }
+    }
+}
+
+/// Decoder for uncompressed mono or stereo WAV files
+#[derive(Debug)]
+pub struct WavDecoder {
+    file_data: FileData,
+    sample_format: SampleFormat,
+    channel_count: usize,
+    sample_rate: u32,
+    tags: Option<OpusTags>,
+    // Byte range of the sample data
+    data_start: usize,
+    data_end: usize,
+    // Frame the next decode starts from
+    position: usize,
+}
+
+impl WavDecoder {
+    pub fn new(file_data: FileData) -> Result<Self, MixerError> {
+        let bytes = file_data.bytes();
+        if !bytes.starts_with(b"RIFF") || bytes.get(8..12) != Some(&b"WAVE"[..]) {
+            return Err(invalid("missing RIFF/WAVE signature"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut tags = None;
+
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            let body_start = pos + 8;
+
+            // Writers that stream to disk can leave the data chunk size unset, so let the last
+            // chunk run to the end of the file
+            let body_end = body_start.saturating_add(size).min(bytes.len());
+            let body = &bytes[body_start..body_end];
+
+            match id {
+                b"fmt " => format = Some(parse_format(body)?),
+                b"data" => data = Some((body_start, body_end)),
+                b"LIST" if body.starts_with(b"INFO") => tags = Some(parse_info(&body[4..])),
+                _ => debug!("Skipping WAV chunk {:?}", String::from_utf8_lossy(id)),
+            }
+
+            // Chunks are padded to an even length
+            pos = body_end + (size & 1);
+        }
+
+        let (sample_format, channel_count, sample_rate) =
+            format.ok_or_else(|| invalid("no fmt chunk"))?;
+        let (data_start, data_end) = data.ok_or_else(|| invalid("no data chunk"))?;
+        debug!(
+            "WAV file: {:?}, {} channels at {}Hz, {} bytes of audio",
+            sample_format,
+            channel_count,
+            sample_rate,
+            data_end - data_start
+        );
+        drop(bytes);
+
+        Ok(Self {
+            file_data,
+            sample_format,
+            channel_count,
+            sample_rate,
+            tags,
+            data_start,
+            data_end,
+            position: 0,
+        })
+    }
+
+    fn frame_bytes(&self) -> usize {
+        self.sample_format.bytes() * self.channel_count
+    }
+}
+
+impl StreamDecoder for WavDecoder {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    fn mapping_family(&self) -> u8 {
+        0
+    }
+
+    fn length(&self) -> i64 {
+        ((self.data_end - self.data_start) / self.frame_bytes()) as i64
+    }
+
+    fn tags(&self) -> Option<&OpusTags> {
+        self.tags.as_ref()
+    }
+
+    fn decode_next(&mut self, output: &mut Vec<f32>) -> Result<Option<usize>, MixerError> {
+        let frames = (self.length() as usize)
+            .saturating_sub(self.position)
+            .min(DECODE_FRAMES);
+        if frames == 0 {
+            return Ok(None);
+        }
+
+        let frame_bytes = self.frame_bytes();
+        let start = self.data_start + self.position * frame_bytes;
+        let file_bytes = self.file_data.bytes();
+        let bytes = &file_bytes[start..start + frames * frame_bytes];
+        let sample_format = self.sample_format;
+        output.extend(
+            bytes
+                .chunks_exact(sample_format.bytes())
+                .map(|sample| sample_format.read(sample)),
+        );
+
+        self.position += frames;
+        Ok(Some(frames))
+    }
+
+    fn seek(&mut self, target: i64) -> Result<i64, MixerError> {
+        self.position = target.max(0).min(self.length()) as usize;
+        Ok(self.position as i64)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StreamDecoder>, MixerError> {
+        Ok(Box::new(Self {
+            file_data: self.file_data.clone(),
+            tags: self.tags.clone(),
+            position: 0,
+            ..*self
+        }))
+    }
+}
+
+/// Parse a fmt chunk, returning the sample format, channel count and sample rate
+fn parse_format(body: &[u8]) -> Result<(SampleFormat, usize, u32), MixerError> {
+    if body.len() < 16 {
+        return Err(invalid("fmt chunk is truncated"));
+    }
+
+    let mut format_tag = u16::from_le_bytes([body[0], body[1]]);
+    let channel_count = u16::from_le_bytes([body[2], body[3]]) as usize;
+    let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+    let bits_per_sample = u16::from_le_bytes([body[14], body[15]]);
+
+    // The extensible format keeps the real format tag at the start of its sub-format GUID
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if body.len() < 26 {
+            return Err(invalid("extensible fmt chunk is truncated"));
+        }
+        format_tag = u16::from_le_bytes([body[24], body[25]]);
+    }
+
+    let sample_format = match (format_tag, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 16) => SampleFormat::Int16,
+        (WAVE_FORMAT_PCM, 24) => SampleFormat::Int24,
+        (WAVE_FORMAT_PCM, 32) => SampleFormat::Int32,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Float32,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => SampleFormat::Float64,
+        _ => {
+            return Err(invalid(&format!(
+                "unsupported sample format {} with {} bits per sample",
+                format_tag, bits_per_sample
+            )))
+        }
+    };
+
+    if channel_count == 0 || channel_count > 2 {
+        return Err(invalid(&format!(
+            "{} channels, only mono and stereo are supported",
+            channel_count
+        )));
+    }
+    if sample_rate == 0 {
+        return Err(invalid("sample rate is 0"));
+    }
+
+    Ok((sample_format, channel_count, sample_rate))
+}
+
+/// Read the text fields of a LIST INFO chunk as Vorbis comments
+fn parse_info(mut body: &[u8]) -> OpusTags {
+    let mut tags = OpusTags::default();
+
+    while body.len() >= 8 {
+        let id = &body[..4];
+        let size = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let end = 8usize.saturating_add(size).min(body.len());
+
+        if let Some((_, key)) = INFO_KEYS.iter().find(|(info_id, _)| *info_id == id) {
+            let value = String::from_utf8_lossy(&body[8..end]);
+            let value = value.trim_end_matches('\0').trim();
+            if !value.is_empty() {
+                tags.comments.push((key.to_string(), value.to_string()));
+            }
+        }
+
+        body = &body[(end + (size & 1)).min(body.len())..];
+    }
+
+    tags
+}
+
+fn invalid(reason: &str) -> MixerError {
+    MixerError::new(format!("Invalid WAV file: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::wav_writer::{encode_wav, WavFormat};
+
+    fn decode_all(decoder: &mut WavDecoder) -> Vec<f32> {
+        let mut output = Vec::new();
+        while decoder.decode_next(&mut output).unwrap().is_some() {}
+        output
+    }
+
+    #[test]
+    fn decodes_every_sample_format() {
+        let samples = [0.0, 0.5, -0.5, -1.0];
+        for &format in &[WavFormat::Pcm16, WavFormat::Pcm24, WavFormat::Float32] {
+            let wav = encode_wav(&samples, 2, 44100, format);
+            let mut decoder = WavDecoder::new(wav.into()).unwrap();
+
+            assert_eq!(decoder.sample_rate(), 44100);
+            assert_eq!(decoder.channel_count(), 2);
+            assert_eq!(decoder.length(), 2);
+            for (decoded, expected) in decode_all(&mut decoder).iter().zip(&samples) {
+                assert!(
+                    (decoded - expected).abs() < 1e-4,
+                    "{} != {}",
+                    decoded,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_info_tags_and_skips_unknown_chunks() {
+        let mut wav = encode_wav(&[0.25; 6], 1, 48000, WavFormat::Pcm16);
+
+        // An odd-length chunk must be followed by a pad byte
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM");
+        info.extend_from_slice(&5u32.to_le_bytes());
+        info.extend_from_slice(b"Bass\0\0");
+        wav.extend_from_slice(b"junk");
+        wav.extend_from_slice(&3u32.to_le_bytes());
+        wav.extend_from_slice(&[1, 2, 3, 0]);
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&info);
+
+        let mut decoder = WavDecoder::new(wav.into()).unwrap();
+        assert_eq!(
+            decoder.tags().and_then(|tags| tags.get("title")),
+            Some("Bass")
+        );
+        assert_eq!(decode_all(&mut decoder).len(), 6);
+    }
+
+    #[test]
+    fn seeks_to_the_exact_frame() {
+        let samples: Vec<f32> = (0..10_000).map(|i| i as f32 / 10_000.0).collect();
+        let mut decoder =
+            WavDecoder::new(encode_wav(&samples, 1, 48000, WavFormat::Float32).into()).unwrap();
+
+        assert_eq!(decoder.seek(7500).unwrap(), 7500);
+        assert_eq!(decode_all(&mut decoder), &samples[7500..]);
+        assert_eq!(decoder.seek(20_000).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        let wav = encode_wav(&[0.0; 6], 3, 48000, WavFormat::Pcm16);
+        assert!(WavDecoder::new(wav.into()).is_err());
+        assert!(WavDecoder::new(b"RIFF\0\0\0\0WAVE".to_vec().into()).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::opus_mixer::error::MixerError;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Sample formats a mix can be rendered to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WavFormat {
+    Pcm16,
+    Pcm24,
+    Float32,
+}
+
+impl WavFormat {
+    pub fn from_name(name: &str) -> Result<Self, MixerError> {
+        match name {
+            "pcm16" => Ok(WavFormat::Pcm16),
+            "pcm24" => Ok(WavFormat::Pcm24),
+            "float32" => Ok(WavFormat::Float32),
+            _ => Err(MixerError::new(format!(
+                "Unknown WAV format: {} (expected pcm16, pcm24 or float32)",
+                name
+            ))),
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Pcm24 => 3,
+            WavFormat::Float32 => 4,
+        }
+    }
+}
+
+/// Encode interleaved samples as a WAV file. Integer formats are clipped to full scale and
+/// rounded to the nearest step.
+pub fn encode_wav(samples: &[f32], channels: u16, sample_rate: u32, format: WavFormat) -> Vec<u8> {
+    let bytes_per_sample = format.bytes_per_sample();
+    let data_size = (samples.len() * bytes_per_sample) as u32;
+    let block_align = channels * bytes_per_sample as u16;
+
+    // Non-PCM formats need the extension size field in the fmt chunk and a fact chunk
+    let is_float = format == WavFormat::Float32;
+    let fmt_size: u32 = if is_float { 18 } else { 16 };
+    let fact_size: u32 = if is_float { 12 } else { 0 };
+    let riff_size = 4 + (8 + fmt_size) + fact_size + (8 + data_size);
+
+    let mut wav = Vec::with_capacity(8 + riff_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&riff_size.to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&fmt_size.to_le_bytes());
+    let format_tag = if is_float {
+        WAVE_FORMAT_IEEE_FLOAT
+    } else {
+        WAVE_FORMAT_PCM
+    };
+    wav.extend_from_slice(&format_tag.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&(bytes_per_sample as u16 * 8).to_le_bytes());
+    if is_float {
+        wav.extend_from_slice(&0u16.to_le_bytes());
+
+        wav.extend_from_slice(b"fact");
+        wav.extend_from_slice(&4u32.to_le_bytes());
+        wav.extend_from_slice(&((samples.len() / channels as usize) as u32).to_le_bytes());
+    }
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for &sample in samples {
+        match format {
+            WavFormat::Pcm16 => {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                wav.extend_from_slice(&value.to_le_bytes());
+            }
+            WavFormat::Pcm24 => {
+                let value = (sample.clamp(-1.0, 1.0) * 8_388_607.0).round() as i32;
+                wav.extend_from_slice(&value.to_le_bytes()[..3]);
+            }
+            WavFormat::Float32 => wav.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(wav: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([wav[pos], wav[pos + 1], wav[pos + 2], wav[pos + 3]])
+    }
+
+    #[test]
+    fn writes_16_bit_pcm() {
+        let wav = encode_wav(&[0.0, 1.0, -1.0, 2.0], 2, 48000, WavFormat::Pcm16);
+
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 24), 48000);
+        assert_eq!(u32_at(&wav, 28), 48000 * 4);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 8);
+        assert_eq!(
+            &wav[44..],
+            &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80, 0xff, 0x7f]
+        );
+    }
+
+    #[test]
+    fn writes_24_bit_pcm() {
+        let wav = encode_wav(&[0.5, -1.0], 1, 44100, WavFormat::Pcm24);
+
+        assert_eq!(u32_at(&wav, 40), 6);
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0x40, 0x01, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn writes_float_with_fact_chunk() {
+        let wav = encode_wav(&[0.25, -0.25, 0.5, -0.5], 2, 48000, WavFormat::Float32);
+
+        assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+        assert_eq!(u32_at(&wav, 16), 18);
+        assert_eq!(&wav[20..22], &[3, 0]);
+        assert_eq!(&wav[38..42], b"fact");
+        assert_eq!(u32_at(&wav, 46), 2);
+        assert_eq!(&wav[50..54], b"data");
+        assert_eq!(&wav[58..62], &0.25f32.to_le_bytes());
+    }
+}
@@ -0,0 +1,752 @@
+use std::convert::TryFrom;
+
+use crate::debug;
+use crate::opus_mixer::error::MixerError;
+use crate::opus_mixer::file_data::FileData;
+use crate::opus_mixer::opus_codec::OpusCodec;
+use crate::opus_mixer::opus_head::OpusHead;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::packet_codec::PacketCodec;
+use crate::opus_mixer::stream_decoder::StreamDecoder;
+use crate::opus_mixer::{PRE_ROLL_SAMPLES, SAMPLE_RATE};
+
+// Matroska element IDs, including their length marker bits
+const EBML: u32 = 0x1a45dfa3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549a966;
+const TIMECODE_SCALE: u32 = 0x2ad7b1;
+const MUXING_APP: u32 = 0x4d80;
+const TRACKS: u32 = 0x1654ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const TAGS: u32 = 0x1254c367;
+const TAG: u32 = 0x7373;
+const SIMPLE_TAG: u32 = 0x67c8;
+const TAG_NAME: u32 = 0x45a3;
+const TAG_STRING: u32 = 0x4487;
+const CUES: u32 = 0x1c53bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+const CLUSTER: u32 = 0x1f43b675;
+const TIMECODE: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const BLOCK_GROUP: u32 = 0xa0;
+const BLOCK: u32 = 0xa1;
+const DISCARD_PADDING: u32 = 0x75a2;
+
+// Timecodes are in milliseconds unless the file says otherwise
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+
+/// An element's ID and where its body lies in the file
+#[derive(Debug, Clone, Copy)]
+struct Element {
+    id: u32,
+    start: usize,
+    body_start: usize,
+    // Where the body ends, None if the size is unknown (as in live recordings)
+    body_end: Option<usize>,
+}
+
+impl Element {
+    /// Where the next element starts when walking the file in order. Segments and clusters
+    /// are entered rather than skipped, so the walk reaches every block.
+    fn next(&self, file_len: usize) -> usize {
+        match (self.id, self.body_end) {
+            (SEGMENT, _) | (CLUSTER, _) | (_, None) => self.body_start,
+            (_, Some(end)) => end.min(file_len),
+        }
+    }
+
+    /// Whether the whole body is in the file, which it may not be while it's still loading
+    fn is_complete(&self, file_len: usize) -> bool {
+        self.body_end.map_or(true, |end| end <= file_len)
+    }
+}
+
+/// A block's frames for the Opus track, with its timestamp converted to samples
+struct Block<'a> {
+    granule: i64,
+    frames: Vec<&'a [u8]>,
+    // Samples to trim from the end of the block's audio
+    discard_padding: i64,
+}
+
+/// Decoder for Opus audio in a WebM or Matroska file, as recorded by `MediaRecorder`
+pub struct WebmDecoder {
+    file_data: FileData,
+    codec: OpusCodec,
+    pre_skip: i64,
+    track_number: u64,
+    timecode_scale: u64, // Nanoseconds per timecode unit
+    tags: Option<OpusTags>,
+    // Where each cue point's cluster starts, sorted by granule position
+    cues: Vec<(i64, usize)>,
+    // Where each cluster starts, for files without cues
+    clusters: Vec<(i64, usize)>,
+    // Granule position where the audio ends, after the last block's discard padding
+    last_granule: i64,
+    // Byte offset to continue walking the file from, and the timecode of the cluster it's in
+    position: usize,
+    cluster_timecode: i64,
+    // Granule position of the next sample to be decoded
+    current_granule_position: i64,
+}
+
+impl WebmDecoder {
+    /// Open a WebM file, reading the track headers and indexing its clusters up front
+    pub fn new(file_data: FileData) -> Result<Self, MixerError> {
+        let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+        let mut muxing_app = String::new();
+        let mut track = None;
+        let mut tags = None;
+        let mut raw_cues = Vec::new();
+        let mut segment_start = 0;
+        let mut clusters = Vec::new();
+        let mut cluster_timecode = 0;
+        let mut last_block = None;
+
+        // Walk the whole file once, reading the metadata wherever it is and noting where each
+        // cluster and the last block of audio are
+        let data = file_data.bytes();
+        let mut pos = 0;
+        while let Some(element) = read_element(&data, pos) {
+            let body = element_body(&data, &element);
+            match element.id {
+                EBML => {
+                    let doc_type = find_child(body, DOC_TYPE).unwrap_or_default();
+                    if doc_type != b"webm" && doc_type != b"matroska" {
+                        return Err(invalid(&format!(
+                            "unsupported document type {:?}",
+                            String::from_utf8_lossy(doc_type)
+                        )));
+                    }
+                }
+                SEGMENT => segment_start = element.body_start,
+                INFO => {
+                    if let Some(scale) = find_child(body, TIMECODE_SCALE) {
+                        timecode_scale = read_uint(scale).max(1);
+                    }
+                    if let Some(app) = find_child(body, MUXING_APP) {
+                        muxing_app = String::from_utf8_lossy(app).into_owned();
+                    }
+                }
+                TRACKS => track = Some(find_opus_track(body)?),
+                TAGS => tags = Some(read_tags(body)),
+                CUES => raw_cues = read_cues(body),
+                CLUSTER => clusters.push((element.start, 0)),
+                TIMECODE => {
+                    cluster_timecode = read_uint(body) as i64;
+                    if let Some(cluster) = clusters.last_mut() {
+                        cluster.1 = cluster_timecode;
+                    }
+                }
+                SIMPLE_BLOCK | BLOCK_GROUP => {
+                    let track_number = track.as_ref().map(|(number, _)| *number);
+                    if track_number.is_some()
+                        && element.is_complete(data.len())
+                        && block_track(body, element.id) == track_number
+                    {
+                        last_block = Some((element, cluster_timecode));
+                    }
+                }
+                _ => {}
+            }
+            pos = element.next(data.len());
+        }
+        drop(data);
+
+        let (track_number, head) = track.ok_or_else(|| invalid("no Opus audio track"))?;
+        let pre_skip = head.pre_skip as i64;
+        let mut decoder = Self {
+            codec: OpusCodec::new(head)?,
+            pre_skip,
+            track_number,
+            timecode_scale,
+            tags: tags.map(|comments| OpusTags {
+                vendor: muxing_app,
+                comments,
+            }),
+            cues: Vec::new(),
+            clusters: Vec::new(),
+            last_granule: 0,
+            position: clusters
+                .first()
+                .map_or(file_data.len(), |cluster| cluster.0),
+            cluster_timecode: 0,
+            current_granule_position: 0,
+            file_data,
+        };
+
+        // Cue positions are relative to the start of the segment; ignore any for other tracks
+        // or that don't point at a cluster
+        decoder.cues = raw_cues
+            .into_iter()
+            .filter(|&(_, cue_track, _)| cue_track == track_number)
+            .map(|(time, _, offset)| (decoder.to_granule(time), segment_start + offset))
+            .filter(|&(_, offset)| {
+                read_element(&decoder.file_data.bytes(), offset).map(|element| element.id)
+                    == Some(CLUSTER)
+            })
+            .collect();
+        decoder.clusters = clusters
+            .into_iter()
+            .map(|(offset, timecode)| (decoder.to_granule(timecode), offset))
+            .collect();
+
+        // The last block's timestamp and length, less its padding, give where the audio ends
+        if let Some((element, timecode)) = last_block {
+            let data = decoder.file_data.bytes();
+            if let Some(block) = decoder.parse_block(&data, &element, timecode) {
+                let samples: usize = block.frames.iter().map(|f| opus_packet_samples(f)).sum();
+                decoder.last_granule = block.granule + samples as i64 - block.discard_padding;
+            }
+        }
+
+        debug!(
+            "WebM file: track {}, {} clusters, {} cue points, ends at granule {}",
+            decoder.track_number,
+            decoder.clusters.len(),
+            decoder.cues.len(),
+            decoder.last_granule
+        );
+        Ok(decoder)
+    }
+
+    /// Convert a timecode to a granule position, which counts samples at 48kHz from the first
+    /// decoded sample, including the pre-skip
+    fn to_granule(&self, timecode: i64) -> i64 {
+        (timecode as f64 * self.timecode_scale as f64 * SAMPLE_RATE as f64 / 1e9).round() as i64
+    }
+
+    /// Read a SimpleBlock or BlockGroup element, if it belongs to the Opus track
+    fn parse_block<'a>(
+        &self,
+        data: &'a [u8],
+        element: &Element,
+        cluster_timecode: i64,
+    ) -> Option<Block<'a>> {
+        let body = element_body(data, element);
+        let (block, discard_padding) = if element.id == BLOCK_GROUP {
+            let discard_padding = find_child(body, DISCARD_PADDING).map_or(0, read_int);
+            (find_child(body, BLOCK)?, discard_padding)
+        } else {
+            (body, 0)
+        };
+
+        if block_track(body, element.id)? != self.track_number {
+            return None;
+        }
+        let (_, len) = read_vint(block, 0)?;
+        let header = block.get(len..len + 3)?;
+        let relative_timecode = i16::from_be_bytes([header[0], header[1]]) as i64;
+        let frames = read_laced_frames(header[2], &block[len + 3..])?;
+
+        Some(Block {
+            granule: self.to_granule(cluster_timecode + relative_timecode),
+            frames,
+            discard_padding: (discard_padding as f64 * SAMPLE_RATE as f64 / 1e9).round() as i64,
+        })
+    }
+
+    /// Walk forward from the current position to the next block of the Opus track, returning
+    /// the element and the timecode of its cluster
+    fn next_block(&mut self) -> Option<(Element, i64)> {
+        let file_data = self.file_data.clone();
+        let data = file_data.bytes();
+        while let Some(element) = read_element(&data, self.position) {
+            self.position = element.next(data.len());
+            match element.id {
+                TIMECODE => {
+                    self.cluster_timecode = read_uint(element_body(&data, &element)) as i64;
+                }
+                SIMPLE_BLOCK | BLOCK_GROUP => {
+                    // The rest of the block hasn't arrived yet
+                    if !element.is_complete(data.len()) {
+                        self.position = element.start;
+                        return None;
+                    }
+                    if self
+                        .parse_block(&data, &element, self.cluster_timecode)
+                        .is_some()
+                    {
+                        return Some((element, self.cluster_timecode));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+impl StreamDecoder for WebmDecoder {
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn channel_count(&self) -> usize {
+        self.codec.channel_count()
+    }
+
+    fn mapping_family(&self) -> u8 {
+        self.codec.mapping_family()
+    }
+
+    /// Timestamps count the pre-skip samples, so the audio ends `pre_skip` samples before the
+    /// end of the last block
+    fn length(&self) -> i64 {
+        (self.last_granule - self.pre_skip).max(0)
+    }
+
+    fn tags(&self) -> Option<&OpusTags> {
+        self.tags.as_ref()
+    }
+
+    fn decode_next(&mut self, output: &mut Vec<f32>) -> Result<Option<usize>, MixerError> {
+        let (element, cluster_timecode) = match self.next_block() {
+            Some(block) => block,
+            None => {
+                debug!("End of stream reached");
+                return Ok(None);
+            }
+        };
+
+        // Keep the file alive separately, as decoding borrows self mutably
+        let file_data = self.file_data.clone();
+        let data = file_data.bytes();
+        let block = match self.parse_block(&data, &element, cluster_timecode) {
+            Some(block) => block,
+            None => return Ok(Some(0)),
+        };
+
+        let start = output.len();
+        let mut decoded_samples = 0;
+        for frame in &block.frames {
+            decoded_samples += self.codec.decode(frame, output)?;
+        }
+
+        // Trim the encoder padding from the end of the last block
+        let decoded_samples =
+            decoded_samples - (block.discard_padding.max(0) as usize).min(decoded_samples);
+        output.truncate(start + decoded_samples * self.codec.channel_count());
+        self.current_granule_position += decoded_samples as i64;
+
+        Ok(Some(decoded_samples))
+    }
+
+    /// Seek to the last block starting before the pre-roll, starting the search from the
+    /// nearest cue point (or cluster, for files without cues)
+    fn seek(&mut self, target: i64) -> Result<i64, MixerError> {
+        // Timestamps include the pre-skip, so offset the target by it
+        let target_granule = target.max(0) + self.pre_skip;
+        let preroll_granule = (target_granule - PRE_ROLL_SAMPLES).max(0);
+
+        let index = if self.cues.is_empty() {
+            &self.clusters
+        } else {
+            &self.cues
+        };
+        let start = index
+            .iter()
+            .take_while(|(granule, _)| *granule <= preroll_granule)
+            .last()
+            .or_else(|| index.first())
+            .map_or(self.file_data.len(), |&(_, offset)| offset);
+        debug!(
+            "Seeking to granule position {} ({} samples), searching from byte {}",
+            target_granule, target, start
+        );
+
+        // Find the last block at or before the pre-roll position, or the first block if they're
+        // all after it
+        self.position = start;
+        let mut resume_from = None;
+        while let Some((element, cluster_timecode)) = self.next_block() {
+            let granule =
+                match self.parse_block(&self.file_data.bytes(), &element, cluster_timecode) {
+                    Some(block) => block.granule,
+                    None => continue,
+                };
+            if granule > preroll_granule && resume_from.is_some() {
+                break;
+            }
+            resume_from = Some((element.start, cluster_timecode, granule));
+            if granule > preroll_granule {
+                break;
+            }
+        }
+
+        match resume_from {
+            Some((offset, cluster_timecode, granule)) => {
+                self.position = offset;
+                self.cluster_timecode = cluster_timecode;
+                self.current_granule_position = granule;
+            }
+            None => {
+                self.position = self.file_data.len();
+                self.current_granule_position = self.last_granule;
+            }
+        }
+
+        // Reset decoder state
+        self.codec.reset(None)?;
+
+        Ok(self.current_granule_position - self.pre_skip)
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StreamDecoder>, MixerError> {
+        Ok(Box::new(Self::new(self.file_data.clone())?))
+    }
+}
+
+/// The track number a SimpleBlock or BlockGroup belongs to
+fn block_track(body: &[u8], id: u32) -> Option<u64> {
+    let block = if id == BLOCK_GROUP {
+        find_child(body, BLOCK)?
+    } else {
+        body
+    };
+    read_vint(block, 0).map(|(track_number, _)| track_number)
+}
+
+/// Read an EBML variable length integer at `pos`, returning its value (without the length
+/// marker) and its length in bytes
+fn read_vint(data: &[u8], pos: usize) -> Option<(u64, usize)> {
+    let first = *data.get(pos)?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+
+    let bytes = data.get(pos..pos + len)?;
+    let value = bytes[1..]
+        .iter()
+        .fold(first as u64 & (0xff >> len), |value, &byte| {
+            (value << 8) | byte as u64
+        });
+    Some((value, len))
+}
+
+/// Read the element header at `pos`
+fn read_element(data: &[u8], pos: usize) -> Option<Element> {
+    // IDs keep their length marker, and are at most 4 bytes long
+    let first = *data.get(pos)?;
+    let id_len = first.leading_zeros() as usize + 1;
+    if id_len > 4 {
+        return None;
+    }
+    let id = data
+        .get(pos..pos + id_len)?
+        .iter()
+        .fold(0, |id, &byte| (id << 8) | byte as u32);
+
+    let (size, size_len) = read_vint(data, pos + id_len)?;
+    let body_start = pos + id_len + size_len;
+
+    // A size of all ones means unknown, which live recordings use for segments and clusters
+    let unknown_size = size == (1 << (7 * size_len)) - 1;
+    let body_end = if unknown_size {
+        None
+    } else {
+        Some(body_start.checked_add(size as usize)?)
+    };
+
+    Some(Element {
+        id,
+        start: pos,
+        body_start,
+        body_end,
+    })
+}
+
+/// The element's body, cut short if the file is
+fn element_body<'a>(data: &'a [u8], element: &Element) -> &'a [u8] {
+    let end = element.body_end.unwrap_or(data.len()).min(data.len());
+    &data[element.body_start.min(end)..end]
+}
+
+/// The direct children of a master element's body, as (ID, body) pairs
+fn children(body: &[u8]) -> Vec<(u32, &[u8])> {
+    let mut children = Vec::new();
+    let mut pos = 0;
+    while let Some(element) = read_element(body, pos) {
+        let child_body = element_body(body, &element);
+        children.push((element.id, child_body));
+        match element.body_end {
+            Some(end) if end > pos => pos = end,
+            _ => break,
+        }
+    }
+    children
+}
+
+fn find_child(body: &[u8], id: u32) -> Option<&[u8]> {
+    children(body)
+        .into_iter()
+        .find(|(child_id, _)| *child_id == id)
+        .map(|(_, child)| child)
+}
+
+fn read_uint(body: &[u8]) -> u64 {
+    body.iter()
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn read_int(body: &[u8]) -> i64 {
+    // Sign extend from the top bit of the first byte
+    let negative = body.first().map_or(false, |&byte| byte & 0x80 != 0);
+    body.iter()
+        .fold(if negative { -1 } else { 0 }, |value, &byte| {
+            (value << 8) | byte as i64
+        })
+}
+
+/// Find the first Opus audio track, returning its number and header
+fn find_opus_track(tracks: &[u8]) -> Result<(u64, OpusHead), MixerError> {
+    for (id, entry) in children(tracks) {
+        if id != TRACK_ENTRY || find_child(entry, CODEC_ID) != Some(&b"A_OPUS"[..]) {
+            continue;
+        }
+
+        let track_number = find_child(entry, TRACK_NUMBER).map_or(0, read_uint);
+        let head = find_child(entry, CODEC_PRIVATE)
+            .ok_or_else(|| invalid("Opus track has no OpusHead"))?;
+        return Ok((track_number, OpusHead::parse(head)?));
+    }
+
+    Err(invalid("no Opus audio track"))
+}
+
+/// Read the top-level simple tags as Vorbis comment style (KEY, value) pairs
+fn read_tags(tags: &[u8]) -> Vec<(String, String)> {
+    let mut comments = Vec::new();
+    for (_, tag) in children(tags).into_iter().filter(|(id, _)| *id == TAG) {
+        for (_, simple_tag) in children(tag)
+            .into_iter()
+            .filter(|(id, _)| *id == SIMPLE_TAG)
+        {
+            if let (Some(name), Some(value)) = (
+                find_child(simple_tag, TAG_NAME),
+                find_child(simple_tag, TAG_STRING),
+            ) {
+                comments.push((
+                    String::from_utf8_lossy(name).to_ascii_uppercase(),
+                    String::from_utf8_lossy(value).into_owned(),
+                ));
+            }
+        }
+    }
+    comments
+}
+
+/// Read the cue points as (timecode, track, cluster offset from the segment start)
+fn read_cues(cues: &[u8]) -> Vec<(i64, u64, usize)> {
+    let mut points = Vec::new();
+    for (_, point) in children(cues)
+        .into_iter()
+        .filter(|(id, _)| *id == CUE_POINT)
+    {
+        let time = find_child(point, CUE_TIME).map_or(0, read_uint) as i64;
+        for (_, position) in children(point)
+            .into_iter()
+            .filter(|(id, _)| *id == CUE_TRACK_POSITIONS)
+        {
+            if let (Some(track), Some(offset)) = (
+                find_child(position, CUE_TRACK),
+                find_child(position, CUE_CLUSTER_POSITION),
+            ) {
+                points.push((time, read_uint(track), read_uint(offset) as usize));
+            }
+        }
+    }
+    points.sort_by_key(|&(time, _, _)| time);
+    points
+}
+
+/// Split a block's data into frames according to the lacing in its flags
+fn read_laced_frames(flags: u8, data: &[u8]) -> Option<Vec<&[u8]>> {
+    let lacing = (flags >> 1) & 3;
+    if lacing == 0 {
+        return Some(vec![data]);
+    }
+
+    let count = *data.first()? as usize + 1;
+    let mut pos = 1;
+    let mut sizes = Vec::with_capacity(count);
+    match lacing {
+        // Xiph lacing: each size is a run of 255s and a final byte less than 255
+        1 => {
+            for _ in 0..count - 1 {
+                let mut size = 0;
+                loop {
+                    let byte = *data.get(pos)?;
+                    pos += 1;
+                    size += byte as usize;
+                    if byte < 255 {
+                        break;
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        // Fixed-size lacing: the frames split the data evenly
+        2 => {
+            let size = (data.len() - 1) / count;
+            sizes.resize(count - 1, size);
+        }
+        // EBML lacing: the first size, then signed differences from the previous size
+        _ => {
+            for index in 0..count - 1 {
+                let (raw, len) = read_vint(data, pos)?;
+                pos += len;
+                let size = if index == 0 {
+                    raw as i64
+                } else {
+                    let bias = (1i64 << (7 * len - 1)) - 1;
+                    *sizes.last()? as i64 + raw as i64 - bias
+                };
+                sizes.push(usize::try_from(size).ok()?);
+            }
+        }
+    }
+
+    // The last frame takes whatever is left
+    let mut frames = Vec::with_capacity(count);
+    for size in sizes {
+        frames.push(data.get(pos..pos + size)?);
+        pos += size;
+    }
+    frames.push(data.get(pos..)?);
+    Some(frames)
+}
+
+/// Number of samples at 48kHz in an Opus packet, from its TOC byte (RFC 6716 section 3.1)
+fn opus_packet_samples(packet: &[u8]) -> usize {
+    let toc = match packet.first() {
+        Some(&toc) => toc,
+        None => return 0,
+    };
+
+    let config = (toc >> 3) as usize;
+    let frame_size = match config {
+        0..=11 => [480, 960, 1920, 2880][config % 4], // SILK
+        12..=15 => [480, 960][config % 2],            // Hybrid
+        _ => [120, 240, 480, 960][config % 4],        // CELT
+    };
+    let frame_count = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |&count| (count & 0x3f) as usize),
+    };
+
+    frame_size * frame_count
+}
+
+fn invalid(reason: &str) -> MixerError {
+    MixerError::new(format!("Invalid WebM file: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opus_mixer::test_utils::{encode_webm, sine};
+    use crate::opus_mixer::FRAME_SIZE;
+
+    fn decode_all(decoder: &mut WebmDecoder) -> Vec<f32> {
+        let mut output = Vec::new();
+        while decoder.decode_next(&mut output).unwrap().is_some() {}
+        output
+    }
+
+    #[test]
+    fn decodes_files_with_and_without_cues() {
+        for &live in &[false, true] {
+            let data = encode_webm(&sine(440.0, 1.5, 2), 2, live);
+            let mut decoder = WebmDecoder::new(data.into()).unwrap();
+
+            assert_eq!(decoder.channel_count(), 2);
+            assert_eq!(decoder.length(), 72_000);
+            assert_eq!(decoder.cues.is_empty(), live);
+
+            // Decoding from the start gives the pre-skip then exactly the encoded audio
+            let start = decoder.seek(0).unwrap();
+            assert_eq!(start, -decoder.pre_skip);
+            let decoded = decode_all(&mut decoder);
+            assert_eq!(decoded.len() as i64, (decoder.length() - start) * 2);
+        }
+    }
+
+    #[test]
+    fn seeks_to_the_block_before_the_pre_roll() {
+        for &live in &[false, true] {
+            let data = encode_webm(&sine(440.0, 3.0, 1), 1, live);
+            let mut decoder = WebmDecoder::new(data.into()).unwrap();
+            let pre_skip = decoder.pre_skip;
+
+            for &target in &[100_000, 1000, 143_000, 0, 60_000] {
+                let start = decoder.seek(target).unwrap();
+                let preroll_granule = (target + pre_skip - PRE_ROLL_SAMPLES).max(0);
+                let frame_size = FRAME_SIZE as i64;
+                assert_eq!(start, preroll_granule / frame_size * frame_size - pre_skip);
+
+                let mut output = Vec::new();
+                assert_eq!(decoder.decode_next(&mut output).unwrap(), Some(FRAME_SIZE));
+            }
+        }
+    }
+
+    #[test]
+    fn splits_laced_blocks() {
+        let data = [2, 3, 1, 0xaa, 0xaa, 0xaa, 0xbb, 0xcc, 0xcc];
+        let expected: Vec<&[u8]> = vec![&[0xaa; 3], &[0xbb], &[0xcc; 2]];
+
+        // Xiph lacing
+        assert_eq!(read_laced_frames(0x02, &data).unwrap(), expected);
+
+        // EBML lacing: 3, then a difference of -2 (biased by 63)
+        let data = [2, 0x83, 0x80 | 61, 0xaa, 0xaa, 0xaa, 0xbb, 0xcc, 0xcc];
+        assert_eq!(read_laced_frames(0x06, &data).unwrap(), expected);
+
+        // Fixed-size lacing
+        let data = [1, 0xaa, 0xaa, 0xbb, 0xbb];
+        let expected: Vec<&[u8]> = vec![&[0xaa; 2], &[0xbb; 2]];
+        assert_eq!(read_laced_frames(0x04, &data).unwrap(), expected);
+
+        // A lace of one frame has no sizes, whichever lacing it uses
+        let expected: Vec<&[u8]> = vec![&[0xaa, 0xbb]];
+        for &flags in &[0x02, 0x04, 0x06] {
+            assert_eq!(
+                read_laced_frames(flags, &[0, 0xaa, 0xbb]).unwrap(),
+                expected
+            );
+        }
+
+        assert!(read_laced_frames(0x02, &[2, 255]).is_none());
+    }
+
+    #[test]
+    fn counts_samples_in_opus_packets() {
+        assert_eq!(opus_packet_samples(&[31 << 3]), 960); // One 20ms CELT frame
+        assert_eq!(opus_packet_samples(&[(1 << 3) | 1]), 1920); // Two 20ms SILK frames
+        assert_eq!(opus_packet_samples(&[(16 << 3) | 3, 6]), 720); // Six 2.5ms CELT frames
+        assert_eq!(opus_packet_samples(&[]), 0);
+    }
+
+    #[test]
+    fn rejects_other_document_types() {
+        let mut data = encode_webm(&sine(440.0, 0.1, 1), 1, false);
+        let doc_type = data
+            .windows(4)
+            .position(|window| window == b"webm")
+            .unwrap();
+        data[doc_type..doc_type + 4].copy_from_slice(b"mkv!");
+        assert!(WebmDecoder::new(data.into()).is_err());
+    }
+}
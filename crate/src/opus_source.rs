@@ -1,63 +1,404 @@
-use crate::debug;
 use crate::opus_mixer::audio_mixer::AudioMixer;
-use crate::opus_mixer::{FRAME_SIZE, SAMPLE_RATE};
+use crate::opus_mixer::audio_stream::AudioStream;
+use crate::opus_mixer::downmix::ChannelMode;
+use crate::opus_mixer::drift_stats::SyncStats;
+use crate::opus_mixer::ogg_opus_writer::{encode_ogg_opus, OpusEncoderSettings};
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::wav_writer::{encode_wav, WavFormat};
+use crate::opus_mixer::{CHANNELS, FRAME_SIZE, SAMPLE_RATE};
 use crate::ring_buffer::RingBuffer;
+use crate::sample_rate_converter::{ResamplerQuality, SampleRateConverter};
 use crate::source::Source;
+use crate::utils::read_file_to_array_buffer;
+use crate::{debug, log_error, log_warn};
 use std::any::Any;
+use std::mem;
 use std::sync::atomic::{AtomicBool, Ordering};
 use wasm_bindgen::prelude::*;
 use web_sys::File;
 
+// Identifies the exporter in the tags of rendered Opus files
+const ENCODER_NAME: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// A file being loaded in chunks, before playback of the set it's part of can start
+enum LoadingStream {
+    // Not enough of the file has arrived to read its headers
+    Waiting { data: Vec<u8>, complete: bool },
+    Opened(Box<AudioStream>),
+}
+
 pub struct OpusSource {
     sample_rate: f32,
     ring_buffer: RingBuffer,
     mixer: Option<AudioMixer>,
+    // Converts the mixer's 48kHz output to the AudioContext sample rate
+    converter: SampleRateConverter,
     is_running: AtomicBool,
     file_loaded: bool,
+    // Files arriving in chunks, until every one of them can be opened and mixed
+    loading_streams: Vec<LoadingStream>,
 }
 
 impl OpusSource {
-    pub fn new(sample_rate: f32) -> Result<Self, JsValue> {
-        let ring_buffer = RingBuffer::new()?;
+    pub fn new(sample_rate: f32, buffer_size: usize) -> Result<Self, JsValue> {
+        let ring_buffer = RingBuffer::new(buffer_size)?;
+        let converter = SampleRateConverter::new(
+            SAMPLE_RATE as f64,
+            sample_rate as f64,
+            CHANNELS as usize,
+            ResamplerQuality::Medium,
+        );
+
+        // Each mixed frame is written whole, so the buffer has to be able to hold one
+        let frame_samples = converter.max_output_frames(FRAME_SIZE) * CHANNELS as usize;
+        if ring_buffer.available_write() < frame_samples {
+            return Err(JsValue::from_str(&format!(
+                "Ring buffer of {} samples is too small for the opus player, which writes {} at a time",
+                buffer_size, frame_samples
+            )));
+        }
 
         Ok(Self {
             sample_rate,
             ring_buffer,
             mixer: None,
+            converter,
             is_running: AtomicBool::new(false),
             file_loaded: false,
+            loading_streams: Vec::new(),
         })
     }
 
     pub async fn load_file(&mut self, file: File) -> Result<(), JsValue> {
-        // Create a vector with a single file
-        let files = vec![file];
+        self.load_files(vec![file]).await
+    }
 
-        // Create a new mixer with the file, starting at timestamp 0.0
-        self.mixer = Some(AudioMixer::new(files, 0.0).await?);
+    pub async fn load_files(&mut self, files: Vec<File>) -> Result<(), JsValue> {
+        let mut streams = Vec::with_capacity(files.len());
+        for file in files {
+            let array_buffer = read_file_to_array_buffer(file).await?;
+            let data = js_sys::Uint8Array::new(&array_buffer).to_vec();
+            streams.push(AudioStream::from_bytes(data)?);
+        }
+
+        // Create a new mixer with the streams, starting at timestamp 0.0
+        self.mixer = Some(AudioMixer::new(streams, 0.0));
         self.file_loaded = true;
+        self.loading_streams.clear();
 
         Ok(())
     }
 
-    pub async fn load_files(&mut self, files: Vec<File>) -> Result<(), JsValue> {
-        // Create a new mixer with multiple files, starting at timestamp 0.0
-        self.mixer = Some(AudioMixer::new(files, 0.0).await?);
-        self.file_loaded = true;
+    /// Start loading `stream_count` files in chunks, replacing whatever was loaded. Playback
+    /// can start once the headers of every file have arrived.
+    pub fn begin_loading(&mut self, stream_count: usize) {
+        debug!("Loading {} files in chunks", stream_count);
+        self.mixer = None;
+        self.file_loaded = false;
+        self.converter.reset();
+        self.ring_buffer.flush();
+        self.loading_streams = (0..stream_count)
+            .map(|_| LoadingStream::Waiting {
+                data: Vec::new(),
+                complete: false,
+            })
+            .collect();
+    }
+
+    /// Add the next chunk of a file, returning whether playback can start
+    pub fn push_chunk(&mut self, stream_idx: usize, chunk: &[u8]) -> Result<bool, JsValue> {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.append_stream_bytes(stream_idx, chunk)?;
+            return Ok(true);
+        }
+
+        match self.loading_stream(stream_idx)? {
+            LoadingStream::Waiting { data, .. } => data.extend_from_slice(chunk),
+            LoadingStream::Opened(stream) => stream.append_bytes(chunk)?,
+        }
+        self.open_loading_stream(stream_idx)?;
+        Ok(self.file_loaded)
+    }
+
+    /// Mark a file as complete once all its chunks have been added, returning whether
+    /// playback can start
+    pub fn finish_chunks(&mut self, stream_idx: usize) -> Result<bool, JsValue> {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.finish_stream_loading(stream_idx)?;
+            return Ok(true);
+        }
+
+        match self.loading_stream(stream_idx)? {
+            LoadingStream::Waiting { complete, .. } => *complete = true,
+            LoadingStream::Opened(stream) => stream.finish_loading()?,
+        }
+        self.open_loading_stream(stream_idx)?;
+        Ok(self.file_loaded)
+    }
+
+    fn loading_stream(&mut self, stream_idx: usize) -> Result<&mut LoadingStream, JsValue> {
+        let stream_count = self.loading_streams.len();
+        self.loading_streams.get_mut(stream_idx).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Invalid stream index {} ({} files loading)",
+                stream_idx, stream_count
+            ))
+        })
+    }
+
+    /// Open a file once enough of it has arrived, and start the mixer once every file is open
+    fn open_loading_stream(&mut self, stream_idx: usize) -> Result<(), JsValue> {
+        if let LoadingStream::Waiting { data, complete } = &mut self.loading_streams[stream_idx] {
+            let stream = if *complete {
+                AudioStream::from_bytes(mem::take(data))?
+            } else {
+                // Until the headers are all there, opening fails; wait for the next chunk
+                match AudioStream::from_partial_bytes(data.clone()) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("File {} can't be opened yet: {:?}", stream_idx, e);
+                        return Ok(());
+                    }
+                }
+            };
+            self.loading_streams[stream_idx] = LoadingStream::Opened(Box::new(stream));
+        }
+
+        let all_opened = self
+            .loading_streams
+            .iter()
+            .all(|stream| matches!(stream, LoadingStream::Opened(_)));
+        if all_opened {
+            let streams = self
+                .loading_streams
+                .drain(..)
+                .filter_map(|stream| match stream {
+                    LoadingStream::Opened(stream) => Some(*stream),
+                    LoadingStream::Waiting { .. } => None,
+                })
+                .collect();
+            self.mixer = Some(AudioMixer::new(streams, 0.0));
+            self.file_loaded = true;
+        }
 
         Ok(())
     }
 
+    /// Add a file to the mix while it plays, with its first frame at `offset` seconds on the
+    /// timeline, returning the new stream's id. Nothing already queued for playback is
+    /// touched, so the other streams carry on without a gap.
+    pub fn add_stream(&mut self, data: Vec<u8>, offset: f64) -> Result<usize, JsValue> {
+        if !self.loading_streams.is_empty() {
+            return Err(JsValue::from_str(
+                "Can't add a file until the files being loaded are ready to play",
+            ));
+        }
+
+        let stream = AudioStream::from_bytes(data)?;
+        let mixer = self
+            .mixer
+            .get_or_insert_with(|| AudioMixer::new(Vec::new(), 0.0));
+        let stream_id = mixer.add_stream(stream, offset)?;
+        self.file_loaded = true;
+
+        Ok(stream_id)
+    }
+
+    /// Remove a stream from the mix. While playing it fades out first, so it doesn't click.
+    pub fn remove_stream(&mut self, stream_id: usize) -> Result<(), JsValue> {
+        let fade = self.is_running.load(Ordering::SeqCst);
+        Ok(self.mixer_mut()?.remove_stream(stream_id, fade)?)
+    }
+
     pub fn reset(&mut self) {
         if let Some(mixer) = &mut self.mixer {
             // Reset the mixer by seeking to the start timestamp
-            let _ = mixer.seek_to_timestamp();
+            let _ = mixer.reset();
+            self.converter.reset();
+            self.ring_buffer.flush();
         }
     }
 
+    /// Seek to `timestamp` seconds, returning the position playback resumes from
+    pub fn seek(&mut self, timestamp: f64) -> Result<f64, JsValue> {
+        let position = self.mixer_mut()?.seek_to_timestamp(timestamp)?;
+
+        // Drop the audio from before the seek that hasn't been played yet
+        self.converter.reset();
+        self.ring_buffer.flush();
+
+        Ok(position)
+    }
+
+    /// Render the mix from `start` to `end` seconds to a WAV file
+    pub fn render_wav(
+        &mut self,
+        start: f64,
+        end: f64,
+        format: WavFormat,
+    ) -> Result<Vec<u8>, JsValue> {
+        let samples = self.render(start, end)?;
+        Ok(encode_wav(&samples, CHANNELS, SAMPLE_RATE, format))
+    }
+
+    /// Render the mix from `start` to `end` seconds to an Ogg Opus file
+    pub fn render_opus(
+        &mut self,
+        start: f64,
+        end: f64,
+        settings: OpusEncoderSettings,
+    ) -> Result<Vec<u8>, JsValue> {
+        let samples = self.render(start, end)?;
+        let tags = OpusTags {
+            vendor: ENCODER_NAME.to_string(),
+            comments: vec![
+                ("ENCODER".to_string(), ENCODER_NAME.to_string()),
+                (
+                    "ENCODER_OPTIONS".to_string(),
+                    format!(
+                        "--bitrate {} --comp {}",
+                        settings.bitrate / 1000,
+                        settings.complexity
+                    ),
+                ),
+            ],
+        };
+        Ok(encode_ogg_opus(&samples, CHANNELS as u8, &tags, settings)?)
+    }
+
+    /// Render the mix from `start` to `end` seconds. Playback carries on from where it was
+    /// once the render is done.
+    fn render(&mut self, start: f64, end: f64) -> Result<Vec<f32>, JsValue> {
+        let position = self.position();
+        let samples = self.mixer_mut()?.render(start, end);
+
+        // Rendering moved the mixer, so put it back even if the render failed
+        self.seek(position)?;
+
+        Ok(samples?)
+    }
+
     pub fn is_file_loaded(&self) -> bool {
         self.file_loaded
     }
+
+    /// Position of the audio currently being played, in seconds. Samples still in the sample
+    /// rate converter or queued in the ring buffer haven't been played yet, so they're
+    /// subtracted from the mixer's position.
+    pub fn position(&self) -> f64 {
+        match &self.mixer {
+            Some(mixer) => {
+                let queued_frames = self.ring_buffer.queued_samples() / CHANNELS as usize;
+                let position = mixer.position()
+                    - self.converter.delay_seconds()
+                    - queued_frames as f64 / self.sample_rate as f64;
+                position.max(0.0).min(mixer.duration())
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Length of the loaded files in seconds. Files still loading count up to the end of
+    /// what's arrived.
+    pub fn duration(&self) -> f64 {
+        self.mixer.as_ref().map_or(0.0, |mixer| mixer.duration())
+    }
+
+    /// Whether any file is still arriving
+    pub fn is_loading(&self) -> bool {
+        !self.loading_streams.is_empty()
+            || self
+                .mixer
+                .as_ref()
+                .map_or(false, |mixer| mixer.is_loading())
+    }
+
+    /// Whether playback is held up waiting for more of a file to arrive
+    pub fn is_buffering(&self) -> bool {
+        self.mixer
+            .as_ref()
+            .map_or(false, |mixer| mixer.is_buffering())
+    }
+
+    /// Loop playback between `start` and `end` seconds, returning the region actually used
+    pub fn set_loop(&mut self, start: f64, end: f64) -> Result<(f64, f64), JsValue> {
+        Ok(self.mixer_mut()?.set_loop(start, end)?)
+    }
+
+    pub fn clear_loop(&mut self) -> Result<(), JsValue> {
+        self.mixer_mut()?.clear_loop();
+        Ok(())
+    }
+
+    /// Change the sample rate conversion quality. Takes effect immediately, carrying on from
+    /// the audio buffered in the converter so playback doesn't click.
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        if quality != self.converter.quality() {
+            self.converter.set_quality(quality);
+        }
+    }
+
+    fn mixer(&self) -> Result<&AudioMixer, JsValue> {
+        self.mixer
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("No audio files loaded"))
+    }
+
+    /// Number of loaded streams, 0 if nothing is loaded
+    pub fn stream_count(&self) -> usize {
+        self.mixer.as_ref().map_or(0, |mixer| mixer.stream_count())
+    }
+
+    /// Ids of the loaded streams, in the order they were added
+    pub fn stream_ids(&self) -> Vec<usize> {
+        self.mixer
+            .as_ref()
+            .map_or_else(Vec::new, |mixer| mixer.stream_ids().to_vec())
+    }
+
+    /// Drift and decoding statistics for the mix, if anything is loaded
+    pub fn sync_stats(&self) -> Option<SyncStats> {
+        self.mixer.as_ref().map(|mixer| mixer.sync_stats())
+    }
+
+    pub fn stream_tags(&self, stream_id: usize) -> Result<Option<&OpusTags>, JsValue> {
+        Ok(self.mixer()?.stream_tags(stream_id)?)
+    }
+
+    fn mixer_mut(&mut self) -> Result<&mut AudioMixer, JsValue> {
+        self.mixer
+            .as_mut()
+            .ok_or_else(|| JsValue::from_str("No audio files loaded"))
+    }
+
+    pub fn set_stream_gain(&mut self, stream_id: usize, gain_db: f32) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_gain(stream_id, gain_db)?)
+    }
+
+    pub fn set_stream_pan(&mut self, stream_id: usize, pan: f32) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_pan(stream_id, pan)?)
+    }
+
+    pub fn set_stream_mute(&mut self, stream_id: usize, muted: bool) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_mute(stream_id, muted)?)
+    }
+
+    pub fn set_stream_solo(&mut self, stream_id: usize, solo: bool) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_solo(stream_id, solo)?)
+    }
+
+    /// Move a stream so its first frame plays `offset` samples (at 48kHz) into the timeline
+    pub fn set_stream_offset(&mut self, stream_id: usize, offset: i64) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_offset(stream_id, offset)?)
+    }
+
+    pub fn set_stream_channel_mode(
+        &mut self,
+        stream_id: usize,
+        mode: ChannelMode,
+    ) -> Result<(), JsValue> {
+        Ok(self.mixer_mut()?.set_stream_channel_mode(stream_id, mode)?)
+    }
 }
 
 impl Source for OpusSource {
@@ -66,6 +407,8 @@ impl Source for OpusSource {
     }
 
     fn start(&mut self) {
+        // The buffer ran dry while stopped, which isn't an underrun
+        self.ring_buffer.ignore_underruns();
         self.is_running.store(true, Ordering::SeqCst);
     }
 
@@ -80,16 +423,23 @@ impl Source for OpusSource {
 
         let mixer = self.mixer.as_mut().unwrap();
 
+        // Nor is the buffer running dry once the mix has played to the end
+        if !mixer.is_active() {
+            self.ring_buffer.ignore_underruns();
+        }
+
         // Update the read pointer based on what JavaScript has read
         self.ring_buffer.update_read_ptr();
 
-        // TODO: get rid of num_samples, and just fill the buffer each time
-
         // Calculate how many frames we need to process
         // Note: For stereo, each frame contains twice as many samples as mono
+        // Each mixed frame is converted to the output rate, so size by the converted length
         let available_samples = self.ring_buffer.available_write();
-        let mut frames_to_process = (num_samples + FRAME_SIZE - 1) / FRAME_SIZE;
-        let available_frames = available_samples / (FRAME_SIZE * 2); // Always 2 channels
+        let converted_frame_samples =
+            self.converter.max_output_frames(FRAME_SIZE) * CHANNELS as usize;
+        let mut frames_to_process =
+            (num_samples + converted_frame_samples - 1) / converted_frame_samples;
+        let available_frames = available_samples / converted_frame_samples;
         let mut total_samples_written = 0;
 
         debug!(
@@ -103,22 +453,27 @@ impl Source for OpusSource {
 
         for _ in 0..frames_to_process {
             // Mix the next frame of samples
-            if let Ok(Some(mixed_samples)) = mixer.mix_next_samples() {
-                // Write the mixed samples to the ring buffer
-                let samples_written = self.ring_buffer.write(mixed_samples);
-                total_samples_written += samples_written;
-
-                // If we couldn't write all samples, the buffer is full
-                if samples_written < mixed_samples.len() {
-                    debug!(
-                        "Dropped samples! {} samples written, {} samples needed",
-                        samples_written,
-                        mixed_samples.len()
-                    );
+            let mixed_samples = match mixer.mix_next_samples() {
+                Ok(Some(mixed_samples)) => mixed_samples,
+                Ok(None) => break, // No more samples available
+                Err(e) => {
+                    log_error!("Failed to mix the next frame: {:?}", e);
                     break;
                 }
-            } else {
-                // No more samples available or error occurred
+            };
+
+            // Convert to the output sample rate and write to the ring buffer
+            let output_samples = self.converter.process(mixed_samples);
+            let samples_written = self.ring_buffer.write(output_samples);
+            total_samples_written += samples_written;
+
+            // If we couldn't write all samples, the buffer is full
+            if samples_written < output_samples.len() {
+                log_warn!(
+                    "Dropped samples! {} samples written, {} samples needed",
+                    samples_written,
+                    output_samples.len()
+                );
                 break;
             }
         }
@@ -37,6 +37,8 @@ impl Source for Oscillator {
     }
 
     fn start(&mut self) {
+        // The buffer ran dry while stopped, which isn't an underrun
+        self.ring_buffer.ignore_underruns();
         self.is_running = true;
     }
 
@@ -53,9 +55,9 @@ impl Source for Oscillator {
             return 0;
         }
 
-        // Calculate how many samples we can write
+        // Calculate how many stereo frames we can write
         let available = self.ring_buffer.available_write();
-        let to_process = num_samples.min(available / 2);
+        let to_process = (num_samples / 2).min(available / 2);
 
         // Generate samples
         let mut samples = vec![0.0; to_process * 2];
@@ -102,8 +104,8 @@ impl Source for Oscillator {
 }
 
 impl Oscillator {
-    pub fn new(sample_rate: f32) -> Result<Oscillator, JsValue> {
-        let ring_buffer = RingBuffer::new()?;
+    pub fn new(sample_rate: f32, buffer_size: usize) -> Result<Oscillator, JsValue> {
+        let ring_buffer = RingBuffer::new(buffer_size)?;
 
         Ok(Oscillator {
             ring_buffer,
@@ -1,11 +1,15 @@
 use js_sys::{Float32Array, SharedArrayBuffer};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use wasm_bindgen::prelude::*;
 
-// Constants for the ring buffer
-const BUFFER_SIZE: usize = 4096; // Must be a power of 2 (~4 frames)
-const BUFFER_MASK: usize = BUFFER_SIZE - 1; // For efficient modulo operations
-const METADATA_SIZE: usize = 2; // For read and write pointers
+use crate::opus_mixer::CHANNELS;
+
+// Constants for the ring buffer. Sizes are in samples and must be powers of 2, for efficient
+// modulo operations.
+pub const DEFAULT_BUFFER_SIZE: usize = 4096; // ~43ms of stereo at 48kHz
+pub const MIN_BUFFER_SIZE: usize = 256; // ~3ms, less than the output reads at a time
+pub const MAX_BUFFER_SIZE: usize = 262144; // ~2.7s
+const METADATA_SIZE: usize = 5; // For read and write pointers, the flush request and underruns
 
 #[wasm_bindgen]
 pub struct RingBuffer {
@@ -13,10 +17,16 @@ pub struct RingBuffer {
     buffer: SharedArrayBuffer,
     // Float32Array view of the buffer for easy access
     buffer_view: Float32Array,
+    // Number of samples the buffer holds (excluding metadata), and the mask to wrap indices
+    size: usize,
+    mask: usize,
     // Atomic read pointer (index where JS will read from)
     read_ptr: AtomicUsize,
     // Atomic write pointer (index where Rust will write to)
     write_ptr: AtomicUsize,
+    // Position JS has been asked to skip to, and whether it hasn't got there yet
+    flush_ptr: AtomicUsize,
+    flush_pending: AtomicBool,
 
     // Metrics
     high_water_mark_read: AtomicUsize, // Maximum number of samples available to read
@@ -24,6 +34,8 @@ pub struct RingBuffer {
     total_writes: AtomicUsize,         // Total number of write operations
     total_reads: AtomicUsize,          // Total number of read operations
     total_underruns: AtomicUsize,      // Total number of buffer underruns
+    total_overruns: AtomicUsize,       // Total number of writes that didn't all fit
+    reader_underruns: AtomicUsize,     // Underruns counted by JS when last checked
     total_samples_written: AtomicUsize, // Total number of samples written
     total_samples_read: AtomicUsize,   // Total number of samples read
 }
@@ -34,8 +46,12 @@ impl Clone for RingBuffer {
         RingBuffer {
             buffer: self.buffer.clone(),
             buffer_view: self.buffer_view.clone(),
+            size: self.size,
+            mask: self.mask,
             read_ptr: AtomicUsize::new(self.read_ptr.load(Ordering::Relaxed)),
             write_ptr: AtomicUsize::new(self.write_ptr.load(Ordering::Relaxed)),
+            flush_ptr: AtomicUsize::new(self.flush_ptr.load(Ordering::Relaxed)),
+            flush_pending: AtomicBool::new(self.flush_pending.load(Ordering::Relaxed)),
             high_water_mark_read: AtomicUsize::new(
                 self.high_water_mark_read.load(Ordering::Relaxed),
             ),
@@ -45,6 +61,8 @@ impl Clone for RingBuffer {
             total_writes: AtomicUsize::new(self.total_writes.load(Ordering::Relaxed)),
             total_reads: AtomicUsize::new(self.total_reads.load(Ordering::Relaxed)),
             total_underruns: AtomicUsize::new(self.total_underruns.load(Ordering::Relaxed)),
+            total_overruns: AtomicUsize::new(self.total_overruns.load(Ordering::Relaxed)),
+            reader_underruns: AtomicUsize::new(self.reader_underruns.load(Ordering::Relaxed)),
             total_samples_written: AtomicUsize::new(
                 self.total_samples_written.load(Ordering::Relaxed),
             ),
@@ -55,28 +73,41 @@ impl Clone for RingBuffer {
 
 #[wasm_bindgen]
 impl RingBuffer {
+    // Create a ring buffer holding `size` samples, a power of 2 between MIN_BUFFER_SIZE and
+    // MAX_BUFFER_SIZE
     #[wasm_bindgen(constructor)]
-    pub fn new() -> Result<RingBuffer, JsValue> {
+    pub fn new(size: usize) -> Result<RingBuffer, JsValue> {
+        check_buffer_size(size)?;
+
         // Create a SharedArrayBuffer with space for the audio data plus metadata
-        // Metadata: [read_ptr, write_ptr, unused, unused]
+        // Metadata: [read_ptr, write_ptr, flush_ptr, flush_seq, underrun_count]
         // Multiply by 4 because each float is 4 bytes
-        let buffer = SharedArrayBuffer::new(((BUFFER_SIZE + METADATA_SIZE) * 4) as u32);
+        let buffer = SharedArrayBuffer::new(((size + METADATA_SIZE) * 4) as u32);
         let buffer_view = Float32Array::new(&buffer);
 
         // Initialize read and write pointers to 0
         buffer_view.set_index(0, 0.0); // read_ptr
         buffer_view.set_index(1, 0.0); // write_ptr
+        buffer_view.set_index(2, 0.0); // flush_ptr
+        buffer_view.set_index(3, 0.0); // flush_seq
+        buffer_view.set_index(4, 0.0); // underrun_count
 
         Ok(RingBuffer {
             buffer,
             buffer_view,
+            size,
+            mask: size - 1,
             read_ptr: AtomicUsize::new(0),
             write_ptr: AtomicUsize::new(0),
+            flush_ptr: AtomicUsize::new(0),
+            flush_pending: AtomicBool::new(false),
             high_water_mark_read: AtomicUsize::new(0),
-            high_water_mark_write: AtomicUsize::new(BUFFER_SIZE - 1), // Start with max available
+            high_water_mark_write: AtomicUsize::new(size - 1), // Start with max available
             total_writes: AtomicUsize::new(0),
             total_reads: AtomicUsize::new(0),
             total_underruns: AtomicUsize::new(0),
+            total_overruns: AtomicUsize::new(0),
+            reader_underruns: AtomicUsize::new(0),
             total_samples_written: AtomicUsize::new(0),
             total_samples_read: AtomicUsize::new(0),
         })
@@ -94,7 +125,7 @@ impl RingBuffer {
 
         // Calculate available space, leaving one slot empty to distinguish full from empty
         let available = if write_ptr >= read_ptr {
-            BUFFER_SIZE - (write_ptr - read_ptr) - 1
+            self.size - (write_ptr - read_ptr) - 1
         } else {
             read_ptr - write_ptr - 1
         };
@@ -107,20 +138,20 @@ impl RingBuffer {
         self.total_samples_written
             .fetch_add(to_write, Ordering::Relaxed);
 
-        // Check if we couldn't write all samples (potential overrun)
+        // Count an overrun if we couldn't write all samples
         if to_write < samples.len() {
-            // This is not an underrun but could be tracked as an overrun if needed
+            self.total_overruns.fetch_add(1, Ordering::Relaxed);
         }
 
         // Write samples to the buffer
         for i in 0..to_write {
-            let buffer_idx = (write_ptr + i) & BUFFER_MASK;
+            let buffer_idx = (write_ptr + i) & self.mask;
             self.buffer_view
                 .set_index((buffer_idx + METADATA_SIZE) as u32, samples[i]);
         }
 
         // Update write pointer atomically
-        let new_write_ptr = (write_ptr + to_write) & BUFFER_MASK;
+        let new_write_ptr = (write_ptr + to_write) & self.mask;
         self.write_ptr.store(new_write_ptr, Ordering::Release);
 
         // Update the write pointer in the shared buffer for JS to read
@@ -147,8 +178,8 @@ impl RingBuffer {
             to_write,
             read_ptr,
             write_ptr,
-            (current_available_read as f32 / BUFFER_SIZE as f32) * 100.0,
-            (self.high_water_mark_read.load(Ordering::Relaxed) as f32 / BUFFER_SIZE as f32) * 100.0
+            (current_available_read as f32 / self.size as f32) * 100.0,
+            (self.high_water_mark_read.load(Ordering::Relaxed) as f32 / self.size as f32) * 100.0
         );
 
         to_write
@@ -164,7 +195,7 @@ impl RingBuffer {
         let samples_read = if js_read_ptr >= old_read_ptr {
             js_read_ptr - old_read_ptr
         } else {
-            BUFFER_SIZE - old_read_ptr + js_read_ptr
+            self.size - old_read_ptr + js_read_ptr
         };
 
         // Update metrics
@@ -172,18 +203,17 @@ impl RingBuffer {
             self.total_reads.fetch_add(1, Ordering::Relaxed);
             self.total_samples_read
                 .fetch_add(samples_read, Ordering::Relaxed);
+        }
 
-            // Check for underruns - if JS tried to read more than was available
-            let write_ptr = self.write_ptr.load(Ordering::Relaxed);
-            let available_before_read = if write_ptr >= old_read_ptr {
-                write_ptr - old_read_ptr
-            } else {
-                BUFFER_SIZE - old_read_ptr + write_ptr
-            };
-
-            if samples_read > available_before_read {
-                self.total_underruns.fetch_add(1, Ordering::Relaxed);
-            }
+        // JS never reads past the write pointer, so it counts the render quanta it had to
+        // fill without enough samples itself; add any new ones
+        let reader_underruns = self.buffer_view.get_index(4) as usize;
+        let previous = self
+            .reader_underruns
+            .swap(reader_underruns, Ordering::Relaxed);
+        if reader_underruns > previous {
+            self.total_underruns
+                .fetch_add(reader_underruns - previous, Ordering::Relaxed);
         }
 
         // Update our local read pointer
@@ -205,6 +235,14 @@ impl RingBuffer {
         }
     }
 
+    // Treat the underruns JS has counted so far as seen without adding them to the total, for
+    // when the buffer runs dry on purpose because playback stopped or reached the end
+    pub fn ignore_underruns(&self) {
+        let reader_underruns = self.buffer_view.get_index(4) as usize;
+        self.reader_underruns
+            .store(reader_underruns, Ordering::Relaxed);
+    }
+
     // Get the number of samples available to read
     pub fn available_read(&self) -> usize {
         let write_ptr = self.write_ptr.load(Ordering::Acquire);
@@ -213,7 +251,7 @@ impl RingBuffer {
         if write_ptr >= read_ptr {
             write_ptr - read_ptr
         } else {
-            BUFFER_SIZE - read_ptr + write_ptr
+            self.size - read_ptr + write_ptr
         }
     }
 
@@ -224,7 +262,7 @@ impl RingBuffer {
 
         // We need to leave one slot empty to distinguish between full and empty buffer
         if write_ptr >= read_ptr {
-            BUFFER_SIZE - (write_ptr - read_ptr) - 1
+            self.size - (write_ptr - read_ptr) - 1
         } else {
             read_ptr - write_ptr - 1
         }
@@ -232,7 +270,7 @@ impl RingBuffer {
 
     // Get the buffer size (excluding metadata)
     pub fn get_buffer_size(&self) -> usize {
-        BUFFER_SIZE
+        self.size
     }
 
     // Clear the buffer by resetting read and write pointers
@@ -248,6 +286,45 @@ impl RingBuffer {
         // Note: We don't reset metrics here as they track lifetime statistics
     }
 
+    // Discard all samples that have been written but not yet read.
+    // Only JavaScript may move the read pointer while it's reading, so rather than rewinding
+    // the write pointer we publish the position to skip to and bump a sequence number; the
+    // reader jumps to that position the next time it sees the sequence change.
+    pub fn flush(&self) {
+        let write_ptr = self.write_ptr.load(Ordering::Acquire);
+        let flush_seq = self.buffer_view.get_index(3) + 1.0;
+
+        self.flush_ptr.store(write_ptr, Ordering::Release);
+        self.flush_pending.store(true, Ordering::Release);
+
+        self.buffer_view.set_index(2, write_ptr as f32); // flush_ptr
+        self.buffer_view.set_index(3, flush_seq); // flush_seq
+
+        crate::debug!("Flushing ring buffer up to {}", write_ptr);
+    }
+
+    // Get the number of samples written but not yet played, read live from the JS read pointer.
+    // Samples that have been flushed but not yet skipped by JS are not counted.
+    pub fn queued_samples(&self) -> usize {
+        let write_ptr = self.write_ptr.load(Ordering::Acquire);
+        let js_read_ptr = self.buffer_view.get_index(0) as usize;
+        let queued = (write_ptr + self.size - js_read_ptr) & self.mask;
+
+        if self.flush_pending.load(Ordering::Acquire) {
+            let flush_ptr = self.flush_ptr.load(Ordering::Acquire);
+            let queued_after_flush = (write_ptr + self.size - flush_ptr) & self.mask;
+
+            // Once JS has skipped ahead, its read pointer is between the flush and write pointers
+            if queued <= queued_after_flush {
+                self.flush_pending.store(false, Ordering::Release);
+            } else {
+                return queued_after_flush;
+            }
+        }
+
+        queued
+    }
+
     // Methods to retrieve metrics
 
     // Get high water mark for read buffer (max samples available to read)
@@ -275,6 +352,11 @@ impl RingBuffer {
         self.total_underruns.load(Ordering::Relaxed)
     }
 
+    // Get total number of writes that didn't all fit in the buffer
+    pub fn get_total_overruns(&self) -> usize {
+        self.total_overruns.load(Ordering::Relaxed)
+    }
+
     // Get total number of samples written
     pub fn get_total_samples_written(&self) -> usize {
         self.total_samples_written.load(Ordering::Relaxed)
@@ -289,19 +371,73 @@ impl RingBuffer {
     pub fn reset_metrics(&self) {
         self.high_water_mark_read.store(0, Ordering::Relaxed);
         self.high_water_mark_write
-            .store(BUFFER_SIZE - 1, Ordering::Relaxed);
+            .store(self.size - 1, Ordering::Relaxed);
         self.total_writes.store(0, Ordering::Relaxed);
         self.total_reads.store(0, Ordering::Relaxed);
         self.total_underruns.store(0, Ordering::Relaxed);
+        self.total_overruns.store(0, Ordering::Relaxed);
         self.total_samples_written.store(0, Ordering::Relaxed);
         self.total_samples_read.store(0, Ordering::Relaxed);
     }
 }
 
+/// A snapshot of the ring buffer's fill level and lifetime counters
+#[derive(Clone, Copy, Debug)]
+pub struct RingBufferMetrics {
+    pub buffer_size: usize,
+    pub queued_samples: usize,
+    /// Audio waiting to be played, in milliseconds
+    pub fill_ms: f64,
+    /// Audio the buffer holds when full, in milliseconds
+    pub capacity_ms: f64,
+    pub high_water_mark_read: usize,
+    pub high_water_mark_write: usize,
+    pub writes: usize,
+    pub reads: usize,
+    pub underruns: usize,
+    pub overruns: usize,
+    pub samples_written: usize,
+    pub samples_read: usize,
+}
+
+impl RingBuffer {
+    /// Snapshot the metrics, with durations for stereo output at `sample_rate`
+    pub fn metrics(&self, sample_rate: f32) -> RingBufferMetrics {
+        let to_ms = |samples: usize| samples as f64 / CHANNELS as f64 / sample_rate as f64 * 1000.0;
+        let queued_samples = self.queued_samples();
+
+        RingBufferMetrics {
+            buffer_size: self.size,
+            queued_samples,
+            fill_ms: to_ms(queued_samples),
+            capacity_ms: to_ms(self.size - 1),
+            high_water_mark_read: self.get_high_water_mark_read(),
+            high_water_mark_write: self.get_high_water_mark_write(),
+            writes: self.get_total_writes(),
+            reads: self.get_total_reads(),
+            underruns: self.get_total_underruns(),
+            overruns: self.get_total_overruns(),
+            samples_written: self.get_total_samples_written(),
+            samples_read: self.get_total_samples_read(),
+        }
+    }
+}
+
+/// Check a ring buffer size is a power of 2 between MIN_BUFFER_SIZE and MAX_BUFFER_SIZE
+pub fn check_buffer_size(size: usize) -> Result<(), JsValue> {
+    if !size.is_power_of_two() || !(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE).contains(&size) {
+        return Err(JsValue::from_str(&format!(
+            "Invalid ring buffer size: {} (expected a power of 2 from {} to {})",
+            size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE
+        )));
+    }
+    Ok(())
+}
+
 // Constants exposed to JavaScript
 #[wasm_bindgen]
 pub fn get_buffer_size() -> usize {
-    BUFFER_SIZE
+    DEFAULT_BUFFER_SIZE
 }
 
 #[wasm_bindgen]
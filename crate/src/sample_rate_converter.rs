@@ -0,0 +1,403 @@
+use std::f64::consts::PI;
+use wasm_bindgen::JsValue;
+
+// Number of fractional positions the filter table is computed for; positions in between are
+// linearly interpolated from the two nearest rows
+const FILTER_PHASES: usize = 256;
+
+// Taps either side of the centre for the longest filter. That much input before the read
+// position is always kept, so the quality can change without losing any history.
+const MAX_HALF_TAPS: usize = 32;
+
+/// Quality presets for the sample rate converter. Higher quality uses a longer filter, which
+/// gives a sharper cutoff and less aliasing at the cost of more CPU time and latency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResamplerQuality {
+    Low,
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    pub fn from_name(name: &str) -> Result<Self, JsValue> {
+        match name {
+            "low" => Ok(ResamplerQuality::Low),
+            "medium" => Ok(ResamplerQuality::Medium),
+            "high" => Ok(ResamplerQuality::High),
+            _ => Err(JsValue::from_str(&format!(
+                "Unknown resampler quality: {} (expected low, medium or high)",
+                name
+            ))),
+        }
+    }
+
+    // (taps either side of the centre, Kaiser window beta, passband as a fraction of Nyquist)
+    fn filter_params(self) -> (usize, f64, f64) {
+        match self {
+            ResamplerQuality::Low => (4, 5.0, 0.85),
+            ResamplerQuality::Medium => (16, 7.0, 0.92),
+            ResamplerQuality::High => (32, 9.0, 0.95),
+        }
+    }
+}
+
+/// Streaming windowed-sinc sample rate converter for interleaved audio
+#[derive(Debug)]
+pub struct SampleRateConverter {
+    input_rate: f64,
+    output_rate: f64,
+    channels: usize,
+    quality: ResamplerQuality,
+    // Input frames per output frame
+    step: f64,
+    half_taps: usize,
+    // FILTER_PHASES + 1 rows of 2 * half_taps coefficients
+    table: Vec<f32>,
+    // Buffered interleaved input that's still needed by the filter
+    input: Vec<f32>,
+    // Read position into `input`, in frames
+    position: f64,
+    output: Vec<f32>,
+}
+
+impl SampleRateConverter {
+    pub fn new(
+        input_rate: f64,
+        output_rate: f64,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Self {
+        let mut converter = Self {
+            input_rate,
+            output_rate,
+            channels,
+            quality,
+            step: input_rate / output_rate,
+            half_taps: 0,
+            table: Vec::new(),
+            input: Vec::new(),
+            position: 0.0,
+            output: Vec::new(),
+        };
+        converter.set_quality(quality);
+        converter.reset();
+        converter
+    }
+
+    pub fn quality(&self) -> ResamplerQuality {
+        self.quality
+    }
+
+    /// Switch to the filter for `quality`, carrying on from the buffered input so the output
+    /// doesn't jump. The delay changes by the difference in filter lengths.
+    pub fn set_quality(&mut self, quality: ResamplerQuality) {
+        let (half_taps, beta, passband) = quality.filter_params();
+
+        // When downsampling the cutoff has to drop to the output Nyquist frequency
+        let cutoff = passband * (1.0 / self.step).min(1.0);
+        self.table = build_filter_table(half_taps, cutoff, beta);
+        self.half_taps = half_taps;
+        self.quality = quality;
+    }
+
+    fn is_passthrough(&self) -> bool {
+        self.input_rate == self.output_rate
+    }
+
+    /// Discard buffered audio, e.g. after a seek
+    pub fn reset(&mut self) {
+        // Prime with silence so the first output frame lines up with the first input frame
+        self.input.clear();
+        self.input.resize(MAX_HALF_TAPS * self.channels, 0.0);
+        self.position = MAX_HALF_TAPS as f64;
+    }
+
+    /// Input frames either side of an output frame that the filter reads. Starting the input
+    /// this far before the first output frame that's wanted avoids a fade in from the silence
+    /// the filter is primed with.
+    pub fn history_frames(&self) -> usize {
+        if self.is_passthrough() {
+            0
+        } else {
+            self.half_taps
+        }
+    }
+
+    /// Upper bound on the output frames produced from `input_frames` input frames
+    pub fn max_output_frames(&self, input_frames: usize) -> usize {
+        if self.is_passthrough() {
+            input_frames
+        } else {
+            (input_frames as f64 / self.step).ceil() as usize + 1
+        }
+    }
+
+    /// Audio that has gone into the converter but not come out yet, in seconds
+    pub fn delay_seconds(&self) -> f64 {
+        if self.is_passthrough() {
+            return 0.0;
+        }
+
+        let buffered_frames = (self.input.len() / self.channels) as f64 - self.position;
+        buffered_frames.max(0.0) / self.input_rate
+    }
+
+    /// Convert a block of interleaved input, returning all the output it makes available
+    pub fn process(&mut self, input: &[f32]) -> &[f32] {
+        self.output.clear();
+
+        if self.is_passthrough() {
+            self.output.extend_from_slice(input);
+            return &self.output;
+        }
+
+        self.input.extend_from_slice(input);
+
+        let channels = self.channels;
+        let taps = self.half_taps * 2;
+        let available = self.input.len() / channels;
+
+        loop {
+            let centre = self.position as usize;
+            if centre + self.half_taps >= available {
+                break;
+            }
+
+            // Interpolate between the two filter rows either side of the fractional position
+            let phase = (self.position - centre as f64) * FILTER_PHASES as f64;
+            let row = phase as usize;
+            let weight = (phase - row as f64) as f32;
+            let row0 = &self.table[row * taps..(row + 1) * taps];
+            let row1 = &self.table[(row + 1) * taps..(row + 2) * taps];
+
+            let first_frame = centre + 1 - self.half_taps;
+            for channel in 0..channels {
+                let mut sum = 0.0;
+                for tap in 0..taps {
+                    let coeff = row0[tap] + (row1[tap] - row0[tap]) * weight;
+                    sum += coeff * self.input[(first_frame + tap) * channels + channel];
+                }
+                self.output.push(sum);
+            }
+
+            self.position += self.step;
+        }
+
+        // Drop the input frames the filter has moved past, other than the history kept for a
+        // longer filter
+        let consumed = (self.position as usize + 1).saturating_sub(MAX_HALF_TAPS);
+        let consumed = consumed.min(available);
+        if consumed > 0 {
+            self.input.drain(..consumed * channels);
+            self.position -= consumed as f64;
+        }
+
+        &self.output
+    }
+
+    /// Push out the audio the filter is still holding back at the end of the input by
+    /// feeding it silence. The output runs on past the end of the input by up to
+    /// `history_frames` frames.
+    pub fn flush(&mut self) -> &[f32] {
+        let silence = vec![0.0; self.history_frames() * self.channels];
+        self.process(&silence)
+    }
+}
+
+/// Build a Kaiser-windowed sinc lowpass filter for each fractional phase. Row `p` holds the
+/// coefficients for an output frame `p / FILTER_PHASES` of the way past the centre tap.
+fn build_filter_table(half_taps: usize, cutoff: f64, beta: f64) -> Vec<f32> {
+    let taps = half_taps * 2;
+    let window_norm = bessel_i0(beta);
+    let mut table = Vec::with_capacity((FILTER_PHASES + 1) * taps);
+
+    for phase in 0..=FILTER_PHASES {
+        let fraction = phase as f64 / FILTER_PHASES as f64;
+        let row_start = table.len();
+
+        for tap in 0..taps {
+            // Distance from the interpolation point to this tap, in input frames
+            let x = tap as f64 - (half_taps as f64 - 1.0) - fraction;
+            let window_pos = x / half_taps as f64;
+            let window = if window_pos.abs() >= 1.0 {
+                0.0
+            } else {
+                bessel_i0(beta * (1.0 - window_pos * window_pos).sqrt()) / window_norm
+            };
+            table.push((cutoff * sinc(cutoff * x) * window) as f32);
+        }
+
+        // Normalise each row to unity gain at DC
+        let sum: f32 = table[row_start..].iter().sum();
+        if sum != 0.0 {
+            for coeff in &mut table[row_start..] {
+                *coeff /= sum;
+            }
+        }
+    }
+
+    table
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Zeroth-order modified Bessel function of the first kind, used by the Kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+    for k in 1..50 {
+        term *= (half_x / k as f64) * (half_x / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|frame| (0.5 * (2.0 * PI * frequency * frame as f64 / rate).sin()) as f32)
+            .collect()
+    }
+
+    /// Convert mono `input` in mixer-sized blocks, then flush
+    fn convert(converter: &mut SampleRateConverter, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::new();
+        for block in input.chunks(960) {
+            output.extend_from_slice(converter.process(block));
+        }
+        output.extend_from_slice(converter.flush());
+        output
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        let sum: f64 = samples.iter().map(|&sample| (sample as f64).powi(2)).sum();
+        (sum / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn converts_a_second_to_a_second() {
+        for &output_rate in &[44_100.0, 96_000.0] {
+            for &quality in &[ResamplerQuality::Low, ResamplerQuality::High] {
+                let mut converter = SampleRateConverter::new(48_000.0, output_rate, 2, quality);
+                let output = convert(&mut converter, &vec![0.25; 96_000]);
+
+                // The flush runs on by up to the filter's length past the end
+                let frames = output.len() / 2;
+                let extra = converter.max_output_frames(converter.history_frames());
+                assert!(frames >= output_rate as usize, "{} frames", frames);
+                assert!(frames <= output_rate as usize + extra, "{} frames", frames);
+            }
+        }
+    }
+
+    #[test]
+    fn passes_through_at_the_same_rate() {
+        let mut converter = SampleRateConverter::new(48_000.0, 48_000.0, 2, ResamplerQuality::High);
+        let input = sine(440.0, 48_000.0, 960);
+        assert_eq!(converter.process(&input), &input[..]);
+        assert_eq!(converter.delay_seconds(), 0.0);
+        assert_eq!(converter.history_frames(), 0);
+        assert!(converter.flush().is_empty());
+    }
+
+    #[test]
+    fn reports_the_audio_held_back_by_the_filter() {
+        let mut converter =
+            SampleRateConverter::new(48_000.0, 44_100.0, 1, ResamplerQuality::Medium);
+        let step = 48_000.0 / 44_100.0;
+        assert_eq!(converter.delay_seconds(), 0.0);
+
+        // Every input frame has either come out or is counted in the delay
+        let output = converter.process(&vec![0.0; 960]).len();
+        let delay = converter.delay_seconds() * 48_000.0;
+        assert!(delay > 0.0);
+        assert!((output as f64 * step + delay - 960.0).abs() < 1.0);
+
+        // Including the silence the flush adds
+        let tail = converter.flush().len();
+        let delay = converter.delay_seconds() * 48_000.0;
+        let input = 960 + converter.history_frames();
+        assert!(((output + tail) as f64 * step + delay - input as f64).abs() < 1.0);
+
+        converter.reset();
+        assert_eq!(converter.delay_seconds(), 0.0);
+    }
+
+    #[test]
+    fn flush_drains_the_tail() {
+        let mut converter =
+            SampleRateConverter::new(48_000.0, 44_100.0, 1, ResamplerQuality::Medium);
+        let mut input = vec![0.0; 960];
+        input[959] = 1.0;
+
+        // An impulse at the very end only comes out once the filter is flushed
+        let held = converter.process(&input).to_vec();
+        assert!(held.iter().all(|sample| sample.abs() < 0.01));
+        let tail = converter.flush();
+        assert!(tail.iter().any(|sample| sample.abs() > 0.5));
+    }
+
+    #[test]
+    fn keeps_the_passband_and_rejects_the_stopband() {
+        // Longer filters have a sharper cutoff, so let less of the stopband through
+        for &(quality, max_level) in &[
+            (ResamplerQuality::Low, 0.25),
+            (ResamplerQuality::Medium, 0.02),
+            (ResamplerQuality::High, 0.001),
+        ] {
+            // 1kHz comes out at the same level, lined up with the input
+            let mut converter = SampleRateConverter::new(48_000.0, 44_100.0, 1, quality);
+            let output = convert(&mut converter, &sine(1000.0, 48_000.0, 48_000));
+            let expected = sine(1000.0, 44_100.0, 44_100);
+            let steady = 100..44_000;
+            let error = output[steady.clone()]
+                .iter()
+                .zip(&expected[steady])
+                .map(|(output, expected)| (output - expected).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 0.01, "{:?}: error {}", quality, error);
+
+            // 23kHz is above the output's Nyquist frequency, so it would alias
+            let mut converter = SampleRateConverter::new(48_000.0, 44_100.0, 1, quality);
+            let output = convert(&mut converter, &sine(23_000.0, 48_000.0, 48_000));
+            let level = rms(&output[100..44_000]) / rms(&sine(23_000.0, 48_000.0, 48_000));
+            assert!(level < max_level, "{:?}: stopband level {}", quality, level);
+        }
+    }
+
+    #[test]
+    fn changes_quality_without_a_jump() {
+        let input = sine(1000.0, 48_000.0, 9600);
+        let mut reference = SampleRateConverter::new(48_000.0, 44_100.0, 1, ResamplerQuality::Low);
+        let expected = convert(&mut reference, &input);
+
+        // Switching filters mid-stream only shifts where the output is split between blocks
+        let mut converter = SampleRateConverter::new(48_000.0, 44_100.0, 1, ResamplerQuality::Low);
+        let mut output = Vec::new();
+        for (index, block) in input.chunks(960).enumerate() {
+            if index == 5 {
+                converter.set_quality(ResamplerQuality::High);
+            }
+            output.extend_from_slice(converter.process(block));
+        }
+        output.extend_from_slice(converter.flush());
+
+        assert_eq!(converter.quality(), ResamplerQuality::High);
+        for (output, expected) in output.iter().zip(&expected).take(8800) {
+            assert!((output - expected).abs() < 0.02);
+        }
+    }
+}
@@ -1,4 +1,13 @@
-use crate::ring_buffer::RingBuffer;
+use crate::opus_mixer::downmix::ChannelMode;
+use crate::opus_mixer::drift_stats::SyncStats;
+use crate::opus_mixer::ogg_opus_writer::OpusEncoderSettings;
+use crate::opus_mixer::opus_tags::OpusTags;
+use crate::opus_mixer::wav_writer::WavFormat;
+use crate::opus_mixer::{CHANNELS, SAMPLE_RATE};
+use crate::opus_source::OpusSource;
+use crate::ring_buffer::{RingBuffer, RingBufferMetrics};
+use crate::sample_rate_converter::ResamplerQuality;
+use crate::utils::bytes_to_blob;
 use std::any::Any;
 use wasm_bindgen::prelude::*;
 
@@ -44,34 +53,42 @@ pub struct AudioSource {
     // The actual source implementation
     source_type: SourceType,
     source: Box<dyn Source>,
+    // Output sample rate, for converting buffer levels to durations
+    sample_rate: f32,
+    // Samples `fill` keeps queued in the ring buffer, at most its capacity
+    target_fill: usize,
 }
 
 #[wasm_bindgen]
 impl AudioSource {
-    // Create a new oscillator source
+    // Create a new oscillator source, with a ring buffer of `buffer_size` samples (a power of 2)
     #[wasm_bindgen(js_name = createOscillator)]
-    pub fn create_oscillator(sample_rate: f32) -> Result<AudioSource, JsValue> {
+    pub fn create_oscillator(sample_rate: f32, buffer_size: usize) -> Result<AudioSource, JsValue> {
         use crate::oscillator::Oscillator;
 
-        let oscillator = Oscillator::new(sample_rate)?;
-
-        Ok(AudioSource {
-            source_type: SourceType::Oscillator,
-            source: Box::new(oscillator),
-        })
+        let oscillator = Oscillator::new(sample_rate, buffer_size)?;
+        Ok(AudioSource::new(
+            SourceType::Oscillator,
+            Box::new(oscillator),
+            sample_rate,
+        ))
     }
 
-    // Create a new opus player source
+    // Create a new opus player source, with a ring buffer of `buffer_size` samples (a power of
+    // 2, big enough for a 20ms frame)
     #[wasm_bindgen(js_name = createOpusPlayer)]
-    pub fn create_opus_player(sample_rate: f32) -> Result<AudioSource, JsValue> {
+    pub fn create_opus_player(
+        sample_rate: f32,
+        buffer_size: usize,
+    ) -> Result<AudioSource, JsValue> {
         use crate::opus_source::OpusSource;
 
-        let opus_source = OpusSource::new(sample_rate)?;
-
-        Ok(AudioSource {
-            source_type: SourceType::OpusPlayer,
-            source: Box::new(opus_source),
-        })
+        let opus_source = OpusSource::new(sample_rate, buffer_size)?;
+        Ok(AudioSource::new(
+            SourceType::OpusPlayer,
+            Box::new(opus_source),
+            sample_rate,
+        ))
     }
 
     // Get the type of this source
@@ -94,6 +111,36 @@ impl AudioSource {
         self.source.process(num_samples)
     }
 
+    // Process enough audio to bring the ring buffer up to the target fill level, returning the
+    // number of samples written
+    pub fn fill(&mut self) -> usize {
+        let queued = self.source.get_ring_buffer().queued_samples();
+        if queued >= self.target_fill {
+            return 0;
+        }
+        self.source.process(self.target_fill - queued)
+    }
+
+    // Set how much audio `fill` keeps queued, in milliseconds, returning the level actually
+    // used after clamping to the ring buffer. Lower responds sooner, higher rides out longer
+    // stalls of the worker.
+    #[wasm_bindgen(js_name = setTargetFill)]
+    pub fn set_target_fill(&mut self, ms: f64) -> Result<f64, JsValue> {
+        if !ms.is_finite() || ms <= 0.0 {
+            return Err(JsValue::from_str(&format!("Invalid target fill: {}ms", ms)));
+        }
+
+        let samples = (ms / 1000.0 * self.sample_rate as f64).round() as usize * CHANNELS as usize;
+        self.target_fill = samples.min(self.buffer_capacity());
+        Ok(self.get_target_fill())
+    }
+
+    // Get the target fill level in milliseconds
+    #[wasm_bindgen(js_name = getTargetFill)]
+    pub fn get_target_fill(&self) -> f64 {
+        self.target_fill as f64 / CHANNELS as f64 / self.sample_rate as f64 * 1000.0
+    }
+
     // Get the shared buffer
     pub fn get_shared_buffer(&self) -> js_sys::SharedArrayBuffer {
         self.source.get_shared_buffer()
@@ -104,6 +151,15 @@ impl AudioSource {
         self.source.is_running()
     }
 
+    // Get a snapshot of the ring buffer's health as { bufferSize, queuedSamples, fillMs,
+    // capacityMs, highWaterMarkRead, highWaterMarkWrite, writes, reads, underruns, overruns,
+    // samplesWritten, samplesRead }
+    #[wasm_bindgen(js_name = getBufferMetrics)]
+    pub fn get_buffer_metrics(&self) -> Result<JsValue, JsValue> {
+        let metrics = self.source.get_ring_buffer().metrics(self.sample_rate);
+        Ok(buffer_metrics_to_js_object(&metrics)?.into())
+    }
+
     // Set frequency (only for oscillator type)
     pub fn set_frequency(&mut self, frequency: f32) -> Result<(), JsValue> {
         match self.source_type {
@@ -185,6 +241,59 @@ impl AudioSource {
         }
     }
 
+    // Start loading `stream_count` files in chunks, replacing whatever was loaded. Playback can
+    // start once the headers of every file have arrived (only for opus player type)
+    #[wasm_bindgen(js_name = beginLoading)]
+    pub fn begin_loading(&mut self, stream_count: usize) -> Result<(), JsValue> {
+        self.opus_source_mut("chunked loading")?
+            .begin_loading(stream_count);
+        Ok(())
+    }
+
+    // Add the next chunk of a file started with beginLoading, returning whether playback can
+    // start (only for opus player type)
+    #[wasm_bindgen(js_name = pushAudioChunk)]
+    pub fn push_audio_chunk(&mut self, stream_index: usize, chunk: &[u8]) -> Result<bool, JsValue> {
+        self.opus_source_mut("chunked loading")?
+            .push_chunk(stream_index, chunk)
+    }
+
+    // Mark a file started with beginLoading as complete, returning whether playback can start
+    // (only for opus player type)
+    #[wasm_bindgen(js_name = finishAudioChunks)]
+    pub fn finish_audio_chunks(&mut self, stream_index: usize) -> Result<bool, JsValue> {
+        self.opus_source_mut("chunked loading")?
+            .finish_chunks(stream_index)
+    }
+
+    // Check if any file is still arriving (only for opus player type)
+    #[wasm_bindgen(js_name = isLoading)]
+    pub fn is_loading(&self) -> Result<bool, JsValue> {
+        Ok(self.opus_source("is_loading")?.is_loading())
+    }
+
+    // Check if playback is waiting for more of a file to arrive (only for opus player type)
+    #[wasm_bindgen(js_name = isBuffering)]
+    pub fn is_buffering(&self) -> Result<bool, JsValue> {
+        Ok(self.opus_source("is_buffering")?.is_buffering())
+    }
+
+    // Add a file to the mix while it plays, with its first frame at `offset` seconds on the
+    // timeline (negative to skip its start), returning the new stream's id (only for opus
+    // player type)
+    #[wasm_bindgen(js_name = addAudioStream)]
+    pub fn add_audio_stream(&mut self, data: Vec<u8>, offset: f64) -> Result<usize, JsValue> {
+        self.opus_source_mut("adding streams")?
+            .add_stream(data, offset)
+    }
+
+    // Remove a stream from the mix by id, fading it out if playing (only for opus player type)
+    #[wasm_bindgen(js_name = removeStream)]
+    pub fn remove_stream(&mut self, stream_id: usize) -> Result<(), JsValue> {
+        self.opus_source_mut("removing streams")?
+            .remove_stream(stream_id)
+    }
+
     // Reset playback position (only for opus player type)
     pub fn reset(&mut self) -> Result<(), JsValue> {
         match self.source_type {
@@ -207,6 +316,206 @@ impl AudioSource {
         }
     }
 
+    // Seek to a position in seconds, returning the position playback resumes from (only for opus player type)
+    pub fn seek(&mut self, seconds: f64) -> Result<f64, JsValue> {
+        self.opus_source_mut("seek")?.seek(seconds)
+    }
+
+    // Get the playback position in seconds (only for opus player type)
+    #[wasm_bindgen(js_name = getPosition)]
+    pub fn get_position(&self) -> Result<f64, JsValue> {
+        Ok(self.opus_source("get_position")?.position())
+    }
+
+    // Get the duration of the loaded files in seconds (only for opus player type)
+    #[wasm_bindgen(js_name = getDuration)]
+    pub fn get_duration(&self) -> Result<f64, JsValue> {
+        Ok(self.opus_source("get_duration")?.duration())
+    }
+
+    // Loop playback between two positions in seconds, returning the [start, end] actually
+    // used after clamping to the files (only for opus player type)
+    #[wasm_bindgen(js_name = setLoop)]
+    pub fn set_loop(&mut self, start: f64, end: f64) -> Result<Vec<f64>, JsValue> {
+        let (start, end) = self.opus_source_mut("looping")?.set_loop(start, end)?;
+        Ok(vec![start, end])
+    }
+
+    // Stop looping (only for opus player type)
+    #[wasm_bindgen(js_name = clearLoop)]
+    pub fn clear_loop(&mut self) -> Result<(), JsValue> {
+        self.opus_source_mut("looping")?.clear_loop()
+    }
+
+    // Render the mix between two positions in seconds to a WAV Blob as fast as possible, with
+    // the current stream settings. Format is "pcm16", "pcm24" or "float32" (only for opus
+    // player type)
+    #[wasm_bindgen(js_name = renderWav)]
+    pub fn render_wav(
+        &mut self,
+        start: f64,
+        end: f64,
+        format: &str,
+    ) -> Result<web_sys::Blob, JsValue> {
+        let format = WavFormat::from_name(format)?;
+        let wav = self
+            .opus_source_mut("rendering")?
+            .render_wav(start, end, format)?;
+        bytes_to_blob(&wav, "audio/wav")
+    }
+
+    // Render the mix between two positions in seconds to an Ogg Opus Blob, encoded at
+    // `bitrate` bits per second with `complexity` from 0 to 10 (only for opus player type)
+    #[wasm_bindgen(js_name = renderOpus)]
+    pub fn render_opus(
+        &mut self,
+        start: f64,
+        end: f64,
+        bitrate: i32,
+        complexity: i32,
+    ) -> Result<web_sys::Blob, JsValue> {
+        let settings = OpusEncoderSettings::new(bitrate, complexity)?;
+        let opus = self
+            .opus_source_mut("rendering")?
+            .render_opus(start, end, settings)?;
+        bytes_to_blob(&opus, "audio/ogg")
+    }
+
+    // Get the number of loaded streams (only for opus player type)
+    #[wasm_bindgen(js_name = getStreamCount)]
+    pub fn get_stream_count(&self) -> Result<usize, JsValue> {
+        Ok(self.opus_source("get_stream_count")?.stream_count())
+    }
+
+    // Get the ids of the loaded streams, in the order they were added. The files loaded together
+    // are numbered from 0 (only for opus player type)
+    #[wasm_bindgen(js_name = getStreamIds)]
+    pub fn get_stream_ids(&self) -> Result<Vec<u32>, JsValue> {
+        Ok(self
+            .opus_source("get_stream_ids")?
+            .stream_ids()
+            .into_iter()
+            .map(|stream_id| stream_id as u32)
+            .collect())
+    }
+
+    // Get a stream's OpusTags metadata as { vendor, title, artist, trackGainDb, comments },
+    // or null if the file had none (only for opus player type)
+    #[wasm_bindgen(js_name = getStreamTags)]
+    pub fn get_stream_tags(&self, stream_id: usize) -> Result<JsValue, JsValue> {
+        match self
+            .opus_source("get_stream_tags")?
+            .stream_tags(stream_id)?
+        {
+            Some(tags) => Ok(tags_to_js_object(tags)?.into()),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    // Get drift and decoding statistics as { maxDriftMs, streams: [{ streamId, maxDriftMs,
+    // averageDriftMs, driftMeasurements, maxSpeedAdjustment, averageSpeedAdjustment,
+    // compensationCount, appliedCorrection, playbackRate, decodedFrames, resampledFrames,
+    // history: [{ time, driftMs, playbackRate }] }] }, or null if nothing is loaded (only for
+    // opus player type)
+    #[wasm_bindgen(js_name = getSyncStats)]
+    pub fn get_sync_stats(&self) -> Result<JsValue, JsValue> {
+        match self.opus_source("get_sync_stats")?.sync_stats() {
+            Some(stats) => Ok(sync_stats_to_js_object(&stats)?.into()),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    // Set the sample rate conversion quality: "low", "medium" or "high" (only for opus player type)
+    #[wasm_bindgen(js_name = setResamplerQuality)]
+    pub fn set_resampler_quality(&mut self, quality: &str) -> Result<(), JsValue> {
+        let quality = ResamplerQuality::from_name(quality)?;
+        self.opus_source_mut("resampler quality")?
+            .set_resampler_quality(quality);
+        Ok(())
+    }
+
+    // Set the gain of a stream in decibels (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamGain)]
+    pub fn set_stream_gain(&mut self, stream_id: usize, gain_db: f32) -> Result<(), JsValue> {
+        self.opus_source_mut("stream gain")?
+            .set_stream_gain(stream_id, gain_db)
+    }
+
+    // Set the pan of a stream from -1.0 (left) to 1.0 (right) (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamPan)]
+    pub fn set_stream_pan(&mut self, stream_id: usize, pan: f32) -> Result<(), JsValue> {
+        self.opus_source_mut("stream pan")?
+            .set_stream_pan(stream_id, pan)
+    }
+
+    // Mute or unmute a stream (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamMute)]
+    pub fn set_stream_mute(&mut self, stream_id: usize, muted: bool) -> Result<(), JsValue> {
+        self.opus_source_mut("stream mute")?
+            .set_stream_mute(stream_id, muted)
+    }
+
+    // Solo or unsolo a stream (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamSolo)]
+    pub fn set_stream_solo(&mut self, stream_id: usize, solo: bool) -> Result<(), JsValue> {
+        self.opus_source_mut("stream solo")?
+            .set_stream_solo(stream_id, solo)
+    }
+
+    // Start a stream `seconds` into the timeline, with silence before it, or skip its first
+    // `-seconds` if negative. Takes effect immediately, in sync with the other streams (only for
+    // opus player type)
+    #[wasm_bindgen(js_name = setStreamOffset)]
+    pub fn set_stream_offset(&mut self, stream_id: usize, seconds: f64) -> Result<(), JsValue> {
+        let samples = (seconds * SAMPLE_RATE as f64).round();
+        if !stream_offset_in_range(samples) {
+            return Err(JsValue::from_str(&format!(
+                "Invalid stream offset: {}s",
+                seconds
+            )));
+        }
+        self.set_stream_offset_samples(stream_id, samples)
+    }
+
+    // Start a stream `samples` into the timeline, counted at 48kHz whatever the output sample
+    // rate. Takes a whole number so out of range offsets are rejected rather than wrapped
+    // (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamOffsetSamples)]
+    pub fn set_stream_offset_samples(
+        &mut self,
+        stream_id: usize,
+        samples: f64,
+    ) -> Result<(), JsValue> {
+        if samples.fract() != 0.0 || !stream_offset_in_range(samples) {
+            return Err(JsValue::from_str(&format!(
+                "Invalid stream offset: {} samples",
+                samples
+            )));
+        }
+        self.opus_source_mut("stream offsets")?
+            .set_stream_offset(stream_id, samples as i64)
+    }
+
+    // Route two of a stream's raw channels, numbered in the file's channel order, straight to
+    // the left and right outputs instead of downmixing (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamRawChannels)]
+    pub fn set_stream_raw_channels(
+        &mut self,
+        stream_id: usize,
+        left: usize,
+        right: usize,
+    ) -> Result<(), JsValue> {
+        self.opus_source_mut("raw channel routing")?
+            .set_stream_channel_mode(stream_id, ChannelMode::Raw { left, right })
+    }
+
+    // Fold all of a stream's channels down to stereo, the default (only for opus player type)
+    #[wasm_bindgen(js_name = setStreamDownmix)]
+    pub fn set_stream_downmix(&mut self, stream_id: usize) -> Result<(), JsValue> {
+        self.opus_source_mut("downmixing")?
+            .set_stream_channel_mode(stream_id, ChannelMode::Downmix)
+    }
+
     // Check if a file is loaded (only for opus player type)
     pub fn is_file_loaded(&self) -> Result<bool, JsValue> {
         match self.source_type {
@@ -229,3 +538,179 @@ impl AudioSource {
         }
     }
 }
+
+impl AudioSource {
+    // Wrap a source, filling its whole ring buffer until a target fill level is set
+    fn new(source_type: SourceType, source: Box<dyn Source>, sample_rate: f32) -> Self {
+        let mut audio_source = AudioSource {
+            source_type,
+            source,
+            sample_rate,
+            target_fill: 0,
+        };
+        audio_source.target_fill = audio_source.buffer_capacity();
+        audio_source
+    }
+
+    // Most samples the ring buffer can hold at once
+    fn buffer_capacity(&self) -> usize {
+        self.source.get_ring_buffer().get_buffer_size() - 1
+    }
+
+    // Downcast to OpusSource, for operations only supported by the opus player
+    fn opus_source(&self, operation: &str) -> Result<&OpusSource, JsValue> {
+        match self.source_type {
+            SourceType::OpusPlayer => self
+                .source
+                .as_any()
+                .downcast_ref::<OpusSource>()
+                .ok_or_else(|| JsValue::from_str("Failed to downcast to OpusSource")),
+            _ => Err(JsValue::from_str(&format!(
+                "This source type does not support {}",
+                operation
+            ))),
+        }
+    }
+
+    // Mutable version of opus_source
+    fn opus_source_mut(&mut self, operation: &str) -> Result<&mut OpusSource, JsValue> {
+        match self.source_type {
+            SourceType::OpusPlayer => self
+                .source
+                .as_mut()
+                .as_any_mut()
+                .downcast_mut::<OpusSource>()
+                .ok_or_else(|| JsValue::from_str("Failed to downcast to OpusSource")),
+            _ => Err(JsValue::from_str(&format!(
+                "This source type does not support {}",
+                operation
+            ))),
+        }
+    }
+}
+
+// Stream offsets go up to about 12 hours either way, the range of an i32 at 48kHz
+fn stream_offset_in_range(samples: f64) -> bool {
+    (i32::MIN as f64..=i32::MAX as f64).contains(&samples)
+}
+
+// Convert stream tags to a JavaScript object:
+// { vendor, title, artist, trackGainDb, comments: { KEY: [values] } }
+fn tags_to_js_object(tags: &OpusTags) -> Result<js_sys::Object, JsValue> {
+    let object = js_sys::Object::new();
+    js_sys::Reflect::set(&object, &"vendor".into(), &JsValue::from_str(&tags.vendor))?;
+
+    let optional_str = |value: Option<&str>| value.map_or(JsValue::NULL, JsValue::from_str);
+    js_sys::Reflect::set(&object, &"title".into(), &optional_str(tags.get("TITLE")))?;
+    js_sys::Reflect::set(&object, &"artist".into(), &optional_str(tags.get("ARTIST")))?;
+    js_sys::Reflect::set(
+        &object,
+        &"trackGainDb".into(),
+        &tags
+            .track_gain_db()
+            .map_or(JsValue::NULL, |gain| JsValue::from_f64(gain as f64)),
+    )?;
+
+    let comments = js_sys::Object::new();
+    for (key, value) in &tags.comments {
+        let key = JsValue::from_str(key);
+        let values = js_sys::Reflect::get(&comments, &key)?;
+        let values = if values.is_undefined() {
+            let values = js_sys::Array::new();
+            js_sys::Reflect::set(&comments, &key, &values)?;
+            values
+        } else {
+            js_sys::Array::from(&values)
+        };
+        values.push(&JsValue::from_str(value));
+    }
+    js_sys::Reflect::set(&object, &"comments".into(), &comments)?;
+
+    Ok(object)
+}
+
+// Convert synchronization statistics to a JavaScript object, with drift in milliseconds and
+// speed adjustments as fractions of 1x
+fn sync_stats_to_js_object(stats: &SyncStats) -> Result<js_sys::Object, JsValue> {
+    let set_f64 = |object: &js_sys::Object, key: &str, value: f64| {
+        js_sys::Reflect::set(object, &key.into(), &JsValue::from_f64(value)).map(|_| ())
+    };
+
+    let object = js_sys::Object::new();
+    set_f64(&object, "maxDriftMs", stats.max_drift_seconds * 1000.0)?;
+
+    let streams = js_sys::Array::new();
+    for stream in &stats.streams {
+        let entry = js_sys::Object::new();
+        set_f64(&entry, "streamId", stream.stream_id as f64)?;
+        set_f64(&entry, "maxDriftMs", stream.max_drift_seconds * 1000.0)?;
+        set_f64(
+            &entry,
+            "averageDriftMs",
+            stream.average_drift_seconds * 1000.0,
+        )?;
+        set_f64(
+            &entry,
+            "driftMeasurements",
+            stream.drift_measurements as f64,
+        )?;
+        set_f64(
+            &entry,
+            "maxSpeedAdjustment",
+            stream.max_compensation as f64 - 1.0,
+        )?;
+        set_f64(
+            &entry,
+            "averageSpeedAdjustment",
+            stream.average_compensation as f64 - 1.0,
+        )?;
+        set_f64(
+            &entry,
+            "compensationCount",
+            stream.compensation_count as f64,
+        )?;
+        set_f64(&entry, "appliedCorrection", stream.applied_correction)?;
+        set_f64(&entry, "playbackRate", stream.playback_rate as f64)?;
+        set_f64(&entry, "decodedFrames", stream.decoded_frames as f64)?;
+        set_f64(&entry, "resampledFrames", stream.resampled_frames as f64)?;
+
+        let history = js_sys::Array::new();
+        for measurement in &stream.history {
+            let point = js_sys::Object::new();
+            set_f64(&point, "time", measurement.time)?;
+            set_f64(&point, "driftMs", measurement.drift_seconds * 1000.0)?;
+            set_f64(&point, "playbackRate", measurement.playback_rate as f64)?;
+            history.push(&point);
+        }
+        js_sys::Reflect::set(&entry, &"history".into(), &history)?;
+
+        streams.push(&entry);
+    }
+    js_sys::Reflect::set(&object, &"streams".into(), &streams)?;
+
+    Ok(object)
+}
+
+// Convert a ring buffer metrics snapshot to a JavaScript object
+fn buffer_metrics_to_js_object(metrics: &RingBufferMetrics) -> Result<js_sys::Object, JsValue> {
+    let object = js_sys::Object::new();
+    let fields = [
+        ("bufferSize", metrics.buffer_size as f64),
+        ("queuedSamples", metrics.queued_samples as f64),
+        ("fillMs", metrics.fill_ms),
+        ("capacityMs", metrics.capacity_ms),
+        ("highWaterMarkRead", metrics.high_water_mark_read as f64),
+        ("highWaterMarkWrite", metrics.high_water_mark_write as f64),
+        ("writes", metrics.writes as f64),
+        ("reads", metrics.reads as f64),
+        ("underruns", metrics.underruns as f64),
+        ("overruns", metrics.overruns as f64),
+        ("samplesWritten", metrics.samples_written as f64),
+        ("samplesRead", metrics.samples_read as f64),
+    ];
+    for (key, value) in fields.iter() {
+        js_sys::Reflect::set(&object, &(*key).into(), &JsValue::from_f64(*value))?;
+    }
+
+    Ok(object)
+}
@@ -44,3 +44,10 @@ pub async fn read_file_to_array_buffer(file: File) -> Result<js_sys::ArrayBuffer
     let result = wasm_bindgen_futures::JsFuture::from(promise).await?;
     Ok(result.into())
 }
+
+pub fn bytes_to_blob(bytes: &[u8], mime_type: &str) -> Result<web_sys::Blob, JsValue> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let mut options = web_sys::BlobPropertyBag::new();
+    options.type_(mime_type);
+    web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+}
@@ -6,6 +6,38 @@ let sharedBuffer;
 let isInitializing = false;
 let pendingOperations = [];
 let sourceType = 'oscillator'; // Default source type
+let lastPositionReport = 0;
+let lastSyncStatsReport = 0;
+let lastBufferMetricsReport = 0;
+let processIntervalMs; // How often the ring buffer is topped up, set from the target fill level
+let loadGeneration = 0; // Incremented by each load, so an earlier one still reading stops
+let wasmModule; // The worker's own instance of the wasm module, with its own log settings
+let pendingLogs = []; // Log messages waiting to be sent to the main thread
+
+// How often to top up the ring buffer. Low target fill levels need it done more often, so the
+// buffer is refilled before it can run dry.
+const MAX_PROCESS_INTERVAL_MS = 10;
+const MIN_PROCESS_INTERVAL_MS = 2;
+
+// How often to send playback position updates to the main thread
+const POSITION_REPORT_INTERVAL_MS = 100;
+
+// How often to send drift statistics to the main thread, for the diagnostics panel
+const SYNC_STATS_INTERVAL_MS = 1000;
+
+// How often to send ring buffer metrics to the main thread, to show buffer health
+const BUFFER_METRICS_INTERVAL_MS = 1000;
+
+// How often to send batches of log messages to the main thread
+const LOG_FLUSH_INTERVAL_MS = 250;
+
+// Whether a file can be given to the mixer. WebM files, MediaRecorder recordings included, are
+// typed as video even when they only hold audio.
+function isAudioFile(file) {
+  return file.type.startsWith('audio/') ||
+    file.type === 'video/webm' ||
+    /\.(webm|mka)$/i.test(file.name);
+}
 
 // TODO: Move most of this to rust
 
@@ -40,7 +72,28 @@ self.onmessage = async (event) => {
         sourceType = data.sourceType;
       }
 
-      await initWorker(data.sampleRate, sourceType);
+      await initWorker(
+        data.sampleRate,
+        sourceType,
+        data.resamplerQuality,
+        data.logLevels,
+        data.bufferSize,
+        data.targetFillMs
+      );
+      break;
+
+    case 'setLogLevel':
+      // Change how much the worker's wasm instance logs
+      if (!wasmModule) {
+        console.log('Ignoring setLogLevel operation - audio engine not initialized');
+        return;
+      }
+
+      try {
+        wasmModule.setLogLevel(data.level, data.category);
+      } catch (error) {
+        console.error('Failed to set log level:', error);
+      }
       break;
 
     case 'loadAudioFile':
@@ -53,14 +106,7 @@ self.onmessage = async (event) => {
         try {
           // Load the file into the audio source
           if (audioSource && sourceType === 'opusPlayer') {
-            await audioSource.loadAudioFile(data.file);
-
-            // Send success message back to main thread
-            self.postMessage({
-              type: 'audioFileReceived',
-              success: true,
-              fileName: data.file.name
-            });
+            await loadStreams([data.file.stream()], data.file.name);
           } else {
             throw new Error('Audio source not initialized or not an opus player');
           }
@@ -97,24 +143,20 @@ self.onmessage = async (event) => {
 
           // Check if all files are valid
           for (const file of filesArray) {
-            if (!file.type.startsWith('audio/')) {
+            if (!isAudioFile(file)) {
               throw new Error(`File "${file.name}" is not an audio file`);
             }
           }
 
           // Load the files into the audio source
           if (audioSource && sourceType === 'opusPlayer') {
-            await audioSource.loadAudioFiles(filesArray);
-
             // Get file names for the success message
             const fileNames = filesArray.map(file => file.name).join(', ');
 
-            // Send success message back to main thread
-            self.postMessage({
-              type: 'audioFileReceived',
-              success: true,
-              fileName: `${fileCount} files: ${fileNames}`
-            });
+            await loadStreams(
+              filesArray.map(file => file.stream()),
+              `${fileCount} files: ${fileNames}`
+            );
           } else {
             throw new Error('Audio source not initialized or not an opus player');
           }
@@ -138,6 +180,44 @@ self.onmessage = async (event) => {
       }
       break;
 
+    case 'loadAudioUrls':
+      // Stream audio files from URLs, starting playback before the downloads finish
+      if (data && data.urls && data.urls.length > 0) {
+        const urls = Array.from(data.urls);
+        console.log(`Received ${urls.length} audio URLs in worker`);
+
+        try {
+          if (audioSource && sourceType === 'opusPlayer') {
+            const responses = await Promise.all(urls.map(async url => {
+              const response = await fetch(url);
+              if (!response.ok || !response.body) {
+                throw new Error(`Failed to fetch "${url}": ${response.status} ${response.statusText}`);
+              }
+              return response;
+            }));
+
+            await loadStreams(responses.map(response => response.body), urls.join(', '));
+          } else {
+            throw new Error('Audio source not initialized or not an opus player');
+          }
+        } catch (error) {
+          console.error('Error loading audio URLs:', error);
+          self.postMessage({
+            type: 'audioFileReceived',
+            success: false,
+            error: error.message || 'Failed to load audio URLs'
+          });
+        }
+      } else {
+        console.error('Invalid audio URLs data received');
+        self.postMessage({
+          type: 'audioFileReceived',
+          success: false,
+          error: 'Invalid audio URLs data'
+        });
+      }
+      break;
+
     case 'start':
       // Queue the operation if not initialized
       if (!isInitialized) {
@@ -183,28 +263,132 @@ self.onmessage = async (event) => {
       resetAudioSource();
       break;
 
+    case 'seek':
+      // Seek the audio source to a new position (for opus player)
+      if (!isInitialized) {
+        console.log('Ignoring seek operation - audio engine not initialized');
+        return;
+      }
+
+      seekAudioSource(data.seconds);
+      break;
+
+    case 'setLoop':
+    case 'clearLoop':
+      // Set or clear the loop region (for opus player)
+      if (!isInitialized) {
+        console.log(`Ignoring ${type} operation - audio engine not initialized`);
+        return;
+      }
+
+      setLoop(type === 'setLoop' ? data : null);
+      break;
+
+    case 'setStreamGain':
+    case 'setStreamPan':
+    case 'setStreamMute':
+    case 'setStreamSolo':
+    case 'setStreamOffset':
+    case 'setStreamOffsetSamples':
+    case 'setStreamRawChannels':
+    case 'setStreamDownmix':
+      // Adjust a per-stream mix control (for opus player)
+      if (!isInitialized) {
+        console.log(`Ignoring ${type} operation - audio engine not initialized`);
+        return;
+      }
+
+      setStreamControl(type, data);
+      break;
+
+    case 'addAudioFile':
+      // Add a file to the running mix (for opus player)
+      if (!isInitialized) {
+        console.log('Ignoring addAudioFile operation - audio engine not initialized');
+        return;
+      }
+
+      await addAudioFile(data.file, data.offset);
+      break;
+
+    case 'removeStream':
+      // Remove a stream from the running mix (for opus player)
+      if (!isInitialized) {
+        console.log('Ignoring removeStream operation - audio engine not initialized');
+        return;
+      }
+
+      removeStream(data.streamId);
+      break;
+
+    case 'setResamplerQuality':
+      // Change the sample rate conversion quality (for opus player)
+      if (!isInitialized) {
+        console.log('Ignoring setResamplerQuality operation - audio engine not initialized');
+        return;
+      }
+
+      setResamplerQuality(data.quality);
+      break;
+
+    case 'setTargetFill':
+      // Change how much audio is kept queued in the ring buffer
+      if (!isInitialized) {
+        console.log('Ignoring setTargetFill operation - audio engine not initialized');
+        return;
+      }
+
+      setTargetFill(data.ms);
+      break;
+
     default:
       console.error('Unknown message type:', type);
   }
 };
 
 // Initialize the worker with the WASM module
-async function initWorker(sampleRate, sourceType = 'oscillator') {
+async function initWorker(
+  sampleRate,
+  sourceType = 'oscillator',
+  resamplerQuality,
+  logLevels = [],
+  bufferSize,
+  targetFillMs
+) {
   try {
     // Import the WASM module
     // TODO: This re-downloads the wasm module. Explore passing the bytes from the main thread instead.
     const wasmImport = await import('/wasm/wasm_pack_test_27_feb.js');
     await wasmImport.default();
+    wasmModule = wasmImport;
+
+    // This instance has its own log settings, so apply the ones made on the main thread, and
+    // send its messages there in batches
+    for (const { level, category } of logLevels) {
+      wasmImport.setLogLevel(level, category);
+    }
+    wasmImport.setLogCallback(record => pendingLogs.push(record));
+    setInterval(flushLogs, LOG_FLUSH_INTERVAL_MS);
 
     // Create the appropriate audio source based on the source type
+    bufferSize = bufferSize || wasmImport.get_buffer_size();
     if (sourceType === 'oscillator') {
-      audioSource = wasmImport.AudioSource.createOscillator(sampleRate);
+      audioSource = wasmImport.AudioSource.createOscillator(sampleRate, bufferSize);
     } else if (sourceType === 'opusPlayer') {
-      audioSource = wasmImport.AudioSource.createOpusPlayer(sampleRate);
+      audioSource = wasmImport.AudioSource.createOpusPlayer(sampleRate, bufferSize);
+      if (resamplerQuality) {
+        audioSource.setResamplerQuality(resamplerQuality);
+      }
     } else {
       throw new Error(`Unknown source type: ${sourceType}`);
     }
 
+    // Keep the whole buffer full unless a lower target fill level was chosen
+    if (targetFillMs) {
+      audioSource.setTargetFill(targetFillMs);
+    }
+    updateProcessInterval();
+
     // Get the shared buffer to reuse later
     sharedBuffer = audioSource.get_shared_buffer();
 
@@ -273,16 +457,7 @@ function startAudioEngine() {
     audioSource.start();
 
     // Set up an interval to process audio samples
-    if (processorIntervalId) {
-      clearInterval(processorIntervalId);
-    }
-
-    processorIntervalId = setInterval(() => {
-      if (audioSource) {
-        // Process FRAME_SIZE * 8 samples at a time
-        audioSource.process(960 * 8);
-      }
-    }, 10); // Process every 2ms
+    scheduleProcessing();
 
     // Send success message with the shared buffer
     self.postMessage({
@@ -302,6 +477,59 @@ function startAudioEngine() {
   }
 }
 
+// Top up the ring buffer to the target fill level every processIntervalMs, replacing any
+// schedule already running
+function scheduleProcessing() {
+  if (processorIntervalId) {
+    clearInterval(processorIntervalId);
+  }
+
+  processorIntervalId = setInterval(() => {
+    if (audioSource) {
+      audioSource.fill();
+      reportPosition();
+      reportSyncStats();
+      reportBufferMetrics();
+    }
+  }, processIntervalMs);
+}
+
+// Process often enough to refill the buffer at least twice before it could drain
+function updateProcessInterval() {
+  processIntervalMs = Math.min(
+    MAX_PROCESS_INTERVAL_MS,
+    Math.max(MIN_PROCESS_INTERVAL_MS, audioSource.getTargetFill() / 2)
+  );
+}
+
+// Change how much audio is kept queued in the ring buffer, and report the level actually used
+function setTargetFill(ms) {
+  try {
+    if (!isInitialized || !audioSource) {
+      throw new Error('Audio engine not initialized');
+    }
+
+    const targetFill = audioSource.setTargetFill(ms);
+    updateProcessInterval();
+    if (processorIntervalId) {
+      scheduleProcessing();
+    }
+
+    self.postMessage({
+      type: 'targetFillSet',
+      success: true,
+      targetFill
+    });
+  } catch (error) {
+    console.error('Failed to set target fill:', error);
+    self.postMessage({
+      type: 'targetFillSet',
+      success: false,
+      error: error.message || error
+    });
+  }
+}
+
 // Stop the audio engine
 function stopAudioEngine() {
   try {
@@ -387,3 +615,352 @@ function resetAudioSource() {
     });
   }
 }
+
+// Feed each ReadableStream of file bytes to the audio source as its chunks arrive. The main
+// thread hears 'audioFileReceived' as soon as every file's headers are in and playback can
+// start, then 'audioLoaded' once the files have arrived in full.
+async function loadStreams(streams, fileName) {
+  const generation = ++loadGeneration;
+  audioSource.beginLoading(streams.length);
+
+  let playable = false;
+  const reportPlayable = (ready) => {
+    if (ready && !playable) {
+      playable = true;
+      self.postMessage({
+        type: 'audioFileReceived',
+        success: true,
+        fileName,
+        duration: audioSource.getDuration(),
+        tags: getStreamTags(),
+        loading: audioSource.isLoading()
+      });
+    }
+  };
+
+  await Promise.all(streams.map(async (stream, index) => {
+    const reader = stream.getReader();
+    for (;;) {
+      const { done, value } = await reader.read();
+
+      // Another load has replaced this one
+      if (generation !== loadGeneration) {
+        await reader.cancel();
+        return;
+      }
+
+      if (done) {
+        reportPlayable(audioSource.finishAudioChunks(index));
+        return;
+      }
+      reportPlayable(audioSource.pushAudioChunk(index, value));
+    }
+  }));
+
+  if (generation === loadGeneration) {
+    self.postMessage({
+      type: 'audioLoaded',
+      success: true,
+      fileName,
+      duration: audioSource.getDuration()
+    });
+  }
+}
+
+// Send the log messages collected since the last flush to the main thread
+function flushLogs() {
+  if (pendingLogs.length === 0) {
+    return;
+  }
+
+  self.postMessage({
+    type: 'log',
+    success: true,
+    records: pendingLogs
+  });
+  pendingLogs = [];
+}
+
+// Collect the OpusTags metadata of every loaded stream, in stream order (null for streams
+// without tags)
+function getStreamTags() {
+  return Array.from(audioSource.getStreamIds(), streamId => audioSource.getStreamTags(streamId));
+}
+
+// Add a file to the mix (for opus player) without interrupting playback, starting `offset`
+// seconds into the timeline, and report the new stream's id
+async function addAudioFile(file, offset) {
+  try {
+    if (!audioSource || sourceType !== 'opusPlayer') {
+      throw new Error('Audio source not initialized or not an opus player');
+    }
+
+    const data = new Uint8Array(await file.arrayBuffer());
+    const streamId = audioSource.addAudioStream(data, offset || 0);
+
+    self.postMessage({
+      type: 'streamAdded',
+      success: true,
+      fileName: file.name,
+      streamId,
+      offset: offset || 0,
+      streamTags: audioSource.getStreamTags(streamId),
+      duration: audioSource.getDuration()
+    });
+  } catch (error) {
+    console.error('Failed to add audio file:', error);
+    self.postMessage({
+      type: 'streamAdded',
+      success: false,
+      fileName: file && file.name,
+      error: error.message || error
+    });
+  }
+}
+
+// Remove a stream from the mix by id (for opus player)
+function removeStream(streamId) {
+  try {
+    if (!audioSource || sourceType !== 'opusPlayer') {
+      throw new Error('Audio source not initialized or not an opus player');
+    }
+
+    audioSource.removeStream(streamId);
+
+    self.postMessage({
+      type: 'streamRemoved',
+      success: true,
+      streamId,
+      duration: audioSource.getDuration()
+    });
+  } catch (error) {
+    console.error('Failed to remove stream:', error);
+    self.postMessage({
+      type: 'streamRemoved',
+      success: false,
+      streamId,
+      error: error.message || error
+    });
+  }
+}
+
+// Periodically send the playback position to the main thread (for opus player)
+function reportPosition() {
+  if (sourceType !== 'opusPlayer') {
+    return;
+  }
+
+  const now = performance.now();
+  if (now - lastPositionReport < POSITION_REPORT_INTERVAL_MS) {
+    return;
+  }
+  lastPositionReport = now;
+
+  try {
+    self.postMessage({
+      type: 'position',
+      success: true,
+      position: audioSource.getPosition(),
+      duration: audioSource.getDuration(),
+      loading: audioSource.isLoading(),
+      buffering: audioSource.isBuffering()
+    });
+  } catch (error) {
+    console.error('Failed to read playback position:', error);
+  }
+}
+
+// Periodically send the mixer's drift and decoding statistics to the main thread (for opus
+// player)
+function reportSyncStats() {
+  if (sourceType !== 'opusPlayer') {
+    return;
+  }
+
+  const now = performance.now();
+  if (now - lastSyncStatsReport < SYNC_STATS_INTERVAL_MS) {
+    return;
+  }
+  lastSyncStatsReport = now;
+
+  try {
+    const stats = audioSource.getSyncStats();
+    if (stats) {
+      self.postMessage({
+        type: 'syncStats',
+        success: true,
+        stats
+      });
+    }
+  } catch (error) {
+    console.error('Failed to read sync statistics:', error);
+  }
+}
+
+// Periodically send a snapshot of the ring buffer's fill level, underruns and overruns to the
+// main thread
+function reportBufferMetrics() {
+  const now = performance.now();
+  if (now - lastBufferMetricsReport < BUFFER_METRICS_INTERVAL_MS) {
+    return;
+  }
+  lastBufferMetricsReport = now;
+
+  try {
+    self.postMessage({
+      type: 'bufferMetrics',
+      success: true,
+      metrics: audioSource.getBufferMetrics()
+    });
+  } catch (error) {
+    console.error('Failed to read buffer metrics:', error);
+  }
+}
+
+// Seek the audio source (for opus player) and report where playback landed
+function seekAudioSource(seconds) {
+  try {
+    if (!isInitialized || !audioSource) {
+      throw new Error('Audio engine not initialized');
+    }
+
+    if (sourceType !== 'opusPlayer') {
+      throw new Error('Cannot seek non-opus player source');
+    }
+
+    const position = audioSource.seek(seconds);
+
+    self.postMessage({
+      type: 'seeked',
+      success: true,
+      position,
+      duration: audioSource.getDuration()
+    });
+  } catch (error) {
+    console.error('Failed to seek audio source:', error);
+    self.postMessage({
+      type: 'seeked',
+      success: false,
+      error: error.message || error
+    });
+  }
+}
+
+// Set the loop region (for opus player), or clear it if `region` is null
+function setLoop(region) {
+  try {
+    if (!isInitialized || !audioSource) {
+      throw new Error('Audio engine not initialized');
+    }
+
+    if (sourceType !== 'opusPlayer') {
+      throw new Error('Cannot loop non-opus player source');
+    }
+
+    let loopStart = null;
+    let loopEnd = null;
+    if (region) {
+      [loopStart, loopEnd] = audioSource.setLoop(region.start, region.end);
+    } else {
+      audioSource.clearLoop();
+    }
+
+    self.postMessage({
+      type: 'loopSet',
+      success: true,
+      loopStart,
+      loopEnd
+    });
+  } catch (error) {
+    console.error('Failed to set loop region:', error);
+    self.postMessage({
+      type: 'loopSet',
+      success: false,
+      error: error.message || error
+    });
+  }
+}
+
+// Set a per-stream mix control (gain, pan, mute, solo, timeline offset or channel routing) on
+// the opus player
+function setStreamControl(type, data) {
+  try {
+    if (!isInitialized || !audioSource) {
+      throw new Error('Audio engine not initialized');
+    }
+
+    if (sourceType !== 'opusPlayer') {
+      throw new Error('Cannot set stream controls on non-opus player source');
+    }
+
+    switch (type) {
+      case 'setStreamGain':
+        audioSource.setStreamGain(data.streamId, data.gainDb);
+        break;
+      case 'setStreamPan':
+        audioSource.setStreamPan(data.streamId, data.pan);
+        break;
+      case 'setStreamMute':
+        audioSource.setStreamMute(data.streamId, data.muted);
+        break;
+      case 'setStreamSolo':
+        audioSource.setStreamSolo(data.streamId, data.solo);
+        break;
+      case 'setStreamOffset':
+        audioSource.setStreamOffset(data.streamId, data.offset);
+        break;
+      case 'setStreamOffsetSamples':
+        audioSource.setStreamOffsetSamples(data.streamId, data.samples);
+        break;
+      case 'setStreamRawChannels':
+        audioSource.setStreamRawChannels(data.streamId, data.left, data.right);
+        break;
+      case 'setStreamDownmix':
+        audioSource.setStreamDownmix(data.streamId);
+        break;
+    }
+
+    self.postMessage({
+      type: 'streamControlSet',
+      success: true,
+      control: type,
+      streamId: data.streamId
+    });
+  } catch (error) {
+    console.error(`Failed to apply ${type}:`, error);
+    self.postMessage({
+      type: 'streamControlSet',
+      success: false,
+      control: type,
+      error: error.message || error
+    });
+  }
+}
+
+// Set the quality of the conversion from 48kHz to the AudioContext sample rate (for opus player)
+function setResamplerQuality(quality) {
+  try {
+    if (!isInitialized || !audioSource) {
+      throw new Error('Audio engine not initialized');
+    }
+
+    if (sourceType !== 'opusPlayer') {
+      throw new Error('Cannot set resampler quality on non-opus player source');
+    }
+
+    audioSource.setResamplerQuality(quality);
+
+    self.postMessage({
+      type: 'resamplerQualitySet',
+      success: true,
+      quality
+    });
+  } catch (error) {
+    console.error('Failed to set resampler quality:', error);
+    self.postMessage({
+      type: 'resamplerQualitySet',
+      success: false,
+      error: error.message || error
+    });
+  }
+}
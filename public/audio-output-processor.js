@@ -11,7 +11,9 @@ class AudioOutputProcessor extends AudioWorkletProcessor {
     this.readPtr = 0;
     this.writePtr = 0;
     this.bufferSize = 0;
-    this.metadataSize = 2; // [readPtr, writePtr]
+    this.metadataSize = 5; // [readPtr, writePtr, flushPtr, flushSeq, underrunCount]
+    this.flushSeq = 0;
+    this.wasFull = false; // Whether the last render quantum had all the samples it needed
     this.channelCount = 2; // Always use 2 channels (stereo)
 
     // Check if we have options with a shared buffer
@@ -43,6 +45,17 @@ class AudioOutputProcessor extends AudioWorkletProcessor {
     const output = outputs[0];
     const outputChannels = output.length;
 
+    // If the writer has requested a flush (e.g. after a seek), skip everything up to the
+    // position it published so stale audio isn't played
+    const flushSeq = this.bufferView[3];
+    if (flushSeq !== this.flushSeq) {
+      this.flushSeq = flushSeq;
+      this.readPtr = this.bufferView[2];
+
+      // The buffer is empty until the writer refills it, which isn't an underrun
+      this.wasFull = false;
+    }
+
     // Read the current write pointer from the shared buffer
     this.writePtr = this.bufferView[1];
 
@@ -63,6 +76,7 @@ class AudioOutputProcessor extends AudioWorkletProcessor {
     const outputLength = output[0].length;
 
     // Process each output sample
+    let missingFrames = 0;
     for (let i = 0; i < outputLength; i++) {
       if (availableFrames > 0) {
         // For stereo, we need to read two consecutive samples for left and right channels
@@ -81,6 +95,7 @@ class AudioOutputProcessor extends AudioWorkletProcessor {
         for (let channel = 0; channel < Math.min(outputChannels, this.channelCount); channel++) {
           output[channel][i] = this.lastSamples[channel];
         }
+        missingFrames++;
 
         // Log buffer underruns (but not too frequently to avoid console spam)
         if (Math.random() < 0.01) {
@@ -89,6 +104,13 @@ class AudioOutputProcessor extends AudioWorkletProcessor {
       }
     }
 
+    // Count an underrun when a quantum comes up short after the buffer had kept up, for the
+    // worker's metrics. The writer discounts the times it stopped writing on purpose.
+    if (missingFrames > 0 && this.wasFull) {
+      this.bufferView[4] += 1;
+    }
+    this.wasFull = missingFrames === 0;
+
     // Update the read pointer in the shared buffer
     this.bufferView[0] = this.readPtr;
 